@@ -0,0 +1,74 @@
+/**
+ * Writes bencode values in order, as described in parser.rs. Dictionary keys must be written in sorted order by the
+ * caller, as the parser rejects dictionaries with unsorted keys.
+ */
+#[derive(Default)]
+pub struct Encoder {
+    bytes: Vec<u8>
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    pub fn integer(&mut self, value: i128) -> &mut Encoder {
+        self.bytes.push(b'i');
+        self.bytes.extend_from_slice(value.to_string().as_bytes());
+        self.bytes.push(b'e');
+        self
+    }
+
+    pub fn string(&mut self, value: &[u8]) -> &mut Encoder {
+        self.bytes.extend_from_slice(value.len().to_string().as_bytes());
+        self.bytes.push(b':');
+        self.bytes.extend_from_slice(value);
+        self
+    }
+
+    pub fn begin_list(&mut self) -> &mut Encoder {
+        self.bytes.push(b'l');
+        self
+    }
+
+    pub fn begin_dictionary(&mut self) -> &mut Encoder {
+        self.bytes.push(b'd');
+        self
+    }
+
+    // Ends the innermost list or dictionary.
+    pub fn end(&mut self) -> &mut Encoder {
+        self.bytes.push(b'e');
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bencode::{BencodeToken, Parser};
+
+    use super::*;
+
+    #[test]
+    fn encoder_should_round_trip_through_parser() {
+        let mut encoder = Encoder::new();
+        encoder.begin_dictionary()
+            .string(b"list").begin_list().integer(-3).string(b"").end()
+            .string(b"name").string(b"spam")
+            .end();
+
+        let bytes = encoder.into_bytes();
+        assert_eq!(b"d4:listli-3e0:e4:name4:spame".as_slice(), bytes.as_slice());
+
+        let BencodeToken::Dictionary(dictionary) = Parser::decode(&bytes).unwrap() else {
+            panic!("Expected dictionary token.");
+        };
+
+        assert_eq!(b"spam", dictionary.find_string_value(b"name").unwrap().value.as_slice());
+        assert_eq!(2, dictionary.find_list_value(b"list").unwrap().value.len());
+    }
+}
\ No newline at end of file
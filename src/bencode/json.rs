@@ -0,0 +1,117 @@
+use std::fmt::Write as FmtWrite;
+
+use crate::hex::to_hex;
+
+use super::BencodeToken;
+
+// Renders a bencode tree as pretty-printed JSON. Strings that are valid UTF-8 are written as JSON strings, any other
+// string, such as piece hashes, is written as {"hex": "..."}. Dictionary keys are always written as JSON strings,
+// non UTF-8 keys are converted lossily.
+pub fn to_json(token: &BencodeToken) -> String {
+    let mut output = String::new();
+    write_token(&mut output, token, 0);
+    output
+}
+
+fn write_token(output: &mut String, token: &BencodeToken, depth: usize) {
+    match token {
+        BencodeToken::Integer(integer) => {
+            write!(output, "{}", integer.value).expect("Unable to write");
+        },
+        BencodeToken::String(string) => match std::str::from_utf8(&string.value) {
+            Ok(value) => write_string(output, value),
+            Err(_) => {
+                output.push_str("{\"hex\": ");
+                write_string(output, &to_hex(&string.value));
+                output.push('}');
+            }
+        },
+        BencodeToken::List(list) => {
+            if list.value.is_empty() {
+                output.push_str("[]");
+                return;
+            }
+
+            output.push('[');
+            for (index, value) in list.value.iter().enumerate() {
+                if index > 0 {
+                    output.push(',');
+                }
+
+                write_indent(output, depth + 1);
+                write_token(output, value, depth + 1);
+            }
+
+            write_indent(output, depth);
+            output.push(']');
+        },
+        BencodeToken::Dictionary(dictionary) => {
+            if dictionary.keys.is_empty() {
+                output.push_str("{}");
+                return;
+            }
+
+            output.push('{');
+            for (index, (key, value)) in dictionary.keys.iter().zip(&dictionary.values).enumerate() {
+                if index > 0 {
+                    output.push(',');
+                }
+
+                write_indent(output, depth + 1);
+                write_string(output, &String::from_utf8_lossy(&key.value));
+                output.push_str(": ");
+                write_token(output, value, depth + 1);
+            }
+
+            write_indent(output, depth);
+            output.push('}');
+        }
+    }
+}
+
+fn write_indent(output: &mut String, depth: usize) {
+    output.push('\n');
+    for _ in 0..depth {
+        output.push_str("  ");
+    }
+}
+
+fn write_string(output: &mut String, value: &str) {
+    output.push('"');
+    for character in value.chars() {
+        match character {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            character if (character as u32) < 0x20 => {
+                write!(output, "\\u{:04x}", character as u32).expect("Unable to write");
+            },
+            character => output.push(character)
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bencode::Parser;
+
+    use super::*;
+
+    #[test]
+    fn to_json_should_render_nested_tokens() {
+        let token = Parser::decode(b"d5:emptyle4:listli1ei-2ee4:name5:a\"b\nce").unwrap();
+
+        let expected = "{\n  \"empty\": [],\n  \"list\": [\n    1,\n    -2\n  ],\n  \"name\": \"a\\\"b\\nc\"\n}";
+        assert_eq!(expected, to_json(&token));
+    }
+
+    #[test]
+    fn to_json_should_render_binary_strings_as_hex() {
+        let token = Parser::decode(b"l2:\xff\x00e").unwrap();
+
+        assert_eq!("[\n  {\"hex\": \"ff00\"}\n]", to_json(&token));
+    }
+}
\ No newline at end of file
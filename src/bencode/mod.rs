@@ -1,7 +1,11 @@
 mod parser;
 mod error;
 mod types;
+mod json;
+mod encoder;
 
 pub use parser::*;
 pub use types::*;
-pub use error::*;
\ No newline at end of file
+pub use error::*;
+pub use json::*;
+pub use encoder::*;
\ No newline at end of file
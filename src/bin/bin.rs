@@ -1,15 +1,78 @@
-use std::{fs::{self}, path::{Path, PathBuf}, time::Instant};
+use std::{fs::{self}, net::SocketAddr, path::{Path, PathBuf}, process::ExitCode, time::{Duration, Instant}};
 
-use clap::Parser;
-use torrent_bootstrap::{orchestrator::OrchestratorOptions, torrent::Torrent};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use torrent_bootstrap::{bencode::{to_json, Parser as BencodeParser}, filesystem::{ScanFilter, ScanPattern}, explain::{Explanation, ExplainTarget, FileExplanation, PieceExplanation}, inspect::TorrentInspection, orchestrator::{OrchestratorOptions, PieceOrder}, plan::TorrentPlan, solver::cancellation::CancellationToken, torrent::{info::get_sha1_hexdigest, Torrent}, verify::{DataLayout, TorrentVerification}, watch::WatchOptions};
 
 #[derive(Parser)] // requires `derive` feature
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, arg_required_else_help = true, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// Path that should be used to load a torrent.
-    #[arg(long, required = true, num_args = 1..)]
-    torrents: Vec<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    bootstrap: Option<BootstrapArgs>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Discover candidates and report the search space for each torrent and piece, without reading or writing any file data.
+    Plan(PlanArgs),
+
+    /// Explain which candidates were found and tried for a single piece or file of a torrent.
+    Explain(ExplainArgs),
+
+    /// Recheck torrents against their data on disk, like a torrent client, without searching or writing anything. Exits with 1 when any torrent is incomplete.
+    Verify(VerifyArgs),
+
+    /// Build or refresh a scan index ahead of time, so later runs with the same index file only read the directories that changed.
+    Index(IndexArgs),
+
+    /// Print the info hashes, piece layout and file list of a torrent.
+    Info(InspectArgs),
+
+    /// Print the raw bencode tree of a torrent as JSON.
+    Dump(InspectArgs),
+
+    /// Watch an inbox directory and bootstrap every .torrent file that arrives, then move it to the processed or failed directory with its report.
+    Watch(WatchArgs),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PieceOrderArg {
+    /// Solve pieces spanning the fewest files first.
+    FileCount,
+    /// Solve pieces in the order their data is stored on disk, so that candidate files are read sequentially.
+    Locality,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DataLayoutArg {
+    /// Data is stored at <DATA>/<torrent name>, as saved by a torrent client.
+    Client,
+    /// Data is stored at <DATA>/<info hash>/Data/<torrent name>, as exported by this tool.
+    InfoHash,
+}
 
+impl From<DataLayoutArg> for DataLayout {
+    fn from(value: DataLayoutArg) -> Self {
+        match value {
+            DataLayoutArg::Client => DataLayout::Client,
+            DataLayoutArg::InfoHash => DataLayout::InfoHash
+        }
+    }
+}
+
+impl From<PieceOrderArg> for PieceOrder {
+    fn from(value: PieceOrderArg) -> Self {
+        match value {
+            PieceOrderArg::FileCount => PieceOrder::FileCount,
+            PieceOrderArg::Locality => PieceOrder::Locality
+        }
+    }
+}
+
+#[derive(Args)]
+struct DiscoveryArgs {
     /// Paths that should be scanned for matching files.
     #[arg(long, required = true, num_args = 1..)]
     scan: Vec<PathBuf>,
@@ -22,45 +85,671 @@ struct Cli {
     #[arg(long, required = false, default_value_t = false)]
     resize_export_files: bool,
 
+    #[command(flatten)]
+    filter: ScanFilterArgs,
+
+    /// Path to a scan index. Directories that are unchanged since the index was saved are not read again, and the index is updated after the scan paths are indexed.
+    #[arg(long, required = false)]
+    index_file: Option<PathBuf>,
+
+    /// Number of threads reading directories in parallel while indexing the scan paths.
+    #[arg(long, required = false, default_value_t = 4)]
+    index_threads: usize,
+}
+
+#[derive(Args)]
+struct ScanFilterArgs {
+    /// Only index scanned files matching a glob. Globs without a / match the file name, others match the path relative to the scan path.
+    #[arg(long, required = false)]
+    include: Vec<String>,
+
+    /// Skip scanned files and directories matching a glob. Globs without a / match the name, others match the path relative to the scan path.
+    #[arg(long, required = false)]
+    exclude: Vec<String>,
+
+    /// Only index scanned files whose path relative to the scan path matches a regular expression.
+    #[arg(long, required = false)]
+    include_regex: Vec<String>,
+
+    /// Skip scanned files and directories whose path relative to the scan path matches a regular expression.
+    #[arg(long, required = false)]
+    exclude_regex: Vec<String>,
+
+    /// Skip scanned files smaller than this number of bytes.
+    #[arg(long, required = false)]
+    min_size: Option<u64>,
+
+    /// Skip scanned files larger than this number of bytes.
+    #[arg(long, required = false)]
+    max_size: Option<u64>,
+
+    /// Skip scanned files and directories whose name starts with a dot.
+    #[arg(long, required = false, default_value_t = false)]
+    skip_hidden: bool,
+
+    /// Maximum depth to scan below each scan path, where files directly in the scan path have a depth of 1.
+    #[arg(long, required = false)]
+    max_depth: Option<usize>,
+
+    /// Do not descend into directories on a different filesystem than the scan path.
+    #[arg(long, required = false, default_value_t = false)]
+    one_file_system: bool,
+
+    /// Descend into symlinks to directories, skipping symlinks to a directory that was already walked, such as a parent directory.
+    #[arg(long, required = false, default_value_t = false)]
+    follow_symlinks: bool,
+
+    /// Index the files within tar archives and the stored entries of zip archives as candidates.
+    #[arg(long, required = false, default_value_t = false)]
+    scan_archives: bool,
+}
+
+// Clap leaves the group of an Args struct with a nested flatten empty, so the torrents argument is listed
+// explicitly for the optional top-level bootstrap arguments to be detected.
+#[derive(Args)]
+#[group(args = ["torrents"])]
+struct BootstrapArgs {
+    /// Torrent file, directory scanned recursively for .torrent files, glob pattern, or @file listing one of these per line.
+    #[arg(long, required = true, num_args = 1..)]
+    torrents: Vec<PathBuf>,
+
+    /// Fail instead of skipping any torrent that cannot be read or parsed.
+    #[arg(long, required = false, default_value_t = false)]
+    strict_torrents: bool,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+
+    #[command(flatten)]
+    solver: SolverArgs,
+}
+
+#[derive(Args)]
+struct SolverArgs {
+    /// Number of read threads for hashing.
+    #[arg(long, required = false, default_value_t = 1)]
+    threads: usize,
+
+    /// Path to a checkpoint file. Pieces recorded in the checkpoint are skipped, and finished pieces are recorded as they complete so an interrupted run can be resumed.
+    #[arg(long, required = false)]
+    checkpoint: Option<PathBuf>,
+
+    /// Maximum number of combinations tried for a single piece before it is reported as exhausted.
+    #[arg(long, required = false)]
+    max_combinations_per_piece: Option<u64>,
+
+    /// Maximum number of seconds spent on a single piece before it is reported as exhausted.
+    #[arg(long, required = false)]
+    max_seconds_per_piece: Option<u64>,
+
+    /// Maximum number of candidate files kept open across all read threads, split evenly between the threads. Set to 0 to open a file for every read.
+    #[arg(long, required = false, default_value_t = 256)]
+    max_open_files: usize,
+
+    /// Maximum number of concurrent candidate reads on each rotational drive.
+    #[arg(long, required = false, default_value_t = 1)]
+    rotational_concurrency: usize,
+
+    /// Maximum number of concurrent candidate reads on each non-rotational drive, or on drives that could not be detected. Unlimited when not set.
+    #[arg(long, required = false)]
+    non_rotational_concurrency: Option<usize>,
+
+    /// Maximum number of concurrent candidate reads on the drive holding a path, as PATH=LIMIT. Overrides the detected drive type.
+    #[arg(long, required = false, value_parser = parse_device_concurrency)]
+    device_concurrency: Vec<(PathBuf, usize)>,
+
+    /// Order in which pieces are solved.
+    #[arg(long, required = false, value_enum, default_value_t = PieceOrderArg::FileCount)]
+    piece_order: PieceOrderArg,
+
+    /// Number of bytes the kernel is asked to read ahead of every candidate read, when pieces are solved in locality order.
+    #[arg(long, required = false, default_value_t = 8 * 1024 * 1024)]
+    read_ahead: u64,
+
+    /// Hash pieces within a single file directly from a memory mapping of the candidate, instead of copying the bytes. Files that cannot be mapped are read normally. Requires --max-open-files above 0. Candidates must not be truncated during the run, as reading a truncated mapping terminates the process.
+    #[arg(long, required = false, default_value_t = false)]
+    mmap: bool,
+
+    /// Number of solved pieces that can be queued for writing before the read threads wait for the writer.
+    #[arg(long, required = false, default_value_t = 4)]
+    pipeline_depth: usize,
+
+    /// Recheck every piece against the existing export files first, like a client recheck, and only search for the pieces that fail.
+    #[arg(long, required = false, default_value_t = false)]
+    verify_first: bool,
+
+    /// Fetch the pieces that cannot be found on disk from the web seeds (url-list) of their torrent. HTTP and HTTPS seeds are supported.
+    #[arg(long, required = false, default_value_t = false)]
+    web_seeds: bool,
+
+    /// Seconds to wait when connecting to a web seed and for every read from it.
+    #[arg(long, required = false, default_value_t = 30)]
+    web_seed_timeout: u64,
+
+    /// Fetch the pieces that cannot be found on disk, or on a web seed, from a peer at IP:PORT over the BitTorrent protocol. Can be repeated.
+    #[arg(long = "peer", required = false)]
+    peers: Vec<SocketAddr>,
+
+    /// Seconds to wait when connecting to a peer and for every message from it.
+    #[arg(long, required = false, default_value_t = 30)]
+    peer_timeout: u64,
+}
+
+#[derive(Args)]
+struct PlanArgs {
+    /// Torrent file, directory scanned recursively for .torrent files, glob pattern, or @file listing one of these per line.
+    #[arg(long, required = true, num_args = 1..)]
+    torrents: Vec<PathBuf>,
+
+    /// Fail instead of skipping any torrent that cannot be read or parsed.
+    #[arg(long, required = false, default_value_t = false)]
+    strict_torrents: bool,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+
+    /// Pieces with more combinations than this are flagged as explosive.
+    #[arg(long, required = false, default_value_t = 1_000_000)]
+    explosive_threshold: u64,
+
+    /// Report every piece, instead of only the pieces that are explosive or have no candidates.
+    #[arg(long, required = false, default_value_t = false)]
+    all_pieces: bool,
+}
+
+#[derive(Args)]
+struct ExplainArgs {
+    /// Path of the torrent to explain.
+    #[arg(long, required = true)]
+    torrent: PathBuf,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+
+    /// Position of the piece to explain.
+    #[arg(long, required_unless_present = "file", conflicts_with = "file")]
+    piece: Option<usize>,
+
+    /// Path of the file to explain, relative to the torrent.
+    #[arg(long)]
+    file: Option<PathBuf>,
+
+    /// Maximum number of combinations hashed for each piece.
+    #[arg(long, required = false, default_value_t = 1_000_000)]
+    max_combinations: u64,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// Torrent file, directory scanned recursively for .torrent files, glob pattern, or @file listing one of these per line.
+    #[arg(long, required = true, num_args = 1..)]
+    torrents: Vec<PathBuf>,
+
+    /// Fail instead of skipping any torrent that cannot be read or parsed.
+    #[arg(long, required = false, default_value_t = false)]
+    strict_torrents: bool,
+
+    /// Path where the torrent data is stored.
+    #[arg(long, required = true)]
+    data: PathBuf,
+
+    /// How the torrent data is laid out under the data path.
+    #[arg(long, required = false, value_enum, default_value_t = DataLayoutArg::Client)]
+    layout: DataLayoutArg,
+
     /// Number of read threads for hashing.
     #[arg(long, required = false, default_value_t = 1)]
     threads: usize,
 }
 
-fn run() -> std::io::Result<()> {
-    let args = Cli::parse();
-    let now = Instant::now();
+#[derive(Args)]
+struct IndexArgs {
+    /// Paths that should be scanned for matching files.
+    #[arg(long, required = true, num_args = 1..)]
+    scan: Vec<PathBuf>,
 
-    // Load Torrents
-    let mut torrents: Vec<Torrent> = Vec::new();
-    for torrent_path in &args.torrents {
-        let bytes = fs::read(torrent_path)?;
+    /// Path to the scan index that is created or refreshed.
+    #[arg(long, required = true)]
+    index_file: PathBuf,
+
+    #[command(flatten)]
+    filter: ScanFilterArgs,
+
+    /// Number of threads reading directories in parallel while indexing the scan paths.
+    #[arg(long, required = false, default_value_t = 4)]
+    index_threads: usize,
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    /// Directory watched for new .torrent files. Torrents already in it are bootstrapped first.
+    #[arg(long, required = true)]
+    inbox: PathBuf,
+
+    /// Directory that completely bootstrapped torrents are moved to, together with their report.
+    #[arg(long, required = true)]
+    processed: PathBuf,
+
+    /// Directory that incomplete torrents, and torrents that could not be bootstrapped, are moved to, together with their report.
+    #[arg(long, required = true)]
+    failed: PathBuf,
+
+    #[command(flatten)]
+    discovery: DiscoveryArgs,
+
+    #[command(flatten)]
+    solver: SolverArgs,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// Path of the torrent to inspect.
+    #[arg(long, required = true)]
+    torrent: PathBuf,
+}
 
-        match Torrent::from_bytes(&bytes) {
-            Ok(torrent) => torrents.push(torrent),
-            Err(error) => eprintln!("Unable to load torrent from path {:#?} due to error: {:#?}:{}", torrent_path, error.kind, error.message),
+fn parse_device_concurrency(value: &str) -> Result<(PathBuf, usize), String> {
+    let (path, limit) = value.rsplit_once('=')
+        .ok_or_else(|| format!("Expected PATH=LIMIT, but found {:?}.", value))?;
+
+    let limit = limit.parse::<usize>()
+        .map_err(|err| format!("Invalid limit {:?}: {}", limit, err))?;
+
+    Ok((PathBuf::from(path), limit))
+}
+
+fn load_torrents(inputs: &[PathBuf], strict: bool) -> std::io::Result<Vec<Torrent>> {
+    let loaded = torrent_bootstrap::torrent::loader::load_torrents(inputs, strict)?;
+
+    if !loaded.skipped.is_empty() {
+        eprintln!("Skipped {} torrents that could not be loaded:", loaded.skipped.len());
+        for skipped in loaded.skipped.iter() {
+            eprintln!("    {:#?}: {}", skipped.path, skipped.message);
         }
     }
 
-    let torrent_len = torrents.len();
+    Ok(loaded.torrents)
+}
 
-    // Start it up!
-    let options = OrchestratorOptions {
+fn build_options(torrents: &[PathBuf], strict: bool, args: &DiscoveryArgs) -> std::io::Result<OrchestratorOptions> {
+    build_options_for(load_torrents(torrents, strict)?, args)
+}
+
+fn build_options_for(torrents: Vec<Torrent>, args: &DiscoveryArgs) -> std::io::Result<OrchestratorOptions> {
+    let mut options = OrchestratorOptions::new(
         torrents,
-        scan_directories: args.scan.iter().map(|value| Path::new(value).to_path_buf()).collect(),
-        export_directory: Path::new(&args.export).to_path_buf(),
-        threads: args.threads,
-        resize_export_files: args.resize_export_files
-    };
+        args.scan.iter().map(|value| Path::new(value).to_path_buf()).collect(),
+        Path::new(&args.export).to_path_buf()
+    );
+
+    options.resize_export_files = args.resize_export_files;
+    options.scan_filter = build_scan_filter(&args.filter)?;
+    options.index_threads = args.index_threads;
+    options.index_file = args.index_file.clone();
+    Ok(options)
+}
+
+fn build_scan_filter(args: &ScanFilterArgs) -> std::io::Result<ScanFilter> {
+    let mut include = Vec::new();
+    for pattern in args.include.iter() {
+        include.push(ScanPattern::glob(pattern)?);
+    }
+
+    for pattern in args.include_regex.iter() {
+        include.push(ScanPattern::regex(pattern)?);
+    }
+
+    let mut exclude = Vec::new();
+    for pattern in args.exclude.iter() {
+        exclude.push(ScanPattern::glob(pattern)?);
+    }
+
+    for pattern in args.exclude_regex.iter() {
+        exclude.push(ScanPattern::regex(pattern)?);
+    }
+
+    Ok(ScanFilter {
+        include,
+        exclude,
+        min_size: args.min_size,
+        max_size: args.max_size,
+        skip_hidden: args.skip_hidden,
+        max_depth: args.max_depth,
+        one_file_system: args.one_file_system,
+        follow_symlinks: args.follow_symlinks,
+        archives: args.scan_archives
+    })
+}
+
+// Stop gracefully on Ctrl-C so that finished pieces are kept in the checkpoint.
+fn install_interrupt_handler() -> std::io::Result<CancellationToken> {
+    let cancellation = CancellationToken::new();
+    let handler_cancellation = cancellation.clone();
+    ctrlc::set_handler(move || {
+        eprintln!("Received interrupt, waiting for in-progress pieces to stop...");
+        handler_cancellation.cancel();
+    }).map_err(|err| std::io::Error::other(format!("Unable to install interrupt handler: {}", err)))?;
+
+    Ok(cancellation)
+}
+
+fn apply_solver_args(options: &mut OrchestratorOptions, args: &SolverArgs) {
+    options.threads = args.threads;
+    options.checkpoint = args.checkpoint.clone();
+    options.max_combinations_per_piece = args.max_combinations_per_piece;
+    options.max_time_per_piece = args.max_seconds_per_piece.map(Duration::from_secs);
+    options.max_open_files = args.max_open_files;
+    options.rotational_concurrency = Some(args.rotational_concurrency);
+    options.non_rotational_concurrency = args.non_rotational_concurrency;
+    options.device_concurrency = args.device_concurrency.clone();
+    options.piece_order = args.piece_order.into();
+    options.read_ahead = args.read_ahead;
+    options.mmap = args.mmap;
+    options.pipeline_depth = args.pipeline_depth;
+    options.verify_first = args.verify_first;
+    options.web_seeds = args.web_seeds;
+    options.web_seed_timeout = Duration::from_secs(args.web_seed_timeout);
+    options.peers = args.peers.clone();
+    options.peer_timeout = Duration::from_secs(args.peer_timeout);
+}
+
+fn bootstrap(args: BootstrapArgs) -> std::io::Result<()> {
+    let now = Instant::now();
+
+    // Load Torrents
+    let mut options = build_options(&args.torrents, args.strict_torrents, &args.discovery)?;
+    let torrent_len = options.torrents.len();
+
+    // Start it up!
+    options.cancellation = install_interrupt_handler()?;
+    apply_solver_args(&mut options, &args.solver);
 
     let res = torrent_bootstrap::orchestrator::start(options);
     let elapsed = now.elapsed().as_secs();
     println!("Time elapsed took {} seconds for {} torrents.", elapsed, torrent_len);
-    res
+    res.map(|_| ())
+}
+
+fn plan(args: PlanArgs) -> std::io::Result<()> {
+    let options = build_options(&args.torrents, args.strict_torrents, &args.discovery)?;
+    let plans = torrent_bootstrap::orchestrator::plan(options, args.explosive_threshold)?;
+
+    for plan in plans.iter() {
+        print_plan(plan, args.all_pieces);
+    }
+
+    Ok(())
+}
+
+fn print_plan(plan: &TorrentPlan, all_pieces: bool) {
+    println!(
+        "Torrent {} ({}): {} pieces, {} combinations, {} bytes to read, {} explosive pieces, {} pieces without candidates",
+        get_sha1_hexdigest(&plan.info_hash), plan.name, plan.pieces.len(), plan.total_combinations,
+        plan.total_bytes_read, plan.explosive_pieces(), plan.unsolvable_pieces()
+    );
+
+    for file in plan.files_without_candidates.iter() {
+        println!("    No candidates for file {:#?}", file);
+    }
+
+    for piece in plan.pieces.iter() {
+        if !all_pieces && !piece.explosive && !piece.unsolvable() {
+            continue;
+        }
+
+        let flag = if piece.unsolvable() {
+            " [no candidates]"
+        } else if piece.explosive {
+            " [explosive]"
+        } else {
+            ""
+        };
+
+        println!(
+            "    Piece {}: candidates per file {:?}, {} combinations, {} bytes to read{}",
+            piece.position, piece.candidates, piece.combinations, piece.bytes_read, flag
+        );
+    }
+}
+
+fn explain(args: ExplainArgs) -> std::io::Result<()> {
+    let options = build_options(std::slice::from_ref(&args.torrent), true, &args.discovery)?;
+
+    let target = match (args.piece, args.file) {
+        (Some(position), _) => ExplainTarget::Piece(position),
+        (None, Some(path)) => ExplainTarget::File(path),
+        (None, None) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Either a piece or a file must be provided."))
+    };
+
+    match torrent_bootstrap::orchestrator::explain(options, target, args.max_combinations)? {
+        Explanation::Piece(piece) => print_piece_explanation(&piece, true),
+        Explanation::File { file, pieces } => {
+            print_file_explanation(&file, "");
+            println!("File spans {} pieces", pieces.len());
+            for piece in pieces.iter() {
+                print_piece_explanation(piece, false);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_file_explanation(file: &FileExplanation, indent: &str) {
+    println!("{}File {:#?} with length {}, exported to {:#?}", indent, file.path, file.file_length, file.export_target);
+
+    if file.padding {
+        println!("{}    Padding file, always filled with zeros", indent);
+        return;
+    }
+
+    println!(
+        "{}    {} files on disk with the same length, {} removed as hard-links, {} candidates",
+        indent, file.length_matches, file.hard_link_duplicates, file.candidates.len()
+    );
+
+    if file.candidates.is_empty() {
+        println!("{}    No candidates, any piece spanning this file cannot be solved", indent);
+    }
+
+    for candidate in file.candidates.iter() {
+        let outcome = match (&candidate.error, candidate.matched, candidate.tried) {
+            (Some(error), _, _) => format!("unreadable: {}", error),
+            (None, true, _) => "matched".to_string(),
+            (None, false, true) => "tried, no match".to_string(),
+            (None, false, false) if candidate.duplicate => "skipped, same bytes as another candidate".to_string(),
+            (None, false, false) => "not tried".to_string()
+        };
+
+        println!("{}    Candidate {:#?}: {}", indent, candidate.path, outcome);
+    }
+}
+
+fn print_piece_explanation(piece: &PieceExplanation, detailed: bool) {
+    let outcome = if piece.solved {
+        "solved".to_string()
+    } else if piece.truncated {
+        format!("not solved, stopped at the limit of {} combinations", piece.combinations_tried)
+    } else if piece.combinations_tried == 0 {
+        "not solved, no combinations could be tried".to_string()
+    } else {
+        format!("not solved, none of the {} combinations matched", piece.combinations_tried)
+    };
+
+    println!("Piece {} with length {} spans {} files, choices {:?}: {}", piece.position, piece.length, piece.files.len(), piece.total_choices, outcome);
+
+    if !detailed {
+        return;
+    }
+
+    for piece_file in piece.files.iter() {
+        println!("    Reads {} bytes starting at {} from:", piece_file.read_length, piece_file.read_start_position);
+        print_file_explanation(&piece_file.file, "    ");
+    }
+}
+
+fn verify(args: VerifyArgs) -> std::io::Result<ExitCode> {
+    let torrents = load_torrents(&args.torrents, args.strict_torrents)?;
+    let mut options = OrchestratorOptions::new(torrents, Vec::new(), args.data);
+    options.threads = args.threads;
+
+    let verifications = torrent_bootstrap::orchestrator::verify(options, args.layout.into())?;
+    for verification in verifications.iter() {
+        print_verification(verification);
+    }
+
+    Ok(verification_exit_code(&verifications))
+}
+
+// Exits with 1 when any torrent is incomplete, so scripts can check the data without parsing the output.
+fn verification_exit_code(verifications: &[TorrentVerification]) -> ExitCode {
+    if verifications.iter().all(|verification| verification.complete()) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
+
+fn print_verification(verification: &TorrentVerification) {
+    let bitfield = &verification.bitfield;
+    let completeness = if bitfield.is_empty() { 100_f64 } else { bitfield.count_ones() as f64 / bitfield.len() as f64 * 100_f64 };
+
+    println!(
+        "Torrent {} ({}): {} of {} pieces verified, {:.03}% complete",
+        get_sha1_hexdigest(&verification.info_hash), verification.name, bitfield.count_ones(), bitfield.len(), completeness
+    );
+    println!("    Bitfield: {}", bitfield.to_hex());
+
+    for file in verification.files.iter() {
+        let state = if !file.exists && file.file_length > 0 {
+            "missing"
+        } else if file.complete() {
+            "complete"
+        } else {
+            "incomplete"
+        };
+
+        println!("    File {:#?}: {} of {} pieces verified, {}", file.path, file.verified_pieces, file.total_pieces, state);
+    }
+}
+
+fn index(args: IndexArgs) -> std::io::Result<()> {
+    let scan_filter = build_scan_filter(&args.filter)?;
+    torrent_bootstrap::orchestrator::index(&args.scan, scan_filter, args.index_threads, &args.index_file)
+}
+
+fn watch(args: WatchArgs) -> std::io::Result<()> {
+    // Scan filters are checked before watching, rather than failing every torrent that arrives.
+    build_scan_filter(&args.discovery.filter)?;
+
+    let options = WatchOptions {
+        inbox: args.inbox,
+        processed: args.processed,
+        failed: args.failed,
+        cancellation: install_interrupt_handler()?
+    };
+
+    torrent_bootstrap::watch::watch(&options, |torrents| {
+        let mut options = build_options_for(torrents, &args.discovery)?;
+        apply_solver_args(&mut options, &args.solver);
+        Ok(options)
+    })
+}
+
+fn info(args: InspectArgs) -> std::io::Result<()> {
+    let bytes = fs::read(&args.torrent)?;
+    let inspection = torrent_bootstrap::inspect::inspect(&bytes)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unable to load torrent from path {:#?}: {}", args.torrent, err.message)))?;
+
+    print_inspection(&inspection);
+    Ok(())
 }
 
-fn main() {
-    if let Err(err) = run() {
-        eprintln!("Error: {}", err);
+fn print_inspection(inspection: &TorrentInspection) {
+    println!("Torrent {}", inspection.name);
+    println!("    Info hash: {}", get_sha1_hexdigest(&inspection.info_hash));
+    if let Some(info_hash_v2) = &inspection.info_hash_v2 {
+        println!("    Info hash v2: {}", get_sha1_hexdigest(info_hash_v2));
+    }
+
+    println!("    Piece length: {}", inspection.piece_length);
+    println!("    Pieces: {}", inspection.piece_count);
+    println!("    Total length: {}", inspection.total_length);
+    println!("    Files: {}", inspection.files.len());
+
+    for file in inspection.files.iter() {
+        let mut flags = Vec::new();
+        if file.padding {
+            flags.push("padding".to_string());
+        }
+
+        if let Some(attr) = &file.attr {
+            flags.push(format!("attr {:?}", attr));
+        }
+
+        let pieces = match file.pieces {
+            Some((first, last)) => format!("pieces {} to {}", first, last),
+            None => "no pieces".to_string()
+        };
+
+        let flags = if flags.is_empty() { String::new() } else { format!(" [{}]", flags.join(", ")) };
+        println!("        File {:#?} with length {} at offset {}, {}{}", file.path, file.length, file.offset, pieces, flags);
     }
 }
+
+fn dump(args: InspectArgs) -> std::io::Result<()> {
+    let bytes = fs::read(&args.torrent)?;
+    let token = BencodeParser::decode(&bytes)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unable to decode torrent from path {:#?}: {}", args.torrent, err.message)))?;
+
+    println!("{}", to_json(&token));
+    Ok(())
+}
+
+fn run() -> std::io::Result<ExitCode> {
+    let args = Cli::parse();
+
+    match (args.command, args.bootstrap) {
+        (Some(Command::Plan(plan_args)), _) => plan(plan_args).map(|_| ExitCode::SUCCESS),
+        (Some(Command::Explain(explain_args)), _) => explain(explain_args).map(|_| ExitCode::SUCCESS),
+        (Some(Command::Verify(verify_args)), _) => verify(verify_args),
+        (Some(Command::Index(index_args)), _) => index(index_args).map(|_| ExitCode::SUCCESS),
+        (Some(Command::Info(inspect_args)), _) => info(inspect_args).map(|_| ExitCode::SUCCESS),
+        (Some(Command::Dump(inspect_args)), _) => dump(inspect_args).map(|_| ExitCode::SUCCESS),
+        (Some(Command::Watch(watch_args)), _) => watch(watch_args).map(|_| ExitCode::SUCCESS),
+        (None, Some(bootstrap_args)) => bootstrap(bootstrap_args).map(|_| ExitCode::SUCCESS),
+        (None, None) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "No command or bootstrap arguments were provided."))
+    }
+}
+
+// Errors exit with 2, so they can be told apart from an incomplete verification.
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use torrent_bootstrap::bitfield::Bitfield;
+
+    use super::*;
+
+    fn verification(bitfield: Bitfield) -> TorrentVerification {
+        TorrentVerification { info_hash: vec![0; 20], name: "Example".to_string(), bitfield, files: Vec::new() }
+    }
+
+    #[test]
+    fn verification_exit_code_should_fail_incomplete_torrents() {
+        let complete = Bitfield::from_bytes(&[0xC0], 2).unwrap();
+        let incomplete = Bitfield::from_bytes(&[0x80], 2).unwrap();
+
+        assert_eq!(ExitCode::SUCCESS, verification_exit_code(&[verification(complete.clone())]));
+        assert_eq!(ExitCode::from(1), verification_exit_code(&[verification(complete), verification(incomplete)]));
+        assert_eq!(ExitCode::SUCCESS, verification_exit_code(&[]));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,105 @@
+use crate::hex;
+
+// Piece bitfield in the BitTorrent wire order, where the high bit of the first byte is the first piece.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitfield {
+    bytes: Vec<u8>,
+    len: usize
+}
+
+impl Bitfield {
+    pub fn new(len: usize) -> Bitfield {
+        Bitfield {
+            bytes: vec![0; len.div_ceil(8)],
+            len
+        }
+    }
+
+    // Reads a bitfield in wire order, such as the bitfield message of a peer. Returns None when the number of bytes
+    // does not match the length, or when any spare bit is set.
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Option<Bitfield> {
+        if bytes.len() != len.div_ceil(8) {
+            return None;
+        }
+
+        let spare_bits = bytes.len() * 8 - len;
+        if spare_bits > 0 && bytes[bytes.len() - 1] & ((1 << spare_bits) - 1) != 0 {
+            return None;
+        }
+
+        Some(Bitfield { bytes: bytes.to_vec(), len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn set(&mut self, index: usize) {
+        assert!(index < self.len, "Bitfield index {} is out of range for length {}.", index, self.len);
+        self.bytes[index / 8] |= 0x80 >> (index % 8);
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        index < self.len && self.bytes[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.bytes.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.count_ones() == self.len
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::to_hex(&self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_should_use_wire_order() {
+        let mut bitfield = Bitfield::new(10);
+        bitfield.set(0);
+        bitfield.set(9);
+
+        assert_eq!(&[0x80, 0x40], bitfield.as_bytes());
+        assert_eq!("8040", bitfield.to_hex());
+        assert!(bitfield.get(0));
+        assert!(!bitfield.get(1));
+        assert!(bitfield.get(9));
+        assert!(!bitfield.get(10));
+    }
+
+    #[test]
+    fn is_complete_should_ignore_spare_bits() {
+        let mut bitfield = Bitfield::new(3);
+        for index in 0..3 {
+            bitfield.set(index);
+        }
+
+        assert_eq!(3, bitfield.count_ones());
+        assert!(bitfield.is_complete());
+    }
+
+    #[test]
+    fn from_bytes_should_reject_invalid_lengths_and_spare_bits() {
+        let bitfield = Bitfield::from_bytes(&[0x80, 0x40], 10).unwrap();
+        assert!(bitfield.get(0));
+        assert!(bitfield.get(9));
+
+        assert_eq!(None, Bitfield::from_bytes(&[0x80], 10));
+        assert_eq!(None, Bitfield::from_bytes(&[0x80, 0x20], 10));
+    }
+}
\ No newline at end of file
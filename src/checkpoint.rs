@@ -0,0 +1,206 @@
+use std::{collections::HashMap, fs::{self, File, OpenOptions}, io::Write as IoWrite, path::Path};
+
+use crate::torrent::info::get_sha1_hexdigest;
+
+/**
+ * A checkpoint is a plain-text file with one line for every piece that finished processing.
+ * Each line is the hex info hash of the torrent, the piece position and the final state of the piece:
+ *
+ * 4f18c48b0d82934790c7fc16234abe38a308127b 12 success
+ *
+ * Lines are appended as pieces finish, so a run that is interrupted at any point leaves behind a usable
+ * checkpoint. A trailing line without a newline was only partially written, it is ignored on load and
+ * removed before new lines are appended.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointState {
+    Success
+}
+
+impl CheckpointState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CheckpointState::Success => "success"
+        }
+    }
+
+    fn parse(value: &str) -> Option<CheckpointState> {
+        match value {
+            "success" => Some(CheckpointState::Success),
+            _ => None
+        }
+    }
+}
+
+pub struct Checkpoint {
+    finished: HashMap<(String, usize), CheckpointState>
+}
+
+impl Checkpoint {
+    pub fn empty() -> Checkpoint {
+        Checkpoint {
+            finished: HashMap::new()
+        }
+    }
+
+    // Loads the checkpoint at the given path. A missing file is treated as an empty checkpoint.
+    pub fn load(path: &Path) -> Result<Checkpoint, std::io::Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Checkpoint::empty()),
+            Err(err) => return Err(err)
+        };
+
+        Checkpoint::parse(&contents).map_err(|message| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unable to read checkpoint {:#?}: {}", path, message))
+        })
+    }
+
+    fn parse(contents: &str) -> Result<Checkpoint, String> {
+        let mut checkpoint = Checkpoint::empty();
+
+        let complete_length = contents.rfind('\n').map(|position| position + 1).unwrap_or(0);
+
+        for (line_number, line) in contents[..complete_length].lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let entry = (parts.next(), parts.next(), parts.next(), parts.next());
+
+            let (info_hash, position, state) = match entry {
+                (Some(info_hash), Some(position), Some(state), None) => (info_hash, position, state),
+                _ => return Err(format!("Malformed entry on line {}.", line_number + 1))
+            };
+
+            let position = position.parse::<usize>()
+                .map_err(|_| format!("Invalid piece position on line {}.", line_number + 1))?;
+
+            let state = CheckpointState::parse(state)
+                .ok_or_else(|| format!("Unknown piece state on line {}.", line_number + 1))?;
+
+            checkpoint.finished.insert((info_hash.to_lowercase(), position), state);
+        }
+
+        Ok(checkpoint)
+    }
+
+    pub fn get(&self, info_hash: &[u8], position: usize) -> Option<CheckpointState> {
+        self.finished.get(&(get_sha1_hexdigest(info_hash), position)).copied()
+    }
+
+    // Whether the piece was found in an earlier run, and can be skipped.
+    pub fn is_finished(&self, info_hash: &[u8], position: usize) -> bool {
+        self.get(info_hash, position) == Some(CheckpointState::Success)
+    }
+
+    pub fn len(&self) -> usize {
+        self.finished.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.finished.is_empty()
+    }
+}
+
+pub struct CheckpointWriter {
+    handle: File
+}
+
+impl CheckpointWriter {
+    pub fn open(path: &Path) -> Result<CheckpointWriter, std::io::Error> {
+        let handle = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        // Appending after a partial line of an interrupted run would merge the next entry into it.
+        let contents = fs::read(path)?;
+        let complete_length = contents.iter().rposition(|byte| *byte == b'\n').map_or(0, |position| position + 1);
+        if complete_length < contents.len() {
+            handle.set_len(complete_length as u64)?;
+        }
+
+        Ok(CheckpointWriter { handle })
+    }
+
+    pub fn record(&mut self, info_hash: &[u8], position: usize, state: CheckpointState) -> Result<(), std::io::Error> {
+        let line = format!("{} {} {}\n", get_sha1_hexdigest(info_hash), position, state.as_str());
+        self.handle.write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::TempDir;
+
+    use super::*;
+
+    const INFO_HASH: [u8; 20] = [79, 24, 196, 139, 13, 130, 147, 71, 144, 199, 252, 22, 35, 74, 190, 56, 163, 8, 18, 123];
+
+    #[test]
+    fn parse_entries_should_succeed() {
+        let input = "4f18c48b0d82934790c7fc16234abe38a308127b 0 success\n4F18C48B0D82934790C7FC16234ABE38A308127B 3 success\n";
+        let checkpoint = Checkpoint::parse(input).unwrap();
+
+        assert_eq!(2, checkpoint.len());
+        assert_eq!(Some(CheckpointState::Success), checkpoint.get(&INFO_HASH, 0));
+        assert_eq!(Some(CheckpointState::Success), checkpoint.get(&INFO_HASH, 3));
+        assert_eq!(None, checkpoint.get(&INFO_HASH, 1));
+    }
+
+    #[test]
+    fn is_finished_should_only_accept_recorded_pieces() {
+        let input = "4f18c48b0d82934790c7fc16234abe38a308127b 0 success\n";
+        let checkpoint = Checkpoint::parse(input).unwrap();
+
+        assert!(checkpoint.is_finished(&INFO_HASH, 0));
+        assert!(!checkpoint.is_finished(&INFO_HASH, 1));
+    }
+
+    #[test]
+    fn parse_partial_trailing_entry_should_be_ignored() {
+        let input = "4f18c48b0d82934790c7fc16234abe38a308127b 0 success\n4f18c48b0d82934790c7fc16234abe38a308127b 1 succ";
+        let checkpoint = Checkpoint::parse(input).unwrap();
+
+        assert_eq!(1, checkpoint.len());
+        assert_eq!(None, checkpoint.get(&INFO_HASH, 1));
+    }
+
+    #[test]
+    fn parse_unknown_state_should_fail() {
+        let input = "4f18c48b0d82934790c7fc16234abe38a308127b 0 failed\n";
+        assert!(Checkpoint::parse(input).is_err());
+    }
+
+    #[test]
+    fn writer_should_resume_after_repeated_interruptions() {
+        let directory = TempDir::new("checkpoint_resume");
+        let path = directory.join("checkpoint");
+        let interrupt = |path: &Path| {
+            let mut handle = OpenOptions::new().append(true).open(path).unwrap();
+            handle.write_all(b"4f18c48b0d82934790c7fc16234abe38a308127b 9 succ").unwrap();
+        };
+
+        CheckpointWriter::open(&path).unwrap().record(&INFO_HASH, 0, CheckpointState::Success).unwrap();
+        interrupt(&path);
+
+        CheckpointWriter::open(&path).unwrap().record(&INFO_HASH, 1, CheckpointState::Success).unwrap();
+        interrupt(&path);
+
+        CheckpointWriter::open(&path).unwrap().record(&INFO_HASH, 2, CheckpointState::Success).unwrap();
+        let checkpoint = Checkpoint::load(&path).unwrap();
+
+        assert_eq!(3, checkpoint.len());
+        assert!((0..3).all(|position| checkpoint.is_finished(&INFO_HASH, position)));
+        assert!(!checkpoint.is_finished(&INFO_HASH, 9));
+    }
+
+    #[test]
+    fn parse_invalid_position_should_fail() {
+        let input = "4f18c48b0d82934790c7fc16234abe38a308127b -1 success\n";
+        assert!(Checkpoint::parse(input).is_err());
+    }
+}
\ No newline at end of file
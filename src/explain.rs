@@ -0,0 +1,356 @@
+use std::path::{Path, PathBuf};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    filesystem::{FileHandleCache, ReadOptions},
+    metadata::TorrentFileEntry,
+    orchestrator::Discovery,
+    solver::{choices::{ChoiceConsumer, ChoiceGenerator}, devices::DeviceScheduler, task::preload_piece},
+    torrent::Torrent
+};
+
+// Candidates are read one piece at a time, so only a few handles are kept open.
+const EXPLAIN_OPEN_FILES: usize = 16;
+
+pub enum ExplainTarget {
+    // Position of the piece in the torrent.
+    Piece(usize),
+    // Path of a file in the torrent, relative to the torrent name.
+    File(PathBuf)
+}
+
+#[derive(Debug)]
+pub struct CandidateExplanation {
+    pub path: PathBuf,
+    // The candidate was part of at least one hashed combination.
+    pub tried: bool,
+    pub matched: bool,
+    // The candidate has the same bytes as an earlier candidate of the piece, so the solver skipped it.
+    pub duplicate: bool,
+    pub error: Option<String>
+}
+
+#[derive(Debug)]
+pub struct FileExplanation {
+    pub path: PathBuf,
+    pub export_target: PathBuf,
+    pub file_length: u64,
+    pub padding: bool,
+    // Files on disk with the same length as the torrent file, before hard-links were removed.
+    pub length_matches: usize,
+    pub hard_link_duplicates: usize,
+    // Candidates in the order the solver searches them.
+    pub candidates: Vec<CandidateExplanation>
+}
+
+#[derive(Debug)]
+pub struct PieceFileExplanation {
+    pub read_start_position: u64,
+    pub read_length: u64,
+    pub file: FileExplanation
+}
+
+#[derive(Debug)]
+pub struct PieceExplanation {
+    pub position: usize,
+    pub length: u64,
+    pub files: Vec<PieceFileExplanation>,
+    // Choices for each file as used by the solver, empty when any file has no candidates.
+    pub total_choices: Vec<usize>,
+    pub combinations_tried: u64,
+    // The search stopped at the combination limit before every combination was tried.
+    pub truncated: bool,
+    pub solved: bool
+}
+
+#[derive(Debug)]
+pub enum Explanation {
+    Piece(PieceExplanation),
+    File {
+        file: FileExplanation,
+        pieces: Vec<PieceExplanation>
+    }
+}
+
+pub fn explain(torrent: &Torrent, discovery: &Discovery, target: &ExplainTarget, max_combinations: u64) -> Result<Explanation, std::io::Error> {
+    match target {
+        ExplainTarget::Piece(position) => {
+            let piece_id = discovery.torrent_pieces.iter()
+                .position(|piece| piece.position == *position)
+                .ok_or_else(|| std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Piece {} does not exist, the torrent has {} pieces.", position, torrent.info.pieces.len())
+                ))?;
+
+            Ok(Explanation::Piece(explain_piece(discovery, piece_id, max_combinations)))
+        },
+        ExplainTarget::File(path) => {
+            let file_id = find_file(torrent, discovery, path)
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("File {:#?} does not exist in the torrent.", path)))?;
+
+            let pieces: Vec<PieceExplanation> = discovery.torrent_pieces.iter()
+                .filter(|piece| piece.files.iter().any(|piece_file| piece_file.file_id == file_id))
+                .map(|piece| explain_piece(discovery, piece.piece_id, max_combinations))
+                .collect();
+
+            // Summarize the candidates across every piece the file spans.
+            let mut file = explain_file(discovery, &discovery.torrent_files[file_id]);
+            for piece in pieces.iter() {
+                let piece_file = piece.files.iter()
+                    .find(|piece_file| piece_file.file.path == file.path)
+                    .expect("Piece should span the explained file.");
+
+                for (candidate, piece_candidate) in file.candidates.iter_mut().zip(piece_file.file.candidates.iter()) {
+                    candidate.tried |= piece_candidate.tried;
+                    candidate.matched |= piece_candidate.matched;
+                    candidate.duplicate |= piece_candidate.duplicate;
+                    if candidate.error.is_none() {
+                        candidate.error = piece_candidate.error.clone();
+                    }
+                }
+            }
+
+            Ok(Explanation::File { file, pieces })
+        }
+    }
+}
+
+fn find_file(torrent: &Torrent, discovery: &Discovery, path: &Path) -> Option<usize> {
+    let name = Path::new(&torrent.info.name);
+
+    discovery.torrent_files.iter()
+        .find(|file| {
+            let relative_target = discovery.path_interner.get(file.relative_target);
+            path == relative_target || path == name.join(relative_target)
+        })
+        .map(|file| file.file_id)
+}
+
+fn explain_file(discovery: &Discovery, file: &TorrentFileEntry) -> FileExplanation {
+    let searches: &[usize] = file.searches.as_deref().unwrap_or(&[]);
+
+    let length_matches = if file.padding {
+        0
+    } else {
+        discovery.path_cache.entries.values()
+            .filter(|entry| entry.length() == file.file_length)
+            .count()
+    };
+
+    let candidates = searches.iter()
+        .map(|search| CandidateExplanation {
+            path: discovery.path_interner.get(*search).to_path_buf(),
+            tried: false,
+            matched: false,
+            duplicate: false,
+            error: None
+        })
+        .collect();
+
+    FileExplanation {
+        path: discovery.path_interner.get(file.relative_target).to_path_buf(),
+        export_target: discovery.path_interner.get(file.export_target).to_path_buf(),
+        file_length: file.file_length,
+        padding: file.padding,
+        length_matches,
+        hard_link_duplicates: length_matches.saturating_sub(searches.len()),
+        candidates
+    }
+}
+
+// Searches the piece the same way as the solver, with the same choices and preloaded candidates, but keeps track of every
+// candidate that was part of a hashed combination.
+fn explain_piece(discovery: &Discovery, piece_id: usize, max_combinations: u64) -> PieceExplanation {
+    let piece = &discovery.torrent_pieces[piece_id];
+    let mut handles = FileHandleCache::new(EXPLAIN_OPEN_FILES, ReadOptions::default());
+
+    // Candidates of every file in the piece, in the order the solver searches them.
+    let searches: Vec<&[usize]> = piece.files.iter()
+        .map(|piece_file| discovery.torrent_files[piece_file.file_id].searches.as_deref().unwrap_or(&[]))
+        .collect();
+
+    let files: Vec<PieceFileExplanation> = piece.files.iter()
+        .map(|piece_file| PieceFileExplanation {
+            read_start_position: piece_file.read_start_position,
+            read_length: piece_file.read_length,
+            file: explain_file(discovery, &discovery.torrent_files[piece_file.file_id])
+        })
+        .collect();
+
+    let mut explanation = PieceExplanation {
+        position: piece.position,
+        length: piece.length,
+        files,
+        total_choices: piece.total_choices.clone(),
+        combinations_tried: 0,
+        truncated: false,
+        solved: false
+    };
+
+    // Pieces spanning multiple files are searched from the preloaded candidates, like the solver does.
+    let mut choices = piece.total_choices.clone();
+    let mut preloaded = None;
+    if piece.files.len() > 1 {
+        let mut faults = Vec::new();
+        let loaded = preload_piece(piece, &discovery.torrent_files, &discovery.path_cache, &DeviceScheduler::unlimited(), &mut handles, &mut faults);
+
+        for fault in faults.iter() {
+            for (file, searches) in explanation.files.iter_mut().zip(searches.iter()) {
+                if let Some(candidate) = find_candidate(&mut file.file, searches, fault.path) {
+                    candidate.error = Some(fault.message.clone());
+                }
+            }
+        }
+
+        for (file_index, loaded_file) in loaded.iter().enumerate() {
+            choices[file_index] = loaded_file.len();
+
+            // Candidates that were readable but left out of the cache have the same bytes as a kept candidate.
+            for candidate in explanation.files[file_index].file.candidates.iter_mut() {
+                candidate.duplicate = candidate.error.is_none();
+            }
+
+            for (path, _) in loaded_file.iter() {
+                if let Some(path) = path && let Some(candidate) = find_candidate(&mut explanation.files[file_index].file, searches[file_index], *path) {
+                    candidate.duplicate = false;
+                }
+            }
+        }
+
+        preloaded = Some(loaded);
+    }
+
+    let mut generator = ChoiceGenerator::empty();
+    let mut consumer = ChoiceConsumer::empty();
+    let mut bytes = Vec::with_capacity(piece.length as usize);
+    let mut paths = Vec::with_capacity(piece.files.len());
+    let mut hasher = Sha1::new();
+
+    generator.reset_from(&choices, 1);
+
+    'chunks: while !generator.ended() {
+        generator.get(&mut consumer);
+        generator.next();
+
+        'choices: while !consumer.ended() {
+            if explanation.combinations_tried >= max_combinations {
+                explanation.truncated = true;
+                break 'chunks;
+            }
+
+            bytes.clear();
+            paths.clear();
+
+            for file_index in 0..consumer.len() {
+                let choice = consumer.get(file_index).get();
+                let piece_file = &piece.files[file_index];
+                let torrent_file = &discovery.torrent_files[piece_file.file_id];
+
+                if let Some(preloaded) = &preloaded {
+                    bytes.extend_from_slice(&preloaded[file_index][choice].1);
+                    paths.push(preloaded[file_index][choice].0);
+                } else if torrent_file.padding {
+                    bytes.extend(vec![0; piece_file.read_length as usize]);
+                    paths.push(None);
+                } else {
+                    let path_id = searches[file_index][choice];
+                    let source = discovery.path_cache.get(path_id).source();
+
+                    if let Err(err) = handles.read_source(source, piece_file.read_length, piece_file.read_start_position, &mut bytes) {
+                        explanation.files[file_index].file.candidates[choice].error = Some(err.to_string());
+                        consumer.next();
+                        continue 'choices;
+                    }
+
+                    paths.push(Some(path_id));
+                }
+            }
+
+            hasher.update(&bytes);
+            let hash = hasher.finalize_reset();
+            let matched = piece.hash.as_slice() == hash.as_slice();
+            explanation.combinations_tried += 1;
+
+            for (file_index, path) in paths.iter().enumerate() {
+                if let Some(path) = path && let Some(candidate) = find_candidate(&mut explanation.files[file_index].file, searches[file_index], *path) {
+                    candidate.tried = true;
+                    candidate.matched |= matched;
+                }
+            }
+
+            if matched {
+                explanation.solved = true;
+                break 'chunks;
+            }
+
+            consumer.next();
+        }
+    }
+
+    explanation
+}
+
+fn find_candidate<'a>(file: &'a mut FileExplanation, searches: &[usize], path_id: usize) -> Option<&'a mut CandidateExplanation> {
+    let index = searches.iter().position(|search| *search == path_id)?;
+    file.candidates.get_mut(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{filesystem::{DataSource, MemorySource}, test_support::{build_discovery, build_torrent}};
+
+    use super::*;
+
+    fn source(name: &str, bytes: &[u8]) -> (PathBuf, Box<dyn DataSource>) {
+        (PathBuf::from(name), Box::new(MemorySource::new(name, bytes.to_vec())))
+    }
+
+    #[test]
+    fn explain_piece_should_find_winning_combination() {
+        let torrent = build_torrent(&[("a.bin", b"abcd"), ("b.bin", b"efgh")], 8);
+        let sources = vec![
+            source("/candidates/a", b"abcd"),
+            source("/candidates/b", b"efgh"),
+            source("/candidates/junk", b"wxyz"),
+            source("/candidates/junk_copy", b"wxyz")
+        ];
+        let discovery = build_discovery(std::slice::from_ref(&torrent), Path::new("/export"), sources);
+
+        let Explanation::Piece(piece) = explain(&torrent, &discovery, &ExplainTarget::Piece(0), 100).unwrap() else {
+            panic!("Expected a piece explanation.");
+        };
+
+        assert!(piece.solved);
+        assert!(!piece.truncated);
+        assert_eq!(vec![4, 4], piece.total_choices);
+        // The copy of the junk candidate is left out when preloading, so at most 3 by 3 combinations are tried.
+        assert!(piece.combinations_tried >= 1 && piece.combinations_tried <= 9);
+
+        for (piece_file, winner) in piece.files.iter().zip(["/candidates/a", "/candidates/b"]) {
+            let matched: Vec<&Path> = piece_file.file.candidates.iter()
+                .filter(|candidate| candidate.matched)
+                .map(|candidate| candidate.path.as_path())
+                .collect();
+
+            assert_eq!(vec![Path::new(winner)], matched);
+            assert_eq!(1, piece_file.file.candidates.iter().filter(|candidate| candidate.duplicate).count());
+            assert!(piece_file.file.candidates.iter().all(|candidate| !(candidate.duplicate && candidate.tried)));
+        }
+    }
+
+    #[test]
+    fn explain_piece_should_stop_at_combination_limit() {
+        let torrent = build_torrent(&[("a.bin", b"abcd")], 4);
+        let sources = vec![source("/candidates/junk", b"wxyz"), source("/candidates/other", b"stuv")];
+        let discovery = build_discovery(std::slice::from_ref(&torrent), Path::new("/export"), sources);
+
+        let Explanation::Piece(piece) = explain(&torrent, &discovery, &ExplainTarget::Piece(0), 1).unwrap() else {
+            panic!("Expected a piece explanation.");
+        };
+
+        assert!(!piece.solved);
+        assert!(piece.truncated);
+        assert_eq!(1, piece.combinations_tried);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,165 @@
+use std::{io::{Error, ErrorKind, Read, Result}, time::Duration};
+
+use ureq::{Agent, AgentBuilder};
+
+// Redirects followed before a request is given up.
+const MAX_REDIRECTS: u32 = 5;
+
+// Builds the client shared by every request, which keeps connections to the same server open between requests.
+pub fn build_agent(timeout: Duration) -> Agent {
+    AgentBuilder::new()
+        .timeout_connect(timeout)
+        .timeout_read(timeout)
+        .timeout_write(timeout)
+        .redirects(MAX_REDIRECTS)
+        .user_agent("torrent_bootstrap")
+        .build()
+}
+
+// Only HTTP and HTTPS URLs can be requested.
+pub fn validate_url(agent: &Agent, url: &str) -> Result<()> {
+    let request_url = agent.get(url)
+        .request_url()
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("Invalid URL {:?}, {}.", url, err)))?;
+
+    match request_url.scheme() {
+        "http" | "https" => Ok(()),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("Invalid URL {:?}, only HTTP and HTTPS are supported.", url)))
+    }
+}
+
+// Requests a byte range of the resource at the URL, following redirects. Servers that ignore the range and return the
+// whole resource are also supported, in which case the bytes before the range are skipped. Returns an error unless
+// exactly the requested number of bytes were received.
+pub fn get_range(agent: &Agent, url: &str, start: u64, length: u64) -> Result<Vec<u8>> {
+    if length == 0 {
+        return Ok(Vec::new());
+    }
+
+    let response = agent.get(url)
+        .set("Range", &format!("bytes={}-{}", start, start + length - 1))
+        .call()
+        .map_err(|err| request_error(url, err))?;
+
+    let skip = match response.status() {
+        // The server may return a different range than the one requested.
+        206 => {
+            let range_start = response.header("content-range")
+                .and_then(|value| value.strip_prefix("bytes "))
+                .and_then(|value| value.split('-').next())
+                .and_then(|value| value.trim().parse::<u64>().ok());
+
+            match range_start {
+                Some(range_start) if range_start <= start => start - range_start,
+                _ => return Err(Error::new(ErrorKind::InvalidData, format!("Response for {} has an unexpected content range.", url)))
+            }
+        },
+        200 => start,
+        status => return Err(Error::other(format!("Request for {} failed with status {} {}.", url, status, response.status_text())))
+    };
+
+    let mut body = response.into_reader();
+    std::io::copy(&mut body.by_ref().take(skip), &mut std::io::sink())?;
+
+    let mut bytes = Vec::with_capacity(length as usize);
+    body.take(length).read_to_end(&mut bytes)?;
+
+    if (bytes.len() as u64) < length {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("Expected to receive {} bytes at position {} from {}, but only {} bytes were received.", length, start, url, bytes.len())
+        ));
+    }
+
+    Ok(bytes)
+}
+
+// Keeps the kind of the underlying IO error, such as a timeout, when there is one.
+fn request_error(url: &str, err: ureq::Error) -> Error {
+    match err {
+        ureq::Error::Status(status, response) => {
+            Error::other(format!("Request for {} failed with status {} {}.", url, status, response.status_text()))
+        },
+        ureq::Error::Transport(transport) => {
+            let kind = std::error::Error::source(&transport)
+                .and_then(|source| source.downcast_ref::<Error>())
+                .map_or(ErrorKind::Other, Error::kind);
+
+            Error::new(kind, format!("Request for {} failed: {}", url, transport))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, BufReader, Write}, net::TcpListener, thread};
+
+    use super::*;
+
+    // Serves one canned response for every request, and returns the requests that were received.
+    fn serve(responses: Vec<Vec<u8>>) -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+
+                    request.push_str(line.trim_end());
+                    request.push('\n');
+                }
+
+                stream.write_all(&response).unwrap();
+                requests.push(request);
+            }
+
+            requests
+        });
+
+        (port, handle)
+    }
+
+    #[test]
+    fn validate_url_should_accept_http_and_https() {
+        let agent = build_agent(Duration::from_secs(5));
+
+        assert!(validate_url(&agent, "http://example.com:8080/seed/a%20b?x=1").is_ok());
+        assert!(validate_url(&agent, "https://example.com/").is_ok());
+        assert_eq!(ErrorKind::InvalidInput, validate_url(&agent, "ftp://example.com/").unwrap_err().kind());
+        assert_eq!(ErrorKind::InvalidInput, validate_url(&agent, "example.com").unwrap_err().kind());
+    }
+
+    #[test]
+    fn get_range_should_handle_ranges_redirects_and_chunks() {
+        let (port, handle) = serve(vec![
+            b"HTTP/1.1 302 Found\r\nLocation: /files/data.bin\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+            b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 2-5/10\r\nContent-Length: 4\r\nConnection: close\r\n\r\n2345".to_vec(),
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n3\r\n012\r\n7;ext=1\r\n3456789\r\n0\r\n\r\n".to_vec(),
+            b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n0123".to_vec(),
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+        ]);
+
+        let url = format!("http://127.0.0.1:{}/data.bin", port);
+        let agent = build_agent(Duration::from_secs(5));
+
+        assert_eq!(b"2345".to_vec(), get_range(&agent, &url, 2, 4).unwrap());
+        assert_eq!(b"4567".to_vec(), get_range(&agent, &url, 4, 4).unwrap());
+        assert_eq!(ErrorKind::UnexpectedEof, get_range(&agent, &url, 2, 4).unwrap_err().kind());
+        assert!(get_range(&agent, &url, 0, 4).unwrap_err().to_string().contains("404"));
+
+        let requests = handle.join().unwrap();
+        assert!(requests[0].starts_with("GET /data.bin HTTP/1.1\n"));
+        assert!(requests[0].contains("Range: bytes=2-5\n"));
+        assert!(requests[1].starts_with("GET /files/data.bin HTTP/1.1\n"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,16 @@
+pub mod http;
+pub mod peer;
+pub mod web_seed;
+
+use crate::metadata::{TorrentFileEntry, TorrentPieceEntry};
+
+// Somewhere other than the scan directories that pieces can be fetched from once the search has finished.
+pub trait PieceFetcher {
+    // Describes the fetcher in progress messages, such as "web seeds".
+    fn description(&self) -> &str;
+
+    fn can_fetch(&self, torrent_id: usize) -> bool;
+
+    // Returns the bytes of the piece, which always match the piece hash, or None when the piece could not be fetched.
+    fn fetch_piece(&mut self, piece: &TorrentPieceEntry, torrent_files: &[TorrentFileEntry]) -> Option<Vec<u8>>;
+}
\ No newline at end of file
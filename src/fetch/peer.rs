@@ -0,0 +1,512 @@
+use std::{collections::{HashMap, HashSet}, io::{Error, ErrorKind, Read, Result, Write}, net::{SocketAddr, TcpStream}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use sha1::{Digest, Sha1};
+
+use crate::{bitfield::Bitfield, fetch::PieceFetcher, metadata::{TorrentFileEntry, TorrentPieceEntry}, torrent::{info::get_sha1_hexdigest, Torrent}};
+
+const PROTOCOL: &[u8] = b"BitTorrent protocol";
+const HANDSHAKE_LENGTH: usize = 1 + 19 + 8 + 20 + 20;
+
+// Pieces are requested in blocks of 16 KiB, which every client serves.
+const BLOCK_LENGTH: u64 = 16384;
+// Blocks requested ahead of the blocks received, so the peer is not idle between blocks.
+const MAX_PENDING_REQUESTS: u64 = 16;
+// Larger messages are treated as a broken peer, the largest expected message is the bitfield of a huge torrent.
+const MAX_MESSAGE_LENGTH: usize = 4 * 1024 * 1024;
+// Keep-alives and unrelated messages restart the read timeout, so waiting for the unchoke, and for every piece, is
+// also limited to this many timeouts in total.
+const DEADLINE_TIMEOUTS: u32 = 4;
+
+const CHOKE: u8 = 0;
+const UNCHOKE: u8 = 1;
+const INTERESTED: u8 = 2;
+const HAVE: u8 = 4;
+const BITFIELD: u8 = 5;
+const REQUEST: u8 = 6;
+const PIECE: u8 = 7;
+
+// Azureus-style peer id. The rest is derived from the process id and the time, so every run uses a new id.
+pub fn generate_peer_id() -> [u8; 20] {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_nanos());
+    let digest = Sha1::digest(format!("{}:{}", std::process::id(), nanos));
+
+    let mut peer_id = [0; 20];
+    peer_id[..8].copy_from_slice(b"-TB0100-");
+    peer_id[8..].copy_from_slice(&digest[..12]);
+    peer_id
+}
+
+// Connection to a single peer for a single torrent, following the wire protocol of BEP 3. No extensions are
+// announced in the handshake, so the peer only sends the messages of BEP 3.
+pub struct PeerConnection {
+    stream: TcpStream,
+    // Pieces the peer announced with its bitfield and have messages.
+    pieces: Bitfield,
+    choked: bool,
+    timeout: Duration
+}
+
+impl PeerConnection {
+    // Performs the handshake, then waits until the peer unchokes this connection. Messages the peer sends before it
+    // unchokes, such as its bitfield, are applied along the way.
+    pub fn connect(address: &SocketAddr, info_hash: &[u8], peer_id: &[u8; 20], piece_count: usize, timeout: Duration) -> Result<PeerConnection> {
+        let mut stream = TcpStream::connect_timeout(address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.set_nodelay(true)?;
+
+        let mut handshake = Vec::with_capacity(HANDSHAKE_LENGTH);
+        handshake.push(PROTOCOL.len() as u8);
+        handshake.extend_from_slice(PROTOCOL);
+        handshake.extend_from_slice(&[0; 8]);
+        handshake.extend_from_slice(info_hash);
+        handshake.extend_from_slice(peer_id);
+        stream.write_all(&handshake)?;
+
+        let mut response = [0; HANDSHAKE_LENGTH];
+        stream.read_exact(&mut response)?;
+
+        if response[0] as usize != PROTOCOL.len() || &response[1..20] != PROTOCOL {
+            return Err(Error::new(ErrorKind::InvalidData, "Peer does not speak the BitTorrent protocol."));
+        }
+
+        if &response[28..48] != info_hash {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Peer answered the handshake for torrent {} with torrent {}.", get_sha1_hexdigest(info_hash), get_sha1_hexdigest(&response[28..48]))
+            ));
+        }
+
+        let mut connection = PeerConnection { stream, pieces: Bitfield::new(piece_count), choked: true, timeout };
+        connection.send_message(INTERESTED, &[])?;
+
+        let deadline = connection.deadline();
+        connection.wait_for_unchoke(deadline)?;
+
+        Ok(connection)
+    }
+
+    pub fn has_piece(&self, index: usize) -> bool {
+        self.pieces.get(index)
+    }
+
+    // Requests every block of the piece, keeping a few requests in flight, and returns the bytes of the piece once
+    // every block arrived. The bytes are not checked against the piece hash.
+    pub fn request_piece(&mut self, index: usize, length: u64) -> Result<Vec<u8>> {
+        let deadline = self.deadline();
+        self.wait_for_unchoke(deadline)?;
+
+        let block_count = length.div_ceil(BLOCK_LENGTH);
+        let mut bytes = vec![0; length as usize];
+        let mut received = vec![false; block_count as usize];
+        let mut received_blocks = 0;
+        let mut requested_blocks = 0;
+
+        while received_blocks < block_count {
+            while requested_blocks < block_count && requested_blocks - received_blocks < MAX_PENDING_REQUESTS {
+                let begin = requested_blocks * BLOCK_LENGTH;
+                let block_length = std::cmp::min(BLOCK_LENGTH, length - begin);
+
+                let mut payload = Vec::with_capacity(12);
+                payload.extend_from_slice(&(index as u32).to_be_bytes());
+                payload.extend_from_slice(&(begin as u32).to_be_bytes());
+                payload.extend_from_slice(&(block_length as u32).to_be_bytes());
+                self.send_message(REQUEST, &payload)?;
+
+                requested_blocks += 1;
+            }
+
+            let Some((id, payload)) = self.read_message(deadline)? else {
+                continue;
+            };
+
+            if id != PIECE {
+                self.apply_message(id, &payload)?;
+                if self.choked {
+                    return Err(Error::other(format!("Peer choked the connection before piece {} was received.", index)));
+                }
+
+                continue;
+            }
+
+            if payload.len() < 8 {
+                return Err(Error::new(ErrorKind::InvalidData, "Peer sent a truncated piece message."));
+            }
+
+            // Blocks of other pieces, or blocks that were already received, are left over from earlier requests.
+            let block_index = u32::from_be_bytes(payload[0..4].try_into().unwrap()) as usize;
+            let begin = u32::from_be_bytes(payload[4..8].try_into().unwrap()) as u64;
+            let block = &payload[8..];
+            let block_number = begin / BLOCK_LENGTH;
+
+            if block_index != index || !begin.is_multiple_of(BLOCK_LENGTH) || block_number >= requested_blocks || received[block_number as usize] {
+                continue;
+            }
+
+            if block.len() as u64 != std::cmp::min(BLOCK_LENGTH, length - begin) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Peer sent a block of {} bytes at offset {} of piece {}.", block.len(), begin, index)));
+            }
+
+            bytes[begin as usize..begin as usize + block.len()].copy_from_slice(block);
+            received[block_number as usize] = true;
+            received_blocks += 1;
+        }
+
+        Ok(bytes)
+    }
+
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout * DEADLINE_TIMEOUTS
+    }
+
+    fn wait_for_unchoke(&mut self, deadline: Instant) -> Result<()> {
+        while self.choked {
+            if let Some((id, payload)) = self.read_message(deadline)? {
+                self.apply_message(id, &payload)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Updates the state of the connection from any message that is not a block.
+    fn apply_message(&mut self, id: u8, payload: &[u8]) -> Result<()> {
+        match id {
+            CHOKE => self.choked = true,
+            UNCHOKE => self.choked = false,
+            HAVE if payload.len() == 4 => {
+                let index = u32::from_be_bytes(payload.try_into().unwrap()) as usize;
+                if index < self.pieces.len() {
+                    self.pieces.set(index);
+                }
+            },
+            BITFIELD => {
+                self.pieces = Bitfield::from_bytes(payload, self.pieces.len())
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Peer sent a bitfield that does not match the piece count."))?;
+            },
+            // Interest from the peer, requests and cancels are ignored, as this connection never uploads.
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn send_message(&mut self, id: u8, payload: &[u8]) -> Result<()> {
+        let mut message = Vec::with_capacity(5 + payload.len());
+        message.extend_from_slice(&(1 + payload.len() as u32).to_be_bytes());
+        message.push(id);
+        message.extend_from_slice(payload);
+        self.stream.write_all(&message)
+    }
+
+    // Returns None for keep-alive messages.
+    fn read_message(&mut self, deadline: Instant) -> Result<Option<(u8, Vec<u8>)>> {
+        if Instant::now() >= deadline {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("Gave up waiting for the peer after {} seconds.", (self.timeout * DEADLINE_TIMEOUTS).as_secs_f64())
+            ));
+        }
+
+        let mut length = [0; 4];
+        self.stream.read_exact(&mut length)?;
+
+        let length = u32::from_be_bytes(length) as usize;
+        if length == 0 {
+            return Ok(None);
+        }
+
+        if length > MAX_MESSAGE_LENGTH {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Peer sent a message of {} bytes.", length)));
+        }
+
+        let mut message = vec![0; length];
+        self.stream.read_exact(&mut message)?;
+
+        let payload = message.split_off(1);
+        Ok(Some((message[0], payload)))
+    }
+}
+
+// The peers given by the user, with a connection to every peer for every torrent that needs pieces.
+pub struct Peers {
+    addresses: Vec<SocketAddr>,
+    info_hashes: Vec<Vec<u8>>,
+    piece_counts: Vec<usize>,
+    peer_id: [u8; 20],
+    timeout: Duration,
+    // Open connections by peer and torrent, reused for every piece of the torrent.
+    connections: HashMap<(usize, usize), PeerConnection>,
+    // Peers that could not be connected to for a torrent are not tried again for that torrent.
+    unreachable: HashSet<(usize, usize)>
+}
+
+impl Peers {
+    pub fn new(addresses: &[SocketAddr], torrents: &[Torrent], timeout: Duration) -> Peers {
+        Peers {
+            addresses: addresses.to_vec(),
+            info_hashes: torrents.iter().map(|torrent| torrent.info_hash.clone()).collect(),
+            piece_counts: torrents.iter().map(|torrent| torrent.info.pieces.len()).collect(),
+            peer_id: generate_peer_id(),
+            timeout,
+            connections: HashMap::new(),
+            unreachable: HashSet::new()
+        }
+    }
+
+    fn connection(&mut self, peer: usize, torrent_id: usize) -> Option<&mut PeerConnection> {
+        let key = (peer, torrent_id);
+        if self.unreachable.contains(&key) {
+            return None;
+        }
+
+        if !self.connections.contains_key(&key) {
+            let info_hash = &self.info_hashes[torrent_id];
+            let address = &self.addresses[peer];
+
+            match PeerConnection::connect(address, info_hash, &self.peer_id, self.piece_counts[torrent_id], self.timeout) {
+                Ok(connection) => {
+                    self.connections.insert(key, connection);
+                },
+                Err(err) => {
+                    eprintln!("Unable to connect to peer {} for torrent {}: {}", address, get_sha1_hexdigest(info_hash), err);
+                    self.unreachable.insert(key);
+                    return None;
+                }
+            }
+        }
+
+        self.connections.get_mut(&key)
+    }
+}
+
+impl PieceFetcher for Peers {
+    fn description(&self) -> &str {
+        "peers"
+    }
+
+    fn can_fetch(&self, _torrent_id: usize) -> bool {
+        !self.addresses.is_empty()
+    }
+
+    // Requests the piece from each peer that has it in turn, until the bytes of a peer match the piece hash. A
+    // connection that fails is dropped, and opened again for the next piece.
+    fn fetch_piece(&mut self, piece: &TorrentPieceEntry, _torrent_files: &[TorrentFileEntry]) -> Option<Vec<u8>> {
+        for peer in 0..self.addresses.len() {
+            let address = self.addresses[peer];
+            let Some(connection) = self.connection(peer, piece.torrent_id) else {
+                continue;
+            };
+
+            if !connection.has_piece(piece.position) {
+                continue;
+            }
+
+            match connection.request_piece(piece.position, piece.length) {
+                Ok(bytes) if Sha1::digest(&bytes).as_slice() == piece.hash.as_slice() => return Some(bytes),
+                Ok(_) => eprintln!("Peer {} sent data for piece {} that does not match the piece hash.", address, piece.position),
+                Err(err) => {
+                    eprintln!("Unable to fetch piece {} from peer {}: {}", piece.position, address, err);
+                    self.connections.remove(&(peer, piece.torrent_id));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{TcpListener, TcpStream}, path::Path, thread};
+
+    use crate::test_support::{build_discovery, build_torrent};
+
+    use super::*;
+
+    // Seeds the data of a torrent, accepting one connection for every entry of the block limits in turn. Every
+    // connection answers its requests in order, and is closed once it sent the given number of blocks.
+    fn serve(info_hash: Vec<u8>, data: Vec<u8>, piece_length: usize, block_limits: Vec<usize>) -> (SocketAddr, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            for block_limit in block_limits {
+                let (stream, _) = listener.accept().unwrap();
+                serve_connection(stream, &info_hash, &data, piece_length, block_limit);
+            }
+        });
+
+        (address, handle)
+    }
+
+    fn serve_connection(mut stream: TcpStream, info_hash: &[u8], data: &[u8], piece_length: usize, block_limit: usize) {
+        let mut handshake = [0; HANDSHAKE_LENGTH];
+        stream.read_exact(&mut handshake).unwrap();
+        handshake[28..48].copy_from_slice(info_hash);
+        stream.write_all(&handshake).unwrap();
+
+        let piece_count = data.len().div_ceil(piece_length);
+        let mut pieces = Bitfield::new(piece_count);
+        for index in 0..piece_count - 1 {
+            pieces.set(index);
+        }
+
+        // The last piece is announced after the unchoke, and a keep-alive is sent first.
+        let mut messages = Vec::new();
+        messages.extend_from_slice(&(1 + pieces.as_bytes().len() as u32).to_be_bytes());
+        messages.push(BITFIELD);
+        messages.extend_from_slice(pieces.as_bytes());
+        messages.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, UNCHOKE, 0, 0, 0, 5, HAVE]);
+        messages.extend_from_slice(&(piece_count as u32 - 1).to_be_bytes());
+        // A client that rejected the handshake has closed the connection already.
+        if stream.write_all(&messages).is_err() {
+            return;
+        }
+
+        let mut sent_blocks = 0;
+        let mut message = [0; 4 + 13];
+        while sent_blocks < block_limit && stream.read_exact(&mut message[..4]).is_ok() {
+            let length = u32::from_be_bytes(message[..4].try_into().unwrap()) as usize;
+            stream.read_exact(&mut message[4..4 + length]).unwrap();
+            if message[4] != REQUEST {
+                continue;
+            }
+
+            let index = u32::from_be_bytes(message[5..9].try_into().unwrap()) as usize;
+            let begin = u32::from_be_bytes(message[9..13].try_into().unwrap()) as usize;
+            let block_length = u32::from_be_bytes(message[13..17].try_into().unwrap()) as usize;
+            let start = index * piece_length + begin;
+
+            let mut response = Vec::new();
+            response.extend_from_slice(&(9 + block_length as u32).to_be_bytes());
+            response.push(PIECE);
+            response.extend_from_slice(&message[5..13]);
+            response.extend_from_slice(&data[start..start + block_length]);
+            stream.write_all(&response).unwrap();
+            sent_blocks += 1;
+        }
+    }
+
+    fn test_data() -> Vec<u8> {
+        (0..100000).map(|value| (value % 251) as u8).collect()
+    }
+
+    #[test]
+    fn request_piece_should_download_every_block() {
+        let data = test_data();
+        let info_hash = vec![7; 20];
+        let (address, handle) = serve(info_hash.clone(), data.clone(), 65536, vec![usize::MAX]);
+
+        let mut connection = PeerConnection::connect(&address, &info_hash, &generate_peer_id(), 2, Duration::from_secs(5)).unwrap();
+        assert!(connection.has_piece(0));
+
+        let first = connection.request_piece(0, 65536).unwrap();
+        assert!(connection.has_piece(1));
+        let last = connection.request_piece(1, 100000 - 65536).unwrap();
+
+        drop(connection);
+        handle.join().unwrap();
+
+        assert_eq!(&data[..65536], first.as_slice());
+        assert_eq!(&data[65536..], last.as_slice());
+    }
+
+    #[test]
+    fn connect_should_reject_other_torrents() {
+        let (address, handle) = serve(vec![8; 20], vec![0; 16], 16, vec![usize::MAX]);
+
+        let result = PeerConnection::connect(&address, &[7; 20], &generate_peer_id(), 1, Duration::from_secs(5));
+        assert_eq!(ErrorKind::InvalidData, result.err().unwrap().kind());
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn connect_should_time_out_when_the_peer_never_unchokes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // Keep-alives arrive well within the read timeout, so only the deadline ends the wait.
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut handshake = [0; HANDSHAKE_LENGTH];
+            stream.read_exact(&mut handshake).unwrap();
+            stream.write_all(&handshake).unwrap();
+
+            while stream.write_all(&[0, 0, 0, 0]).is_ok() {
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let result = PeerConnection::connect(&address, &[7; 20], &generate_peer_id(), 1, Duration::from_millis(100));
+        assert_eq!(ErrorKind::TimedOut, result.err().unwrap().kind());
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn fetch_piece_should_try_the_next_peer_after_a_hash_mismatch() {
+        let data = test_data();
+        let torrent = build_torrent(&[("data.bin", &data)], 65536);
+        let discovery = build_discovery(std::slice::from_ref(&torrent), Path::new("/export"), Vec::new());
+
+        let mut corrupt = data.clone();
+        corrupt[100] ^= 1;
+
+        let (corrupt_address, corrupt_handle) = serve(torrent.info_hash.clone(), corrupt, 65536, vec![usize::MAX]);
+        let (address, handle) = serve(torrent.info_hash.clone(), data.clone(), 65536, vec![usize::MAX]);
+
+        let mut peers = Peers::new(&[corrupt_address, address], std::slice::from_ref(&torrent), Duration::from_secs(5));
+        let bytes = peers.fetch_piece(&discovery.torrent_pieces[0], &discovery.torrent_files);
+
+        assert_eq!(Some(&data[..65536]), bytes.as_deref());
+        // A peer that sent the wrong data is still connected, only the piece is taken from another peer.
+        assert_eq!(2, peers.connections.len());
+
+        drop(peers);
+        corrupt_handle.join().unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn fetch_piece_should_try_the_next_peer_after_a_dropped_connection() {
+        let data = test_data();
+        let torrent = build_torrent(&[("data.bin", &data)], 65536);
+        let discovery = build_discovery(std::slice::from_ref(&torrent), Path::new("/export"), Vec::new());
+
+        let (dropping_address, dropping_handle) = serve(torrent.info_hash.clone(), data.clone(), 65536, vec![1]);
+        let (address, handle) = serve(torrent.info_hash.clone(), data.clone(), 65536, vec![usize::MAX]);
+
+        let mut peers = Peers::new(&[dropping_address, address], std::slice::from_ref(&torrent), Duration::from_secs(5));
+        let bytes = peers.fetch_piece(&discovery.torrent_pieces[0], &discovery.torrent_files);
+
+        assert_eq!(Some(&data[..65536]), bytes.as_deref());
+        assert!(!peers.connections.contains_key(&(0, 0)));
+        assert!(peers.connections.contains_key(&(1, 0)));
+
+        drop(peers);
+        dropping_handle.join().unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn fetch_piece_should_reconnect_after_a_dropped_connection() {
+        let data = test_data();
+        let torrent = build_torrent(&[("data.bin", &data)], 65536);
+        let discovery = build_discovery(std::slice::from_ref(&torrent), Path::new("/export"), Vec::new());
+
+        let (address, handle) = serve(torrent.info_hash.clone(), data.clone(), 65536, vec![1, usize::MAX]);
+
+        let mut peers = Peers::new(&[address], std::slice::from_ref(&torrent), Duration::from_secs(5));
+        assert_eq!(None, peers.fetch_piece(&discovery.torrent_pieces[0], &discovery.torrent_files));
+        assert!(peers.connections.is_empty());
+
+        let bytes = peers.fetch_piece(&discovery.torrent_pieces[0], &discovery.torrent_files);
+        assert_eq!(Some(&data[..65536]), bytes.as_deref());
+        assert!(peers.unreachable.is_empty());
+
+        drop(peers);
+        handle.join().unwrap();
+    }
+}
\ No newline at end of file
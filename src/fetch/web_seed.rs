@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use sha1::{Digest, Sha1};
+use ureq::Agent;
+
+use crate::{fetch::{http::{build_agent, get_range, validate_url}, PieceFetcher}, metadata::{TorrentFileEntry, TorrentPieceEntry}, torrent::Torrent};
+
+// Builds the URL of every file of the torrent on a web seed, following BEP 19. For a single-file torrent, a URL ending
+// with a slash is the directory holding the file, any other URL is the file itself. For a multi-file torrent, the URL
+// is the directory holding the torrent directory.
+pub fn web_seed_file_urls(seed: &str, torrent: &Torrent) -> Vec<String> {
+    let name = percent_encode(&torrent.info.name);
+
+    let files = match &torrent.info.files {
+        Some(files) => files,
+        None if seed.ends_with('/') => return vec![format!("{}{}", seed, name)],
+        None => return vec![seed.to_string()]
+    };
+
+    let separator = if seed.ends_with('/') { "" } else { "/" };
+    files.iter()
+        .map(|file| {
+            let path: Vec<String> = file.path.iter().map(|segment| percent_encode(segment)).collect();
+            format!("{}{}{}/{}", seed, separator, name, path.join("/"))
+        })
+        .collect()
+}
+
+// Encodes every byte of a path segment except the unreserved characters of RFC 3986.
+pub fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    encoded
+}
+
+// The web seeds of every torrent, with the URL of every file of the torrent on each seed.
+pub struct WebSeeds {
+    // Indexed by torrent, then by seed, then by the position of the file within the torrent.
+    file_urls: Vec<Vec<Vec<String>>>,
+    // File id of the first file of every torrent.
+    first_file_ids: Vec<usize>,
+    agent: Agent
+}
+
+impl WebSeeds {
+    // Seeds that cannot be requested, such as FTP seeds, are reported and skipped.
+    pub fn new(torrents: &[Torrent], timeout: Duration) -> WebSeeds {
+        let agent = build_agent(timeout);
+        let mut file_urls = Vec::with_capacity(torrents.len());
+        let mut first_file_ids = Vec::with_capacity(torrents.len());
+        let mut next_file_id = 0;
+
+        for torrent in torrents {
+            let seeds = torrent.url_list.iter()
+                .filter(|seed| match validate_url(&agent, seed) {
+                    Ok(_) => true,
+                    Err(err) => {
+                        eprintln!("Skipping web seed {:?}: {}", seed, err);
+                        false
+                    }
+                })
+                .map(|seed| web_seed_file_urls(seed, torrent))
+                .collect();
+
+            file_urls.push(seeds);
+            first_file_ids.push(next_file_id);
+            next_file_id += torrent.info.files.as_ref().map_or(1, |files| files.len());
+        }
+
+        WebSeeds { file_urls, first_file_ids, agent }
+    }
+}
+
+impl PieceFetcher for WebSeeds {
+    fn description(&self) -> &str {
+        "web seeds"
+    }
+
+    fn can_fetch(&self, torrent_id: usize) -> bool {
+        !self.file_urls[torrent_id].is_empty()
+    }
+
+    // Requests the ranges of the piece from each web seed of its torrent in turn, until the bytes of a seed match the
+    // piece hash. Returns None when no seed returned matching bytes, after reporting the problem with every seed.
+    fn fetch_piece(&mut self, piece: &TorrentPieceEntry, torrent_files: &[TorrentFileEntry]) -> Option<Vec<u8>> {
+        let first_file_id = self.first_file_ids[piece.torrent_id];
+
+        'seeds: for urls in self.file_urls[piece.torrent_id].iter() {
+            let mut bytes = Vec::with_capacity(piece.length as usize);
+
+            for piece_file in piece.files.iter() {
+                if torrent_files[piece_file.file_id].padding {
+                    bytes.resize(bytes.len() + piece_file.read_length as usize, 0);
+                    continue;
+                }
+
+                let url = &urls[piece_file.file_id - first_file_id];
+                match get_range(&self.agent, url, piece_file.read_start_position, piece_file.read_length) {
+                    Ok(range) => bytes.extend_from_slice(&range),
+                    Err(err) => {
+                        eprintln!("Unable to fetch piece {} from web seed {:?}: {}", piece.position, url, err);
+                        continue 'seeds;
+                    }
+                }
+            }
+
+            if Sha1::digest(&bytes).as_slice() == piece.hash.as_slice() {
+                return Some(bytes);
+            }
+
+            eprintln!("Web seed {:?} returned data for piece {} that does not match the piece hash.", urls[0], piece.position);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{test_support::{build_discovery, build_torrent, serve_directory, TempDir}, torrent::{TorrentFile, TorrentInfo}};
+
+    use super::*;
+
+    fn torrent(files: Option<Vec<TorrentFile>>) -> Torrent {
+        Torrent {
+            info: TorrentInfo {
+                name: "My Album".to_string(),
+                length: if files.is_none() { Some(1) } else { None },
+                attr: None,
+                files,
+                piece_length: 16384,
+                pieces: vec![vec![0; 20]]
+            },
+            url_list: Vec::new(),
+            info_hash: vec![0; 20]
+        }
+    }
+
+    #[test]
+    fn web_seed_file_urls_should_follow_bep_19() {
+        let single = torrent(None);
+        assert_eq!(vec!["http://seed/files/My%20Album"], web_seed_file_urls("http://seed/files/", &single));
+        assert_eq!(vec!["http://seed/a.bin"], web_seed_file_urls("http://seed/a.bin", &single));
+
+        let multiple = torrent(Some(vec![
+            TorrentFile { length: 1, path: vec!["CD 1".to_string(), "01 #1.flac".to_string()], attr: None },
+            TorrentFile { length: 1, path: vec!["ü.txt".to_string()], attr: None }
+        ]));
+
+        assert_eq!(
+            vec!["http://seed/My%20Album/CD%201/01%20%231.flac", "http://seed/My%20Album/%C3%BC.txt"],
+            web_seed_file_urls("http://seed", &multiple)
+        );
+    }
+
+    // Writes the files of a multi-file torrent where a web seed expects them, and serves them.
+    fn serve_seed(directory: &Path, torrent: &Torrent, files: &[(&str, &[u8])]) -> String {
+        let torrent_directory = directory.join(&torrent.info.name);
+        std::fs::create_dir_all(&torrent_directory).unwrap();
+
+        for (name, bytes) in files {
+            std::fs::write(torrent_directory.join(name), bytes).unwrap();
+        }
+
+        serve_directory(directory)
+    }
+
+    #[test]
+    fn fetch_piece_should_try_every_seed_until_the_piece_hash_matches() {
+        let directory = TempDir::new("web_seed_fetch");
+        let files: [(&str, &[u8]); 2] = [("first.bin", b"abcdef"), ("second.bin", b"ghijkl")];
+        let mut torrent = build_torrent(&files, 8);
+
+        let corrupt_seed = serve_seed(&directory.join("corrupt"), &torrent, &[("first.bin", b"abcdef"), ("second.bin", b"gXijkl")]);
+        let missing_seed = serve_seed(&directory.join("missing"), &torrent, &[("first.bin", b"abcdef")]);
+        let seed = serve_seed(&directory.join("seed"), &torrent, &files);
+
+        torrent.url_list = vec!["ftp://seed/".to_string(), corrupt_seed.clone(), missing_seed, seed];
+        let discovery = build_discovery(std::slice::from_ref(&torrent), &directory.join("export"), Vec::new());
+
+        let mut web_seeds = WebSeeds::new(std::slice::from_ref(&torrent), Duration::from_secs(5));
+        assert!(web_seeds.can_fetch(0));
+        assert_eq!(3, web_seeds.file_urls[0].len());
+
+        // The first piece spans both files, the second piece is the end of the second file.
+        let first = web_seeds.fetch_piece(&discovery.torrent_pieces[0], &discovery.torrent_files);
+        let second = web_seeds.fetch_piece(&discovery.torrent_pieces[1], &discovery.torrent_files);
+        assert_eq!(Some(b"abcdefgh".to_vec()), first);
+        assert_eq!(Some(b"ijkl".to_vec()), second);
+
+        torrent.url_list = vec![corrupt_seed];
+        let mut web_seeds = WebSeeds::new(std::slice::from_ref(&torrent), Duration::from_secs(5));
+        assert_eq!(None, web_seeds.fetch_piece(&discovery.torrent_pieces[0], &discovery.torrent_files));
+    }
+}
\ No newline at end of file
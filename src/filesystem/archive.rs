@@ -0,0 +1,510 @@
+use std::{ffi::OsStr, fs::File, io::{Error, ErrorKind, Result}, os::unix::{ffi::OsStrExt, fs::FileExt}, path::{Component, Path, PathBuf}};
+
+const TAR_BLOCK_LENGTH: u64 = 512;
+
+const ZIP_LOCAL_HEADER_SIGNATURE: &[u8] = b"PK\x03\x04";
+const ZIP_CENTRAL_HEADER_SIGNATURE: &[u8] = b"PK\x01\x02";
+const ZIP_END_SIGNATURE: &[u8] = b"PK\x05\x06";
+const ZIP64_END_LOCATOR_SIGNATURE: &[u8] = b"PK\x06\x07";
+const ZIP64_END_SIGNATURE: &[u8] = b"PK\x06\x06";
+
+// Length of the end of central directory record without its comment, which can be up to 65535 bytes long.
+const ZIP_END_LENGTH: u64 = 22;
+
+// A file stored uncompressed within an archive, so its bytes can be read directly from the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveMember {
+    // Path of the archive joined with the name of the member, such as /data/old.tar/album/track.flac.
+    pub path: PathBuf,
+    pub data_offset: u64,
+    pub length: u64
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("tar") || extension.eq_ignore_ascii_case("zip"))
+}
+
+// Lists the regular files of a tar archive, or the stored entries of a zip archive. Compressed and encrypted zip
+// entries cannot be read at an offset, so they are skipped.
+pub fn list_members(archive: &Path) -> Result<Vec<ArchiveMember>> {
+    let file = File::open(archive)?;
+    let is_zip = archive.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+
+    let result = if is_zip {
+        list_zip_members(&file, archive)
+    } else {
+        list_tar_members(&file, archive)
+    };
+
+    result.map_err(|err| Error::new(err.kind(), format!("Unable to list members of archive {:#?}: {}", archive, err)))
+}
+
+/**
+ * A tar archive is a sequence of 512 byte headers, each followed by the data of the entry padded to a multiple of
+ * 512 bytes, and ends with a block of zeros. Long names are either stored in a GNU long name entry (type L) or in a
+ * pax extended header (type x) preceding the entry, which can also hold sizes that do not fit the header.
+ */
+fn list_tar_members(file: &File, archive: &Path) -> Result<Vec<ArchiveMember>> {
+    let archive_length = file.metadata()?.len();
+    let mut members = Vec::new();
+
+    let mut position = 0;
+    let mut long_name = None;
+    let mut pax_path = None;
+    let mut pax_size = None;
+
+    while position + TAR_BLOCK_LENGTH <= archive_length {
+        let mut header = [0; TAR_BLOCK_LENGTH as usize];
+        file.read_exact_at(&mut header, position)?;
+
+        if header.iter().all(|byte| *byte == 0) {
+            break;
+        }
+
+        if parse_tar_number(&header[148..156])? != tar_checksum(&header) {
+            return Err(invalid_data(format!("Invalid tar header checksum at position {}.", position)));
+        }
+
+        let data_offset = position + TAR_BLOCK_LENGTH;
+        let length = match pax_size.take() {
+            Some(length) => length,
+            None => parse_tar_number(&header[124..136])?
+        };
+
+        if data_offset.checked_add(length).is_none_or(|end| end > archive_length) {
+            return Err(invalid_data(format!("Tar entry at position {} extends past the end of the archive.", position)));
+        }
+
+        match header[156] {
+            b'L' => long_name = Some(trim_nul(&read_at(file, data_offset, length)?).to_vec()),
+            b'x' => {
+                for (key, value) in parse_pax_records(&read_at(file, data_offset, length)?) {
+                    match key {
+                        b"path" => pax_path = Some(value.to_vec()),
+                        b"size" => pax_size = std::str::from_utf8(value).ok().and_then(|size| size.parse().ok()),
+                        _ => {}
+                    }
+                }
+            },
+            entry_type => {
+                let name = pax_path.take()
+                    .or(long_name.take())
+                    .unwrap_or_else(|| tar_header_name(&header));
+
+                // Regular files, including contiguous files. Directories, links and devices have no data to read.
+                if matches!(entry_type, 0 | b'0' | b'7') && let Some(path) = member_path(archive, &name) {
+                    members.push(ArchiveMember { path, data_offset, length });
+                }
+            }
+        }
+
+        position = length.div_ceil(TAR_BLOCK_LENGTH)
+            .checked_mul(TAR_BLOCK_LENGTH)
+            .and_then(|padded_length| data_offset.checked_add(padded_length))
+            .ok_or_else(|| invalid_data(format!("Tar entry at position {} extends past the end of the archive.", position)))?;
+    }
+
+    Ok(members)
+}
+
+fn tar_header_name(header: &[u8]) -> Vec<u8> {
+    let name = trim_nul(&header[0..100]);
+
+    // Only POSIX ustar headers store the start of long names in the prefix, GNU headers use that space for other fields.
+    let prefix = trim_nul(&header[345..500]);
+    if &header[257..263] != b"ustar\0" || prefix.is_empty() {
+        return name.to_vec();
+    }
+
+    [prefix, b"/", name].concat()
+}
+
+// Sum of the header bytes, where the checksum field itself counts as spaces.
+fn tar_checksum(header: &[u8]) -> u64 {
+    header.iter()
+        .enumerate()
+        .map(|(index, byte)| if (148..156).contains(&index) { b' ' as u64 } else { *byte as u64 })
+        .sum()
+}
+
+// Numbers are octal text, or big-endian binary when the high bit of the first byte is set.
+fn parse_tar_number(field: &[u8]) -> Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..].iter().fold((field[0] & 0x7f) as u64, |value, byte| (value << 8) | *byte as u64));
+    }
+
+    let text = std::str::from_utf8(field).unwrap_or_default().trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+
+    u64::from_str_radix(text, 8).map_err(|_| invalid_data(format!("Invalid tar number {:?}.", text)))
+}
+
+// Records are written as "<length> <key>=<value>\n", where the length includes the whole record.
+fn parse_pax_records(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut records = Vec::new();
+    let mut remaining = data;
+
+    while let Some(space) = remaining.iter().position(|byte| *byte == b' ') {
+        let length = std::str::from_utf8(&remaining[..space]).ok().and_then(|length| length.parse::<usize>().ok());
+        let Some(length) = length.filter(|length| *length > space + 1 && *length <= remaining.len()) else {
+            break;
+        };
+
+        let record = &remaining[space + 1..length - 1];
+        if let Some(equals) = record.iter().position(|byte| *byte == b'=') {
+            records.push((&record[..equals], &record[equals + 1..]));
+        }
+
+        remaining = &remaining[length..];
+    }
+
+    records
+}
+
+/**
+ * A zip archive is read from its central directory, found through the end of central directory record at the end of
+ * the archive. Archives with more than 65535 entries or larger than 4 GiB store the location of the central directory
+ * in a zip64 end record instead, and the sizes and offsets of large entries in a zip64 extra field. The data of an
+ * entry starts after its local header, whose name and extra field can differ in length from the central directory.
+ */
+fn list_zip_members(file: &File, archive: &Path) -> Result<Vec<ArchiveMember>> {
+    let archive_length = file.metadata()?.len();
+    let tail_length = std::cmp::min(archive_length, ZIP_END_LENGTH + u16::MAX as u64);
+    let tail_position = archive_length - tail_length;
+    let tail = read_at(file, tail_position, tail_length)?;
+
+    let end = (0..tail.len().saturating_sub(ZIP_END_LENGTH as usize - 1))
+        .rev()
+        .find(|index| tail[*index..].starts_with(ZIP_END_SIGNATURE))
+        .ok_or_else(|| invalid_data("No end of central directory record was found.".to_string()))?;
+
+    let mut entries = read_u16(&tail, end + 10) as u64;
+    let mut directory_length = read_u32(&tail, end + 12) as u64;
+    let mut directory_position = read_u32(&tail, end + 16) as u64;
+
+    if entries == u16::MAX as u64 || directory_length == u32::MAX as u64 || directory_position == u32::MAX as u64 {
+        let locator_position = (tail_position + end as u64).checked_sub(20)
+            .ok_or_else(|| invalid_data("No zip64 end of central directory locator was found.".to_string()))?;
+
+        let locator = read_at(file, locator_position, 20)?;
+        if !locator.starts_with(ZIP64_END_LOCATOR_SIGNATURE) {
+            return Err(invalid_data("No zip64 end of central directory locator was found.".to_string()));
+        }
+
+        let zip64_end = read_at(file, read_u64(&locator, 8), 56)?;
+        if !zip64_end.starts_with(ZIP64_END_SIGNATURE) {
+            return Err(invalid_data("Invalid zip64 end of central directory record.".to_string()));
+        }
+
+        entries = read_u64(&zip64_end, 32);
+        directory_length = read_u64(&zip64_end, 40);
+        directory_position = read_u64(&zip64_end, 48);
+    }
+
+    if directory_position.checked_add(directory_length).is_none_or(|end| end > archive_length) {
+        return Err(invalid_data("Central directory extends past the end of the archive.".to_string()));
+    }
+
+    let directory = read_at(file, directory_position, directory_length)?;
+    let mut members = Vec::new();
+    let mut position = 0;
+
+    for _ in 0..entries {
+        if position + 46 > directory.len() || !directory[position..].starts_with(ZIP_CENTRAL_HEADER_SIGNATURE) {
+            return Err(invalid_data(format!("Invalid central directory entry at position {}.", directory_position + position as u64)));
+        }
+
+        let flags = read_u16(&directory, position + 8);
+        let method = read_u16(&directory, position + 10);
+        let compressed_length = read_u32(&directory, position + 20) as u64;
+        let mut length = read_u32(&directory, position + 24) as u64;
+        let name_length = read_u16(&directory, position + 28) as usize;
+        let extra_length = read_u16(&directory, position + 30) as usize;
+        let comment_length = read_u16(&directory, position + 32) as usize;
+        let mut header_position = read_u32(&directory, position + 42) as u64;
+
+        let name_start = position + 46;
+        let extra_start = name_start + name_length;
+        let next_position = extra_start + extra_length + comment_length;
+        if next_position > directory.len() {
+            return Err(invalid_data(format!("Invalid central directory entry at position {}.", directory_position + position as u64)));
+        }
+
+        let name = &directory[name_start..extra_start];
+        apply_zip64_extra(&directory[extra_start..extra_start + extra_length], &mut length, compressed_length, &mut header_position);
+        position = next_position;
+
+        // Only stored entries that are not encrypted can be read as they are.
+        if method != 0 || flags & 1 != 0 || name.ends_with(b"/") {
+            continue;
+        }
+
+        let local_header = read_at(file, header_position, 30)?;
+        if !local_header.starts_with(ZIP_LOCAL_HEADER_SIGNATURE) {
+            return Err(invalid_data(format!("Invalid local header at position {}.", header_position)));
+        }
+
+        // The local header was read in full, so the data offset cannot overflow.
+        let data_offset = header_position + 30 + read_u16(&local_header, 26) as u64 + read_u16(&local_header, 28) as u64;
+        if data_offset.checked_add(length).is_none_or(|end| end > archive_length) {
+            return Err(invalid_data(format!("Zip entry at position {} extends past the end of the archive.", header_position)));
+        }
+
+        if let Some(path) = member_path(archive, name) {
+            members.push(ArchiveMember { path, data_offset, length });
+        }
+    }
+
+    Ok(members)
+}
+
+// The zip64 extra field only holds the values that did not fit the central directory entry, in a fixed order. A value
+// is only present when its field in the central directory entry is 0xFFFFFFFF.
+fn apply_zip64_extra(extra: &[u8], length: &mut u64, compressed_length: u64, header_position: &mut u64) {
+    let mut position = 0;
+
+    while position + 4 <= extra.len() {
+        let id = read_u16(extra, position);
+        let size = read_u16(extra, position + 2) as usize;
+        let end = std::cmp::min(position + 4 + size, extra.len());
+
+        if id == 1 {
+            let mut values = extra[position + 4..end].chunks_exact(8).map(|value| read_u64(value, 0));
+
+            let uncompressed_length = if *length == u32::MAX as u64 { values.next() } else { None };
+            if compressed_length == u32::MAX as u64 {
+                values.next();
+            }
+
+            let header = if *header_position == u32::MAX as u64 { values.next() } else { None };
+
+            *length = uncompressed_length.unwrap_or(*length);
+            *header_position = header.unwrap_or(*header_position);
+            return;
+        }
+
+        position = end;
+    }
+}
+
+// Only the normal components of the name are kept, so a member never points outside of its archive.
+fn member_path(archive: &Path, name: &[u8]) -> Option<PathBuf> {
+    let relative: PathBuf = Path::new(OsStr::from_bytes(name))
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+
+    if relative.as_os_str().is_empty() {
+        return None;
+    }
+
+    Some(archive.join(relative))
+}
+
+fn read_at(file: &File, position: u64, length: u64) -> Result<Vec<u8>> {
+    let mut buffer = vec![0; length as usize];
+    file.read_exact_at(&mut buffer, position)?;
+    Ok(buffer)
+}
+
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+fn read_u16(bytes: &[u8], position: usize) -> u16 {
+    u16::from_le_bytes([bytes[position], bytes[position + 1]])
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], position: usize) -> u64 {
+    u64::from_le_bytes(bytes[position..position + 8].try_into().unwrap())
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_support::TempDir;
+
+    use super::*;
+
+    fn tar_header(name: &[u8], entry_type: u8, length: u64) -> Vec<u8> {
+        let mut header = vec![0; TAR_BLOCK_LENGTH as usize];
+        header[..name.len()].copy_from_slice(name);
+        header[124..135].copy_from_slice(format!("{:011o}", length).as_bytes());
+        header[156] = entry_type;
+        header[257..263].copy_from_slice(b"ustar\0");
+
+        let checksum = format!("{:06o}\0 ", tar_checksum(&header));
+        header[148..156].copy_from_slice(checksum.as_bytes());
+        header
+    }
+
+    fn tar_entry(archive: &mut Vec<u8>, name: &[u8], entry_type: u8, data: &[u8]) {
+        archive.extend(tar_header(name, entry_type, data.len() as u64));
+        archive.extend(data);
+        archive.resize(archive.len().next_multiple_of(TAR_BLOCK_LENGTH as usize), 0);
+    }
+
+    fn zip_entry(archive: &mut Vec<u8>, directory: &mut Vec<u8>, name: &[u8], method: u16, data: &[u8]) {
+        let header_position = archive.len() as u32;
+        let length = data.len() as u32;
+
+        archive.extend(ZIP_LOCAL_HEADER_SIGNATURE);
+        archive.extend([20, 0, 0, 0]);
+        archive.extend(method.to_le_bytes());
+        archive.extend([0; 8]);
+        archive.extend(length.to_le_bytes());
+        archive.extend(length.to_le_bytes());
+        archive.extend((name.len() as u16).to_le_bytes());
+        archive.extend(4u16.to_le_bytes());
+        archive.extend(name);
+        archive.extend([0xff; 4]);
+        archive.extend(data);
+
+        directory.extend(ZIP_CENTRAL_HEADER_SIGNATURE);
+        directory.extend([20, 0, 20, 0, 0, 0]);
+        directory.extend(method.to_le_bytes());
+        directory.extend([0; 8]);
+        directory.extend(length.to_le_bytes());
+        directory.extend(length.to_le_bytes());
+        directory.extend((name.len() as u16).to_le_bytes());
+        directory.extend([0; 12]);
+        directory.extend(header_position.to_le_bytes());
+        directory.extend(name);
+    }
+
+    #[test]
+    fn list_members_should_read_tar_archives() {
+        let long_name = format!("{}/long.bin", "d".repeat(120));
+
+        let mut archive = Vec::new();
+        tar_entry(&mut archive, b"dir/", b'5', b"");
+        tar_entry(&mut archive, b"dir/a.bin", b'0', b"spam");
+        tar_entry(&mut archive, b"././@LongLink", b'L', format!("{}\0", long_name).as_bytes());
+        tar_entry(&mut archive, b"short", b'0', &[1; 600]);
+        tar_entry(&mut archive, b"pax", b'x', b"21 path=pax/eggs.bin\n");
+        tar_entry(&mut archive, b"ignored", b'0', b"eggs");
+        archive.extend([0; 1024]);
+
+        let directory = TempDir::new("archive_tar");
+        let path = directory.join("members.tar");
+        fs::write(&path, &archive).unwrap();
+        let members = list_members(&path).unwrap();
+
+        assert_eq!(vec![
+            ArchiveMember { path: path.join("dir/a.bin"), data_offset: 1024, length: 4 },
+            ArchiveMember { path: path.join(&long_name), data_offset: 3072, length: 600 },
+            ArchiveMember { path: path.join("pax/eggs.bin"), data_offset: 5632, length: 4 }
+        ], members);
+
+        assert_eq!(b"spam", &archive[1024..1028]);
+        assert_eq!(b"eggs", &archive[5632..5636]);
+    }
+
+    #[test]
+    fn list_members_should_read_stored_zip_entries() {
+        let mut archive = Vec::new();
+        let mut directory = Vec::new();
+        zip_entry(&mut archive, &mut directory, b"a/stored.bin", 0, b"spam");
+        zip_entry(&mut archive, &mut directory, b"a/deflated.bin", 8, b"eggs");
+        zip_entry(&mut archive, &mut directory, b"../escaped.bin", 0, b"ham");
+
+        let directory_position = archive.len() as u32;
+        archive.extend(&directory);
+        archive.extend(ZIP_END_SIGNATURE);
+        archive.extend([0, 0, 0, 0, 3, 0, 3, 0]);
+        archive.extend((directory.len() as u32).to_le_bytes());
+        archive.extend(directory_position.to_le_bytes());
+        archive.extend(0u16.to_le_bytes());
+
+        let directory = TempDir::new("archive_zip");
+        let path = directory.join("members.zip");
+        fs::write(&path, &archive).unwrap();
+        let members = list_members(&path).unwrap();
+
+        assert_eq!(2, members.len());
+        assert_eq!(path.join("a/stored.bin"), members[0].path);
+        assert_eq!(path.join("escaped.bin"), members[1].path);
+        assert_eq!(b"spam", &archive[members[0].data_offset as usize..][..4]);
+        assert_eq!(b"ham", &archive[members[1].data_offset as usize..][..3]);
+
+        fs::write(&path, b"not an archive").unwrap();
+        assert_eq!(ErrorKind::InvalidData, list_members(&path).unwrap_err().kind());
+    }
+
+    #[test]
+    fn list_members_should_read_zip64_header_offset() {
+        let mut archive = Vec::new();
+        let mut directory = Vec::new();
+        zip_entry(&mut archive, &mut directory, b"offset.bin", 0, b"spam");
+
+        // Only the header offset is moved to the zip64 extra field, so it is the first and only value.
+        directory[30..32].copy_from_slice(&12u16.to_le_bytes());
+        directory[42..46].copy_from_slice(&u32::MAX.to_le_bytes());
+        directory.extend([1, 0, 8, 0]);
+        directory.extend(0u64.to_le_bytes());
+
+        let directory_position = archive.len() as u32;
+        archive.extend(&directory);
+        archive.extend(ZIP_END_SIGNATURE);
+        archive.extend([0, 0, 0, 0, 1, 0, 1, 0]);
+        archive.extend((directory.len() as u32).to_le_bytes());
+        archive.extend(directory_position.to_le_bytes());
+        archive.extend(0u16.to_le_bytes());
+
+        let directory = TempDir::new("archive_zip64");
+        let path = directory.join("offset.zip");
+        fs::write(&path, &archive).unwrap();
+        let members = list_members(&path).unwrap();
+
+        assert_eq!(1, members.len());
+        assert_eq!(path.join("offset.bin"), members[0].path);
+        assert_eq!(4, members[0].length);
+        assert_eq!(b"spam", &archive[members[0].data_offset as usize..][..4]);
+    }
+
+    #[test]
+    fn apply_zip64_extra_should_only_read_values_of_full_fields() {
+        let mut extra = vec![1, 0, 24, 0];
+        extra.extend(5_000_000_000u64.to_le_bytes());
+        extra.extend(5_000_000_000u64.to_le_bytes());
+        extra.extend(6_000_000_000u64.to_le_bytes());
+
+        let mut length = u32::MAX as u64;
+        let mut header_position = u32::MAX as u64;
+        apply_zip64_extra(&extra, &mut length, u32::MAX as u64, &mut header_position);
+        assert_eq!((5_000_000_000, 6_000_000_000), (length, header_position));
+
+        let mut length = 4;
+        let mut header_position = u32::MAX as u64;
+        apply_zip64_extra(&extra[..12], &mut length, 4, &mut header_position);
+        assert_eq!((4, 5_000_000_000), (length, header_position));
+    }
+
+    #[test]
+    fn list_members_should_reject_overflowing_tar_lengths() {
+        let mut archive = tar_header(b"huge.bin", b'0', 0);
+        archive[124..136].copy_from_slice(&[0x80, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        let checksum = format!("{:06o}\0 ", tar_checksum(&archive));
+        archive[148..156].copy_from_slice(checksum.as_bytes());
+        archive.extend([0; 1024]);
+
+        let directory = TempDir::new("archive_overflow");
+        let path = directory.join("huge.tar");
+        fs::write(&path, &archive).unwrap();
+
+        assert_eq!(ErrorKind::InvalidData, list_members(&path).unwrap_err().kind());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,210 @@
+use std::{fs::File, io::Result, os::unix::fs::FileExt, path::{Path, PathBuf}};
+
+use crate::filesystem::handle_cache::{check_source_range, read_range, read_range_with};
+
+// Identifies the bytes behind a source, so the same bytes reached through several paths, such as hard links, are only
+// searched once.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SourceIdentity {
+    // Bytes of a local file starting at an offset, which is only set for archive members.
+    File { device_node: u64, index_node: u64, data_offset: u64 },
+    // Any other source, identified by a unique name such as a URL.
+    Named(String)
+}
+
+impl SourceIdentity {
+    // Device holding the bytes, which limits the number of concurrent reads.
+    pub fn device_node(&self) -> Option<u64> {
+        match self {
+            SourceIdentity::File { device_node, .. } => Some(*device_node),
+            SourceIdentity::Named(_) => None
+        }
+    }
+}
+
+// Local file holding the bytes of a source. Sources sharing a file share its handle id, so the file is only opened once.
+pub struct DataLocation<'a> {
+    pub handle_id: usize,
+    pub path: &'a Path,
+    pub data_offset: u64
+}
+
+// Bytes that can be searched as a candidate for torrent files of the same length.
+pub trait DataSource: Send + Sync {
+    fn length(&self) -> u64;
+
+    fn identity(&self) -> &SourceIdentity;
+
+    // Sources backed by a local file are read through the file handle cache, which keeps handles open between reads
+    // and supports read-ahead, memory mappings and hole detection.
+    fn location(&self) -> Option<DataLocation<'_>> {
+        None
+    }
+
+    // Positional read, which may return fewer bytes than requested, and returns 0 at the end of the source.
+    fn read_at(&self, buffer: &mut [u8], position: u64) -> Result<usize>;
+}
+
+// Appends the requested range of the source to the output, opening its local file once when it has one.
+pub fn read_source(source: &dyn DataSource, read_length: u64, read_start_position: u64, output: &mut Vec<u8>) -> Result<()> {
+    check_source_range(source, read_length, read_start_position)?;
+    match source.location() {
+        Some(location) => {
+            let file = File::open(location.path)?;
+            read_range(&file, read_length, location.data_offset + read_start_position, output)
+        },
+        None => read_range_with(|buffer, position| source.read_at(buffer, position), read_length, read_start_position, output)
+    }
+}
+
+pub struct LocalFile {
+    handle_id: usize,
+    path: PathBuf,
+    length: u64,
+    identity: SourceIdentity
+}
+
+impl LocalFile {
+    pub fn new(handle_id: usize, path: PathBuf, length: u64, device_node: u64, index_node: u64) -> LocalFile {
+        LocalFile {
+            handle_id,
+            path,
+            length,
+            identity: SourceIdentity::File { device_node, index_node, data_offset: 0 }
+        }
+    }
+}
+
+impl DataSource for LocalFile {
+    fn length(&self) -> u64 {
+        self.length
+    }
+
+    fn identity(&self) -> &SourceIdentity {
+        &self.identity
+    }
+
+    fn location(&self) -> Option<DataLocation<'_>> {
+        Some(DataLocation { handle_id: self.handle_id, path: &self.path, data_offset: 0 })
+    }
+
+    fn read_at(&self, buffer: &mut [u8], position: u64) -> Result<usize> {
+        File::open(&self.path)?.read_at(buffer, position)
+    }
+}
+
+// A file stored uncompressed within an archive, read from the archive starting at its data offset.
+pub struct ArchiveMemberSource {
+    archive_id: usize,
+    archive: PathBuf,
+    data_offset: u64,
+    length: u64,
+    identity: SourceIdentity
+}
+
+impl ArchiveMemberSource {
+    pub fn new(archive_id: usize, archive: PathBuf, data_offset: u64, length: u64, device_node: u64, index_node: u64) -> ArchiveMemberSource {
+        ArchiveMemberSource {
+            archive_id,
+            archive,
+            data_offset,
+            length,
+            identity: SourceIdentity::File { device_node, index_node, data_offset }
+        }
+    }
+}
+
+impl DataSource for ArchiveMemberSource {
+    fn length(&self) -> u64 {
+        self.length
+    }
+
+    fn identity(&self) -> &SourceIdentity {
+        &self.identity
+    }
+
+    fn location(&self) -> Option<DataLocation<'_>> {
+        Some(DataLocation { handle_id: self.archive_id, path: &self.archive, data_offset: self.data_offset })
+    }
+
+    fn read_at(&self, buffer: &mut [u8], position: u64) -> Result<usize> {
+        let remaining = self.length.saturating_sub(position);
+        let buffer_length = std::cmp::min(buffer.len() as u64, remaining) as usize;
+        File::open(&self.archive)?.read_at(&mut buffer[..buffer_length], self.data_offset + position)
+    }
+}
+
+// Bytes held in memory, for test fixtures and data that was fetched from elsewhere.
+pub struct MemorySource {
+    bytes: Vec<u8>,
+    identity: SourceIdentity
+}
+
+impl MemorySource {
+    pub fn new(name: &str, bytes: Vec<u8>) -> MemorySource {
+        MemorySource { bytes, identity: SourceIdentity::Named(name.to_string()) }
+    }
+}
+
+impl DataSource for MemorySource {
+    fn length(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    fn identity(&self) -> &SourceIdentity {
+        &self.identity
+    }
+
+    fn read_at(&self, buffer: &mut [u8], position: u64) -> Result<usize> {
+        let start = std::cmp::min(position, self.bytes.len() as u64) as usize;
+        let length = std::cmp::min(buffer.len(), self.bytes.len() - start);
+        buffer[..length].copy_from_slice(&self.bytes[start..start + length]);
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use crate::test_support::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn read_source_should_read_memory_sources() {
+        let source = MemorySource::new("memory", b"0123456789".to_vec());
+
+        let mut output = b"ab".to_vec();
+        read_source(&source, 4, 3, &mut output).unwrap();
+        assert_eq!(b"ab3456".as_slice(), output.as_slice());
+
+        let result = read_source(&source, 4, 8, &mut output);
+        assert_eq!(ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+        assert_eq!(b"ab3456".as_slice(), output.as_slice());
+        assert_eq!(None, source.identity().device_node());
+    }
+
+    #[test]
+    fn read_source_should_read_archive_members_at_offset() {
+        let directory = TempDir::new("data_source");
+        let path = directory.join("archive");
+        std::fs::write(&path, b"header0123456789trailer").unwrap();
+
+        let source = ArchiveMemberSource::new(0, path.clone(), 6, 10, 1, 2);
+
+        let mut output = Vec::new();
+        read_source(&source, 4, 6, &mut output).unwrap();
+
+        let mut buffer = [0; 8];
+        let read = source.read_at(&mut buffer, 6).unwrap();
+
+        assert_eq!(b"6789".as_slice(), output.as_slice());
+        assert_eq!(b"6789".as_slice(), &buffer[..read]);
+        assert_eq!(SourceIdentity::File { device_node: 1, index_node: 2, data_offset: 6 }, *source.identity());
+
+        let result = read_source(&source, 4, 8, &mut output);
+        assert_eq!(ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+        assert_eq!(b"6789".as_slice(), output.as_slice());
+    }
+}
\ No newline at end of file
@@ -7,6 +7,7 @@ pub trait ExportPathFormatter {
     fn format_single_file(torrent: &Torrent, export_root: &Path) -> PathBuf;
 }
 
+// Exports into <export root>/<info hash>/Data/<torrent name>.
 pub struct DefaultExportPathFormatter {}
 impl ExportPathFormatter for DefaultExportPathFormatter {
     fn format_multiple_files(torrent_file: &TorrentFile, torrent: &Torrent, export_root: &Path) -> PathBuf {
@@ -32,3 +33,63 @@ impl ExportPathFormatter for DefaultExportPathFormatter {
     }
 }
 
+// Same layout as a torrent client saving into the export root, <export root>/<torrent name>.
+pub struct ClientExportPathFormatter {}
+impl ExportPathFormatter for ClientExportPathFormatter {
+    fn format_multiple_files(torrent_file: &TorrentFile, torrent: &Torrent, export_root: &Path) -> PathBuf {
+        let torrent_name = Path::new(&torrent.info.name);
+
+        [export_root, torrent_name, &torrent_file.path.iter().collect::<PathBuf>()]
+            .iter()
+            .collect()
+    }
+
+    fn format_single_file(torrent: &Torrent, export_root: &Path) -> PathBuf {
+        export_root.join(&torrent.info.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::torrent::TorrentInfo;
+
+    use super::*;
+
+    fn torrent(files: Option<Vec<TorrentFile>>) -> Torrent {
+        Torrent {
+            info: TorrentInfo {
+                name: "Example".to_string(),
+                length: files.is_none().then_some(4),
+                attr: None,
+                files,
+                piece_length: 4,
+                pieces: vec![vec![0; 20]]
+            },
+            url_list: Vec::new(),
+            info_hash: vec![0xAB; 20]
+        }
+    }
+
+    #[test]
+    fn client_formatter_should_use_torrent_name_under_export_root() {
+        let file = TorrentFile { length: 4, path: vec!["Disc 1".to_string(), "track.flac".to_string()], attr: None };
+        let multiple = torrent(Some(vec![TorrentFile { length: 4, path: file.path.clone(), attr: None }]));
+        let single = torrent(None);
+
+        assert_eq!(
+            PathBuf::from("/data/Example/Disc 1/track.flac"),
+            ClientExportPathFormatter::format_multiple_files(&file, &multiple, Path::new("/data"))
+        );
+        assert_eq!(PathBuf::from("/data/Example"), ClientExportPathFormatter::format_single_file(&single, Path::new("/data")));
+    }
+
+    #[test]
+    fn default_formatter_should_use_info_hash_directory() {
+        let single = torrent(None);
+
+        assert_eq!(
+            PathBuf::from(format!("/data/{}/Data/Example", "ab".repeat(20))),
+            DefaultExportPathFormatter::format_single_file(&single, Path::new("/data"))
+        );
+    }
+}
\ No newline at end of file
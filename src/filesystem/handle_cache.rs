@@ -0,0 +1,555 @@
+use std::{fs::File, io::{Error, ErrorKind}, path::Path};
+
+use hashlru::Cache;
+use memmap2::Mmap;
+
+use crate::filesystem::data_source::DataSource;
+
+// How candidates are read. Sequential marks every opened file for sequential access, the read-ahead asks the kernel
+// to start loading the bytes following each read before they are requested, and mmap allows ranges to be borrowed
+// from a memory mapping of the file instead of being copied.
+#[derive(Clone, Copy, Default)]
+pub struct ReadOptions {
+    pub sequential: bool,
+    pub read_ahead: u64,
+    pub mmap: bool
+}
+
+struct CachedHandle {
+    file: File,
+    // End of the last range passed to the kernel as read-ahead.
+    advised_until: u64,
+    map: Option<Mmap>,
+    // Mapping is not attempted again for files that could not be mapped, for example on some FUSE mounts.
+    map_failed: bool
+}
+
+impl CachedHandle {
+    fn new(file: File) -> CachedHandle {
+        CachedHandle {
+            file,
+            advised_until: 0,
+            map: None,
+            map_failed: false
+        }
+    }
+}
+
+// Bounded LRU of open candidate files, keyed by the interned path handle. Every worker owns its own
+// cache, so the handles are used without locking, and the least recently used handle is closed once
+// the cache is full. A capacity of 0 disables caching, every read then opens and closes the file.
+pub struct FileHandleCache {
+    handles: Cache<usize, CachedHandle>,
+    capacity: usize,
+    options: ReadOptions
+}
+
+impl FileHandleCache {
+    pub fn new(capacity: usize, options: ReadOptions) -> FileHandleCache {
+        FileHandleCache {
+            handles: Cache::new(capacity),
+            capacity,
+            options
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Mappings are kept with the cached handles, so they are only used when caching is enabled.
+    pub fn mapping_enabled(&self) -> bool {
+        self.options.mmap && self.capacity > 0
+    }
+
+    // Appends the requested range of the file to the output. The handle is dropped from the cache when the read
+    // fails, so a file that is replaced or restored during the run is opened again on the next read.
+    pub fn read_into(
+        &mut self,
+        path_id: usize,
+        path: &Path,
+        read_length: u64,
+        read_start_position: u64,
+        output: &mut Vec<u8>
+    ) -> Result<(), Error> {
+        let read_end = read_start_position + read_length;
+
+        if self.capacity == 0 {
+            let file = self.open(path)?;
+            read_range(&file, read_length, read_start_position, output)?;
+
+            if self.options.read_ahead > 0 {
+                advise_will_need(&file, read_end, self.options.read_ahead);
+            }
+
+            return Ok(());
+        }
+
+        let read_ahead = self.options.read_ahead;
+        let handle = self.handle(path_id, path)?;
+
+        let result = read_range(&handle.file, read_length, read_start_position, output);
+        if result.is_err() {
+            self.handles.remove(&path_id);
+            return result;
+        }
+
+        // Only extend the read-ahead once half of the previous window has been read, or when the reads
+        // moved back before the previous window.
+        let behind = read_end + read_ahead < handle.advised_until;
+        if read_ahead > 0 && (behind || read_end + read_ahead / 2 > handle.advised_until) {
+            advise_will_need(&handle.file, read_end, read_ahead);
+            handle.advised_until = read_end + read_ahead;
+        }
+
+        Ok(())
+    }
+
+    // Borrows the requested range from a memory mapping of the file. Returns None when mapping is disabled or the file
+    // cannot be mapped, in which case the range should be read with read_into instead. The length of the file is checked
+    // before every access, so a file truncated earlier in the run is reported as an error. A file truncated while the
+    // borrowed range is still read faults on the mapping, which terminates the process, so files must not be truncated
+    // concurrently when mappings are enabled.
+    pub fn map_range(
+        &mut self,
+        path_id: usize,
+        path: &Path,
+        read_length: u64,
+        read_start_position: u64
+    ) -> Result<Option<&[u8]>, Error> {
+        if !self.mapping_enabled() {
+            return Ok(None);
+        }
+
+        let sequential = self.options.sequential;
+        let metadata = {
+            let handle = self.handle(path_id, path)?;
+            if handle.map_failed {
+                return Ok(None);
+            }
+
+            handle.file.metadata()
+        };
+
+        let file_length = match metadata {
+            Ok(metadata) => metadata.len(),
+            Err(err) => {
+                self.handles.remove(&path_id);
+                return Err(err);
+            }
+        };
+
+        let read_end = read_start_position + read_length;
+        if file_length < read_end {
+            self.handles.remove(&path_id);
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "Expected to read {} bytes at position {}, but only {} bytes were available.",
+                    read_length, read_start_position, file_length.saturating_sub(read_start_position)
+                )
+            ));
+        }
+
+        let handle = self.handles.get_mut(&path_id)
+            .expect("Handle should have just been inserted.");
+
+        // Map the file again if it has grown since it was mapped.
+        if handle.map.as_ref().is_none_or(|map| (map.len() as u64) < read_end) {
+            handle.map = None;
+
+            // Safety: the mapping is only read, and the length of the file is checked before every access. Truncation
+            // while the range is read is not supported, see above.
+            match unsafe { Mmap::map(&handle.file) } {
+                Ok(map) => {
+                    #[cfg(unix)]
+                    if sequential {
+                        let _ = map.advise(memmap2::Advice::Sequential);
+                    }
+
+                    handle.map = Some(map);
+                },
+                Err(_) => {
+                    handle.map_failed = true;
+                    return Ok(None);
+                }
+            }
+        }
+
+        let map = handle.map.as_ref()
+            .expect("Mapping should have just been created.");
+
+        Ok(Some(&map[read_start_position as usize..read_end as usize]))
+    }
+
+    // Returns true if the range of the file is entirely a hole, so reading it would only return zeros. Any error, or a
+    // filesystem without SEEK_DATA support, reports the range as data so that the read itself reports the problem.
+    pub fn is_hole(&mut self, path_id: usize, path: &Path, read_length: u64, read_start_position: u64) -> bool {
+        if self.capacity == 0 {
+            return match self.open(path) {
+                Ok(file) => range_is_hole(&file, read_length, read_start_position),
+                Err(_) => false
+            };
+        }
+
+        match self.handle(path_id, path) {
+            Ok(handle) => range_is_hole(&handle.file, read_length, read_start_position),
+            Err(_) => false
+        }
+    }
+
+    // Reads a range of a source through the handle of its local file, or from the source itself when it has none.
+    pub fn read_source(
+        &mut self,
+        source: &dyn DataSource,
+        read_length: u64,
+        read_start_position: u64,
+        output: &mut Vec<u8>
+    ) -> Result<(), Error> {
+        check_source_range(source, read_length, read_start_position)?;
+        match source.location() {
+            Some(location) => self.read_into(
+                location.handle_id, location.path, read_length, location.data_offset + read_start_position, output
+            ),
+            None => read_range_with(|buffer, offset| source.read_at(buffer, offset), read_length, read_start_position, output)
+        }
+    }
+
+    // Sources without a local file cannot be mapped, and are read with read_source instead.
+    pub fn map_source(&mut self, source: &dyn DataSource, read_length: u64, read_start_position: u64) -> Result<Option<&[u8]>, Error> {
+        check_source_range(source, read_length, read_start_position)?;
+        match source.location() {
+            Some(location) => self.map_range(location.handle_id, location.path, read_length, location.data_offset + read_start_position),
+            None => Ok(None)
+        }
+    }
+
+    pub fn source_is_hole(&mut self, source: &dyn DataSource, read_length: u64, read_start_position: u64) -> bool {
+        if check_source_range(source, read_length, read_start_position).is_err() {
+            return false;
+        }
+
+        match source.location() {
+            Some(location) => self.is_hole(location.handle_id, location.path, read_length, location.data_offset + read_start_position),
+            None => false
+        }
+    }
+
+    fn handle(&mut self, path_id: usize, path: &Path) -> Result<&mut CachedHandle, Error> {
+        if !self.handles.contains_key(&path_id) {
+            let file = self.open(path)?;
+            self.handles.push(path_id, CachedHandle::new(file));
+        }
+
+        Ok(self.handles.get_mut(&path_id).expect("Handle should have just been inserted."))
+    }
+
+    fn open(&self, path: &Path) -> Result<File, Error> {
+        let file = File::open(path)?;
+        if self.options.sequential {
+            advise_sequential(&file);
+        }
+
+        Ok(file)
+    }
+}
+
+// Positional read of the requested range, appended to the output. The file offset is not used, so a single
+// handle can serve reads for any piece. A file that is shorter than expected, for example because it was
+// truncated during the run, is reported as an error and the output is left unchanged.
+pub fn read_range(handle: &File, read_length: u64, read_start_position: u64, output: &mut Vec<u8>) -> Result<(), Error> {
+    read_range_with(|buffer, offset| read_at(handle, buffer, offset), read_length, read_start_position, output)
+}
+
+// Sources can share their local file with other data, such as the next member of an archive, so reads through
+// their location are checked against the length of the source instead of the length of the file.
+pub fn check_source_range(source: &dyn DataSource, read_length: u64, read_start_position: u64) -> Result<(), Error> {
+    let length = source.length();
+    match read_start_position.checked_add(read_length) {
+        Some(read_end) if read_end <= length => Ok(()),
+        _ => Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!(
+                "Expected to read {} bytes at position {}, but only {} bytes were available.",
+                read_length, read_start_position, length.saturating_sub(read_start_position)
+            )
+        ))
+    }
+}
+
+// Same as read_range, for any positional read.
+pub fn read_range_with(
+    mut read_at: impl FnMut(&mut [u8], u64) -> Result<usize, Error>,
+    read_length: u64,
+    read_start_position: u64,
+    output: &mut Vec<u8>
+) -> Result<(), Error> {
+    let start = output.len();
+    let read_length_usize = read_length as usize;
+    output.resize(start + read_length_usize, 0);
+
+    let mut read = 0;
+    while read < read_length_usize {
+        match read_at(&mut output[start + read..], read_start_position + read as u64) {
+            Ok(0) => break,
+            Ok(count) => read += count,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => {
+                output.truncate(start);
+                return Err(err);
+            }
+        }
+    }
+
+    if read < read_length_usize {
+        output.truncate(start);
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("Expected to read {} bytes at position {}, but only {} bytes were available.", read_length, read_start_position, read)
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn read_at(handle: &File, buffer: &mut [u8], offset: u64) -> Result<usize, Error> {
+    std::os::unix::fs::FileExt::read_at(handle, buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_at(handle: &File, buffer: &mut [u8], offset: u64) -> Result<usize, Error> {
+    std::os::windows::fs::FileExt::seek_read(handle, buffer, offset)
+}
+
+#[cfg(target_os = "linux")]
+fn range_is_hole(handle: &File, read_length: u64, read_start_position: u64) -> bool {
+    use std::os::fd::AsRawFd;
+
+    let read_end = read_start_position + read_length;
+    match handle.metadata() {
+        Ok(metadata) if metadata.len() >= read_end => {},
+        _ => return false
+    }
+
+    // Finds the first data at or after the start of the range, failing with ENXIO when only a hole follows.
+    let data = unsafe { libc::lseek(handle.as_raw_fd(), read_start_position as libc::off_t, libc::SEEK_DATA) };
+    if data < 0 {
+        return Error::last_os_error().raw_os_error() == Some(libc::ENXIO);
+    }
+
+    data as u64 >= read_end
+}
+
+#[cfg(not(target_os = "linux"))]
+fn range_is_hole(_handle: &File, _read_length: u64, _read_start_position: u64) -> bool {
+    false
+}
+
+// The advice is only a hint, so failures are ignored.
+#[cfg(target_os = "linux")]
+fn advise_sequential(handle: &File) {
+    use std::os::fd::AsRawFd;
+    unsafe {
+        libc::posix_fadvise(handle.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn advise_will_need(handle: &File, offset: u64, length: u64) {
+    use std::os::fd::AsRawFd;
+    unsafe {
+        libc::posix_fadvise(handle.as_raw_fd(), offset as libc::off_t, length as libc::off_t, libc::POSIX_FADV_WILLNEED);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_sequential(_handle: &File) {}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_will_need(_handle: &File, _offset: u64, _length: u64) {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{filesystem::data_source::{ArchiveMemberSource, MemorySource}, test_support::TempDir};
+
+    use super::*;
+
+    fn create_file(directory: &TempDir, name: &str, contents: &[u8]) -> PathBuf {
+        let path = directory.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn read_range_should_append() {
+        let directory = TempDir::new("handle_cache_append");
+        let path = create_file(&directory, "append", b"0123456789");
+        let handle = File::open(&path).unwrap();
+
+        let mut output = b"ab".to_vec();
+        read_range(&handle, 4, 3, &mut output).unwrap();
+
+        assert_eq!(b"ab3456".as_slice(), output.as_slice());
+    }
+
+    #[test]
+    fn read_range_past_end_should_fail() {
+        let directory = TempDir::new("handle_cache_short");
+        let path = create_file(&directory, "short", b"0123456789");
+        let handle = File::open(&path).unwrap();
+
+        let mut output = b"ab".to_vec();
+        let result = read_range(&handle, 4, 8, &mut output);
+
+        assert_eq!(ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+        assert_eq!(b"ab".as_slice(), output.as_slice());
+    }
+
+    #[test]
+    fn cache_should_respect_capacity() {
+        let directory = TempDir::new("handle_cache_capacity");
+        let paths: Vec<PathBuf> = (0..3)
+            .map(|index| create_file(&directory, &format!("capacity_{}", index), b"0123456789"))
+            .collect();
+
+        let mut cache = FileHandleCache::new(2, ReadOptions::default());
+        let mut output = Vec::new();
+        for (path_id, path) in paths.iter().enumerate() {
+            cache.read_into(path_id, path, 2, path_id as u64, &mut output).unwrap();
+        }
+
+        assert_eq!(2, cache.len());
+        assert_eq!(b"011223".as_slice(), output.as_slice());
+    }
+
+    #[test]
+    fn cache_with_zero_capacity_should_not_keep_handles() {
+        let directory = TempDir::new("handle_cache_disabled");
+        let path = create_file(&directory, "disabled", b"0123456789");
+
+        let mut cache = FileHandleCache::new(0, ReadOptions::default());
+        let mut output = Vec::new();
+        cache.read_into(0, &path, 10, 0, &mut output).unwrap();
+
+        assert!(cache.is_empty());
+        assert_eq!(b"0123456789".as_slice(), output.as_slice());
+    }
+
+    #[test]
+    fn cache_with_read_hints_should_read() {
+        let directory = TempDir::new("handle_cache_hints");
+        let path = create_file(&directory, "hints", b"0123456789");
+
+        let mut cache = FileHandleCache::new(1, ReadOptions { sequential: true, read_ahead: 4, mmap: false });
+        let mut output = Vec::new();
+        cache.read_into(0, &path, 3, 0, &mut output).unwrap();
+        cache.read_into(0, &path, 3, 3, &mut output).unwrap();
+
+        assert_eq!(b"012345".as_slice(), output.as_slice());
+    }
+
+    #[test]
+    fn map_range_should_borrow_range() {
+        let directory = TempDir::new("handle_cache_map");
+        let path = create_file(&directory, "map", b"0123456789");
+
+        let mut cache = FileHandleCache::new(1, ReadOptions { sequential: false, read_ahead: 0, mmap: true });
+        let range = cache.map_range(0, &path, 4, 3).unwrap().map(|range| range.to_vec());
+
+        assert_eq!(Some(b"3456".to_vec()), range);
+    }
+
+    #[test]
+    fn map_range_after_truncate_should_fail() {
+        let directory = TempDir::new("handle_cache_map_truncate");
+        let path = create_file(&directory, "map_truncate", b"0123456789");
+
+        let mut cache = FileHandleCache::new(1, ReadOptions { sequential: false, read_ahead: 0, mmap: true });
+        assert!(cache.map_range(0, &path, 4, 6).unwrap().is_some());
+
+        File::options().write(true).open(&path).unwrap().set_len(8).unwrap();
+        let result = cache.map_range(0, &path, 4, 6);
+
+        assert_eq!(ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn sources_should_be_read_through_their_location() {
+        let directory = TempDir::new("handle_cache_source");
+        let path = create_file(&directory, "source", b"header0123456789");
+        let member = ArchiveMemberSource::new(0, path.clone(), 6, 10, 1, 2);
+        let memory = MemorySource::new("memory", b"0123456789".to_vec());
+
+        let mut cache = FileHandleCache::new(1, ReadOptions { sequential: false, read_ahead: 0, mmap: true });
+        let mut output = Vec::new();
+        cache.read_source(&member, 2, 1, &mut output).unwrap();
+        cache.read_source(&memory, 2, 3, &mut output).unwrap();
+
+        let mapped = cache.map_source(&member, 4, 6).unwrap().map(|range| range.to_vec());
+        let memory_mapped = cache.map_source(&memory, 4, 6).unwrap().is_none();
+
+        assert_eq!(b"1234".as_slice(), output.as_slice());
+        assert_eq!(Some(b"6789".to_vec()), mapped);
+        assert!(memory_mapped);
+        assert_eq!(1, cache.len());
+    }
+
+    #[test]
+    fn sources_should_not_be_read_past_their_length() {
+        let directory = TempDir::new("handle_cache_source_length");
+        let path = create_file(&directory, "source", b"header0123456789trailer");
+        let member = ArchiveMemberSource::new(0, path.clone(), 6, 10, 1, 2);
+
+        let mut cache = FileHandleCache::new(1, ReadOptions { sequential: false, read_ahead: 0, mmap: true });
+        let mut output = b"ab".to_vec();
+        let read_result = cache.read_source(&member, 4, 8, &mut output);
+        let map_result = cache.map_source(&member, 4, 8).map(|range| range.map(|range| range.to_vec()));
+
+        assert_eq!(ErrorKind::UnexpectedEof, read_result.unwrap_err().kind());
+        assert_eq!(ErrorKind::UnexpectedEof, map_result.unwrap_err().kind());
+        assert_eq!(b"ab".as_slice(), output.as_slice());
+        assert!(!cache.source_is_hole(&member, 4, 8));
+    }
+
+    #[test]
+    fn map_range_without_mmap_should_fall_back() {
+        let directory = TempDir::new("handle_cache_map_disabled");
+        let path = create_file(&directory, "map_disabled", b"0123456789");
+
+        let mut cache = FileHandleCache::new(1, ReadOptions::default());
+        let range = cache.map_range(0, &path, 4, 3).unwrap().is_none();
+
+        assert!(range);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn is_hole_should_detect_sparse_range() {
+        let directory = TempDir::new("handle_cache_sparse");
+        let path = create_file(&directory, "sparse", b"");
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_len(4 * 1024 * 1024).unwrap();
+        std::os::unix::fs::FileExt::write_all_at(&file, &[1; 4096], 2 * 1024 * 1024).unwrap();
+
+        let mut cache = FileHandleCache::new(1, ReadOptions::default());
+        let hole = cache.is_hole(0, &path, 65536, 0);
+        let data = cache.is_hole(0, &path, 65536, 2 * 1024 * 1024);
+        let past_end = cache.is_hole(0, &path, 65536, 4 * 1024 * 1024);
+
+        assert!(hole);
+        assert!(!data);
+        assert!(!past_end);
+    }
+}
\ No newline at end of file
@@ -1,7 +1,19 @@
 mod path_cache;
 mod path_interner;
 mod export_path_formatter;
+mod handle_cache;
+mod scan_filter;
+mod walker;
+mod scan_index;
+mod archive;
+mod data_source;
 
 pub use path_interner::*;
 pub use path_cache::*;
-pub use export_path_formatter::*;
\ No newline at end of file
+pub use export_path_formatter::*;
+pub use handle_cache::*;
+pub use scan_filter::*;
+pub use walker::*;
+pub use scan_index::*;
+pub use archive::*;
+pub use data_source::*;
\ No newline at end of file
@@ -1,26 +1,33 @@
-use std::{collections::HashMap, fs::{Metadata, OpenOptions}, os::unix::fs::MetadataExt, path::{Path, PathBuf}};
+use std::{collections::HashMap, fs::{self, Metadata}, os::unix::fs::MetadataExt, path::{Path, PathBuf}};
 
-use walkdir::WalkDir;
-
-use crate::filesystem::path_interner::PathInterner;
+use crate::filesystem::{
+    archive::{is_archive, list_members},
+    data_source::{ArchiveMemberSource, DataSource, LocalFile, SourceIdentity},
+    path_interner::PathInterner,
+    scan_filter::ScanFilter,
+    scan_index::ScanIndex,
+    walker::{walk, WalkProgress, WalkedFile}
+};
 
 pub struct PathCacheEntry {
-    file_length: u64,
-    device_node: u64,
-    index_node: u64
+    source: Box<dyn DataSource>
 }
 
 impl PathCacheEntry {
+    pub fn new(source: Box<dyn DataSource>) -> PathCacheEntry {
+        PathCacheEntry { source }
+    }
+
     pub fn length(&self) -> u64 {
-        self.file_length
+        self.source.length()
     }
 
-    pub fn device_node(&self) -> u64 {
-        self.device_node
+    pub fn identity(&self) -> &SourceIdentity {
+        self.source.identity()
     }
-    
-    pub fn index_node(&self) -> u64 {
-        self.index_node
+
+    pub fn source(&self) -> &dyn DataSource {
+        self.source.as_ref()
     }
 }
 
@@ -32,15 +39,14 @@ impl PartialOrd for PathCacheEntry {
 
 impl Ord for PathCacheEntry {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.device_node.cmp(&other.device_node)
-            .then(self.index_node.cmp(&other.index_node))
-            .then(self.file_length.cmp(&other.file_length))
+        self.identity().cmp(other.identity())
+            .then(self.length().cmp(&other.length()))
     }
 }
 
 impl PartialEq for PathCacheEntry {
     fn eq(&self, other: &Self) -> bool {
-        self.file_length == other.file_length && self.device_node == other.device_node && self.index_node == other.index_node
+        self.identity() == other.identity() && self.length() == other.length()
     }
 }
 
@@ -48,135 +54,182 @@ impl Eq for PathCacheEntry {}
 
 pub struct PathCache {
     entries: HashMap<usize, PathCacheEntry>,
-    visited_directories: Vec<PathBuf>
+    visited_directories: Vec<PathBuf>,
+    filter: ScanFilter,
+    threads: usize,
+    index: Option<ScanIndex>
 }
  
 impl PathCache {
     pub fn new() -> PathCache {
+        PathCache::with_filter(ScanFilter::default(), 1)
+    }
+
+    // The filter applies to the files found by add_directory, files added directly are always indexed.
+    // Directories are read by the given number of threads.
+    pub fn with_filter(filter: ScanFilter, threads: usize) -> PathCache {
         PathCache {
             entries: HashMap::new(),
-            visited_directories: Vec::new()
+            visited_directories: Vec::new(),
+            filter,
+            threads: std::cmp::max(threads, 1),
+            index: None
         }
     }
 
-    pub fn add_directory(&mut self, interner: &mut PathInterner, root: &Path) {
-        if !root.is_absolute() {
-            panic!("Only absolute paths are supported.");
-        }
+    // Directories of the index that are unchanged are reused by add_directory, and the index is updated with every
+    // directory that is added.
+    pub fn set_index(&mut self, index: ScanIndex) {
+        self.index = Some(index);
+    }
+
+    pub fn take_index(&mut self) -> Option<ScanIndex> {
+        self.index.take()
+    }
+
+    pub fn add_directory(&mut self, interner: &mut PathInterner, root: &Path) -> std::io::Result<()> {
+        self.add_directory_with_progress(interner, root, &|_| {})
+            .map(|_| ())
+    }
+
+    // Relative roots are resolved against the working directory. Roots are canonicalized, so a symlink to a directory
+    // that has already been added, or to a directory within it, is only scanned once. Files are interned in path order,
+    // so the interner ids do not depend on the order in which the walker threads finished. Returns None when the root
+    // was skipped, as it has already been added.
+    pub fn add_directory_with_progress(
+        &mut self,
+        interner: &mut PathInterner,
+        root: &Path,
+        progress: &(dyn Fn(WalkProgress) + Sync)
+    ) -> std::io::Result<Option<WalkProgress>> {
+        let root = Self::canonicalize(root)?;
 
         if !root.is_dir() {
-            panic!("Only directories are supported in add_directory.");
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{:#?} is not a directory.", root)));
         }
 
         // Skip adding if the root path has been added already.
         for visited_directory in &self.visited_directories {
             if root.starts_with(visited_directory) {
-                return;
+                return Ok(None);
             }
         }
 
-        for result in WalkDir::new(root) {
-            if let Err(e) = result {
-                eprintln!("Encountered error while searching directory: {}", e);
-                continue;
-            }
+        let fingerprint = self.filter.fingerprint();
+        let previous = self.index.as_ref().and_then(|index| index.get(&root, &fingerprint));
+        let (tree, mut walk_progress) = walk(&root, &self.filter, self.threads, previous, progress);
 
-            let result = result.unwrap();
-            let path = result.path();
+        for file in tree.values().flat_map(|directory| directory.files.iter()) {
+            if self.filter.archives && is_archive(&file.path) {
+                walk_progress.errors += self.add_archive_members(interner, &root, file);
+
+                // The walk keeps every archive, so the archive itself is only a candidate when the filter accepts it.
+                if !self.filter.accepts_file(&root, &file.path, file.file_length) {
+                    continue;
+                }
+            }
 
             // If we've read this file at some time in the past, we do not need to check it again.
-            if interner.has_key(path) && self.entries.contains_key(&interner.get(path)) {
+            if interner.has_key(&file.path) && self.entries.contains_key(&interner.get(&file.path)) {
                 continue;
             }
 
-            if path.is_dir() {
-                continue;
+            let id = interner.put(file.path.clone());
+            let source = LocalFile::new(id, file.path.clone(), file.file_length, file.device_node, file.index_node);
+            self.entries.insert(id, PathCacheEntry::new(Box::new(source)));
+        }
+
+        if let Some(index) = &mut self.index {
+            index.insert(root.clone(), fingerprint, tree);
+        }
+
+        // Directories within the new root are covered by it from now on.
+        self.visited_directories.retain(|visited_directory| !visited_directory.starts_with(&root));
+        self.visited_directories.push(root);
+        Ok(Some(walk_progress))
+    }
+
+    // Members are listed on every scan, as the scan index only keeps the walked files. Returns the number of errors.
+    fn add_archive_members(&mut self, interner: &mut PathInterner, root: &Path, archive: &WalkedFile) -> usize {
+        let members = match list_members(&archive.path) {
+            Ok(members) => members,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
             }
+        };
 
-            let metadata = Self::to_metadata(path);
+        // The archive is interned for its handle id, but is only a candidate itself when the filter accepts it.
+        let archive_id = interner.get_or_put_clone(&archive.path);
 
-            if let Err(e) = metadata {
-                eprintln!("Encountered error while reading metadata: {}", e);
+        for member in members {
+            let already_read = interner.has_key(&member.path) && self.entries.contains_key(&interner.get(&member.path));
+            if already_read || !self.filter.accepts_file(root, &member.path, member.length) {
                 continue;
             }
 
-            let metadata = metadata.unwrap();
-            let entry = PathCacheEntry {
-                file_length: metadata.len(),
-                index_node: metadata.ino(),
-                device_node: metadata.dev()
-            };
+            let source = ArchiveMemberSource::new(
+                archive_id, archive.path.clone(), member.data_offset, member.length, archive.device_node, archive.index_node
+            );
 
-            let id = interner.get_or_put_clone(path);
-            self.entries.insert(id, entry);
+            let id = interner.put(member.path);
+            self.entries.insert(id, PathCacheEntry::new(Box::new(source)));
         }
 
-        self.visited_directories.push(root.to_path_buf());
-        
+        0
     }
 
-    pub fn add_path(&mut self, interner: &mut PathInterner, path: &Path) {
-        if !path.is_absolute() {
-            panic!("Only absolute paths are supported.");
-        }
+    // Relative paths are resolved against the working directory, and the path is canonicalized before it is interned.
+    pub fn add_path(&mut self, interner: &mut PathInterner, path: &Path) -> std::io::Result<()> {
+        let path = Self::canonicalize(path)?;
+        let path = path.as_path();
 
         // If we've read this file at some time in the past, we do not need to check it again.
         if interner.has_key(path) && self.entries.contains_key(&interner.get(path)) {
-            return;
-        }
-
-        let metadata = Self::to_metadata(path);
-
-        if let Err(e) = metadata {
-            eprintln!("Encountered error while reading metadata: {}", e);
-            return;
+            return Ok(());
         }
 
-        let metadata = metadata.unwrap();
-        let entry = PathCacheEntry {
-            file_length: metadata.len(),
-            index_node: metadata.ino(),
-            device_node: metadata.dev()
-        };
+        let metadata = Self::to_metadata(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Encountered error while reading metadata for path {:#?}: {}", path, e)))?;
 
         let id = interner.get_or_put_clone(path);
-        self.entries.insert(id, entry);
+        let source = LocalFile::new(id, path.to_path_buf(), metadata.len(), metadata.dev(), metadata.ino());
+        self.entries.insert(id, PathCacheEntry::new(Box::new(source)));
+        Ok(())
     }
 
-    pub fn add_path_by_interner_id(&mut self, interner: &mut PathInterner, id: usize) {
-        if self.entries.contains_key(&id) {
-            return;
-        }
+    // Adds a candidate that is not found by scanning, such as an in-memory fixture or a remote blob.
+    pub fn add_source(&mut self, interner: &mut PathInterner, path: PathBuf, source: Box<dyn DataSource>) -> usize {
+        let id = interner.put(path);
+        self.entries.insert(id, PathCacheEntry::new(source));
+        id
+    }
 
+    // Used for export targets, which are always read again. An entry found while scanning may come from a directory
+    // reused from the scan index, and the export target may have been resized since.
+    pub fn add_path_by_interner_id(&mut self, interner: &mut PathInterner, id: usize) {
         let path = interner.get_by_id(id);
         let metadata = Self::to_metadata(path);
 
         if let Err(e) = metadata {
             eprintln!("Encountered error while reading metadata: {}", e);
+            self.entries.remove(&id);
             return;
         }
 
         let metadata = metadata.unwrap();
-        let entry = PathCacheEntry {
-            file_length: metadata.len(),
-            index_node: metadata.ino(),
-            device_node: metadata.dev()
-        };
+        let source = LocalFile::new(id, path.to_path_buf(), metadata.len(), metadata.dev(), metadata.ino());
+        self.entries.insert(id, PathCacheEntry::new(Box::new(source)));
+    }
 
-        self.entries.insert(id, entry);
+    fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+        fs::canonicalize(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Encountered error while resolving path {:#?}: {}", path, e)))
     }
 
+    // Uses stat instead of opening the file, which is considerably faster on network shares.
     fn to_metadata(file_path: &Path) -> std::io::Result<Metadata> {
-        let handle = OpenOptions::new()
-            .write(false)
-            .truncate(false)
-            .create(false)
-            .create_new(false)
-            .read(true)
-            .open(file_path)?;
-
-        let metadata = handle.metadata()?;
-        Ok(metadata)
+        fs::metadata(file_path)
     }
 
     pub fn freeze(self) -> FrozenPathCache {
@@ -198,4 +251,69 @@ impl FrozenPathCache {
     pub fn get(&self, id: usize) -> &PathCacheEntry {
         self.entries.get(&id).unwrap()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::TempDir;
+
+    use super::*;
+
+    fn create_directory(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("path_cache_{}", name));
+        fs::create_dir_all(root.join("data").join("nested")).unwrap();
+        fs::write(root.join("data").join("a.bin"), [1; 4]).unwrap();
+        fs::write(root.join("data").join("nested").join("b.bin"), [2; 8]).unwrap();
+        root
+    }
+
+    #[test]
+    fn add_directory_should_deduplicate_symlinked_roots() {
+        let root = create_directory("symlink");
+        std::os::unix::fs::symlink(root.join("data"), root.join("link")).unwrap();
+        std::os::unix::fs::symlink(root.join("data").join("nested"), root.join("nested_link")).unwrap();
+
+        let mut interner = PathInterner::new();
+        let mut cache = PathCache::new();
+        cache.add_directory(&mut interner, &root.join("nested_link")).unwrap();
+        cache.add_directory(&mut interner, &root.join("link")).unwrap();
+        cache.add_directory(&mut interner, &root.join("data")).unwrap();
+
+        assert_eq!(2, cache.entries.len());
+        assert_eq!(vec![root.join("data")], cache.visited_directories);
+        assert!(interner.has_key(&root.join("data").join("nested").join("b.bin")));
+    }
+
+    #[test]
+    fn add_path_should_return_errors() {
+        let root = create_directory("errors");
+
+        let mut interner = PathInterner::new();
+        let mut cache = PathCache::new();
+        assert!(cache.add_path(&mut interner, &root.join("missing.bin")).is_err());
+        assert!(cache.add_directory(&mut interner, &root.join("data").join("a.bin")).is_err());
+        assert!(cache.add_path(&mut interner, &root.join("data").join("nested").join("..").join("a.bin")).is_ok());
+        assert!(interner.has_key(&root.join("data").join("a.bin")));
+    }
+
+    #[test]
+    fn add_path_by_interner_id_should_replace_stale_entries() {
+        let root = create_directory("export_target");
+        let export_target = root.join("data").join("a.bin");
+
+        let mut interner = PathInterner::new();
+        let mut cache = PathCache::new();
+        cache.add_directory(&mut interner, &root.join("data")).unwrap();
+
+        let id = interner.get(&export_target);
+        assert_eq!(4, cache.entries[&id].length());
+
+        fs::write(&export_target, [1; 16]).unwrap();
+        cache.add_path_by_interner_id(&mut interner, id);
+        assert_eq!(16, cache.entries[&id].length());
+
+        fs::remove_file(&export_target).unwrap();
+        cache.add_path_by_interner_id(&mut interner, id);
+        assert!(!cache.entries.contains_key(&id));
+    }
 }
\ No newline at end of file
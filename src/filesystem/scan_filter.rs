@@ -0,0 +1,146 @@
+use std::{io::{Error, ErrorKind, Result}, path::Path};
+
+use regex::Regex;
+
+#[derive(Debug, Clone)]
+pub enum ScanPattern {
+    // Glob patterns without a separator match the file or directory name, any other glob pattern matches the path
+    // relative to the scan root, using / as the separator.
+    Glob(glob::Pattern),
+    // Regular expressions are searched for anywhere in the path relative to the scan root.
+    Regex(Regex)
+}
+
+impl ScanPattern {
+    pub fn glob(pattern: &str) -> Result<ScanPattern> {
+        glob::Pattern::new(pattern)
+            .map(ScanPattern::Glob)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("Invalid glob pattern {:?}: {}", pattern, err)))
+    }
+
+    pub fn regex(pattern: &str) -> Result<ScanPattern> {
+        Regex::new(pattern)
+            .map(ScanPattern::Regex)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("Invalid regular expression {:?}: {}", pattern, err)))
+    }
+
+    fn matches(&self, name: &str, relative_path: &str) -> bool {
+        match self {
+            ScanPattern::Glob(pattern) if pattern.as_str().contains('/') => pattern.matches(relative_path),
+            ScanPattern::Glob(pattern) => pattern.matches(name),
+            ScanPattern::Regex(regex) => regex.is_match(relative_path)
+        }
+    }
+}
+
+// Rules deciding which entries of a scan root are indexed as candidates. Export files are always indexed.
+#[derive(Debug, Clone, Default)]
+pub struct ScanFilter {
+    // When not empty, only files matching at least one pattern are indexed.
+    pub include: Vec<ScanPattern>,
+    // Files and directories matching any pattern are skipped, directories are not descended into.
+    pub exclude: Vec<ScanPattern>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    // Skips files and directories whose name starts with a dot.
+    pub skip_hidden: bool,
+    // Maximum depth below the scan root, where files directly in the scan root have a depth of 1.
+    pub max_depth: Option<usize>,
+    // Does not descend into directories on a different filesystem than the scan root.
+    pub one_file_system: bool,
+    // Descends into symlinks to directories. Symlinks to a directory that was already walked, such as a parent, are skipped.
+    pub follow_symlinks: bool,
+    // Indexes the files within tar archives and the stored entries of zip archives as candidates, next to the archives.
+    pub archives: bool
+}
+
+impl ScanFilter {
+    // Whether the walk should yield or descend into an entry. The scan root itself is always accepted.
+    pub fn accepts_entry(&self, root: &Path, path: &Path) -> bool {
+        if path == root {
+            return true;
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if self.skip_hidden && name.starts_with('.') {
+            return false;
+        }
+
+        if self.exclude.is_empty() {
+            return true;
+        }
+
+        let relative_path = relative_path(root, path);
+        !self.exclude.iter().any(|pattern| pattern.matches(&name, &relative_path))
+    }
+
+    // Describes the filter, so a walk can only be reused by a later walk with the same filter.
+    pub fn fingerprint(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    // Whether a file that passed accepts_entry is indexed.
+    pub fn accepts_file(&self, root: &Path, path: &Path, file_length: u64) -> bool {
+        if self.min_size.is_some_and(|min_size| file_length < min_size) {
+            return false;
+        }
+
+        if self.max_size.is_some_and(|max_size| file_length > max_size) {
+            return false;
+        }
+
+        if self.include.is_empty() {
+            return true;
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let relative_path = relative_path(root, path);
+        self.include.iter().any(|pattern| pattern.matches(&name, &relative_path))
+    }
+}
+
+fn relative_path(root: &Path, path: &Path) -> String {
+    let relative_path = path.strip_prefix(root).unwrap_or(path);
+
+    relative_path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_should_match_names_and_relative_paths() {
+        let name_glob = ScanPattern::glob("*.jpg").unwrap();
+        let path_glob = ScanPattern::glob("photos/**/*.jpg").unwrap();
+        let regex = ScanPattern::regex("^photos/.*thumb").unwrap();
+
+        assert!(name_glob.matches("a.jpg", "photos/2020/a.jpg"));
+        assert!(path_glob.matches("a.jpg", "photos/2020/a.jpg"));
+        assert!(!path_glob.matches("a.jpg", "music/a.jpg"));
+        assert!(regex.matches("a.jpg", "photos/thumbs/a.jpg"));
+        assert!(!regex.matches("a.jpg", "music/photos/thumbs/a.jpg"));
+
+        assert!(ScanPattern::glob("[").is_err());
+        assert!(ScanPattern::regex("(").is_err());
+    }
+
+    #[test]
+    fn accepts_file_should_apply_sizes_and_includes() {
+        let root = Path::new("/scan");
+        let filter = ScanFilter {
+            include: vec![ScanPattern::glob("*.flac").unwrap()],
+            min_size: Some(10),
+            max_size: Some(100),
+            ..Default::default()
+        };
+
+        assert!(filter.accepts_file(root, Path::new("/scan/album/a.flac"), 50));
+        assert!(!filter.accepts_file(root, Path::new("/scan/album/a.flac"), 5));
+        assert!(!filter.accepts_file(root, Path::new("/scan/album/a.flac"), 500));
+        assert!(!filter.accepts_file(root, Path::new("/scan/album/a.mp3"), 50));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,294 @@
+use std::{collections::HashMap, ffi::OsStr, fs, os::unix::ffi::OsStrExt, path::{Path, PathBuf}};
+
+use crate::{
+    bencode::{BencodeDictionary, BencodeError, BencodeErrorKind, BencodeList, BencodeToken, Encoder, Parser},
+    filesystem::walker::{Timestamp, WalkedDirectory, WalkedFile, WalkedTree}
+};
+
+const SCAN_INDEX_VERSION: i128 = 1;
+
+/**
+ * A scan index stores the directories walked under each scan root, so later runs only read the directories that
+ * changed since. It is a bencoded dictionary, where paths are stored as raw bytes:
+ *
+ * {
+ *     "roots": [{
+ *         "directories": [{
+ *             "directories": [<subdirectory name>, ...],
+ *             "files": [{ "device": 2049, "inode": 131, "length": 1024, "modified": [<seconds>, <nanoseconds>], "name": <file name> }, ...],
+ *             "modified": [<seconds>, <nanoseconds>], omitted when the directory must be read again,
+ *             "path": <directory path>
+ *         }, ...],
+ *         "filter": <description of the scan filter used for the walk>,
+ *         "path": <root path>
+ *     }, ...],
+ *     "version": 1
+ * }
+ */
+#[derive(Default)]
+pub struct ScanIndex {
+    roots: HashMap<PathBuf, IndexedRoot>
+}
+
+struct IndexedRoot {
+    filter: String,
+    tree: WalkedTree
+}
+
+impl ScanIndex {
+    pub fn empty() -> ScanIndex {
+        ScanIndex::default()
+    }
+
+    // Loads the index at the given path. A missing file is treated as an empty index.
+    pub fn load(path: &Path) -> Result<ScanIndex, std::io::Error> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(ScanIndex::empty()),
+            Err(err) => return Err(err)
+        };
+
+        ScanIndex::decode(&bytes).map_err(|err| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unable to read scan index {:#?}: {}", path, err.message))
+        })
+    }
+
+    // Writes the index to a temporary file first, so an interrupted save never leaves a truncated index behind.
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+
+        fs::write(&temporary_path, self.encode())?;
+        fs::rename(&temporary_path, path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    // Returns the previous walk of a root, only when it was walked with the same filter.
+    pub fn get(&self, root: &Path, filter: &str) -> Option<&WalkedTree> {
+        self.roots.get(root)
+            .filter(|indexed| indexed.filter == filter)
+            .map(|indexed| &indexed.tree)
+    }
+
+    pub fn insert(&mut self, root: PathBuf, filter: String, tree: WalkedTree) {
+        self.roots.insert(root, IndexedRoot { filter, tree });
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut roots: Vec<_> = self.roots.iter().collect();
+        roots.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut encoder = Encoder::new();
+        encoder.begin_dictionary()
+            .string(b"roots")
+            .begin_list();
+
+        for (root, indexed) in roots {
+            encoder.begin_dictionary()
+                .string(b"directories")
+                .begin_list();
+
+            for (path, directory) in indexed.tree.iter() {
+                encode_directory(&mut encoder, path, directory);
+            }
+
+            encoder.end()
+                .string(b"filter").string(indexed.filter.as_bytes())
+                .string(b"path").string(root.as_os_str().as_bytes())
+                .end();
+        }
+
+        encoder.end()
+            .string(b"version").integer(SCAN_INDEX_VERSION)
+            .end();
+
+        encoder.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<ScanIndex, BencodeError> {
+        let BencodeToken::Dictionary(index) = Parser::decode(bytes)? else {
+            return Err(malformed("Unexpected token at root. Expected dictionary token"));
+        };
+
+        if index.find_integer_value(b"version")?.value != SCAN_INDEX_VERSION {
+            return Err(malformed("Unsupported scan index version"));
+        }
+
+        let mut scan_index = ScanIndex::empty();
+        for root in index.find_list_value(b"roots")?.value.iter() {
+            let root = as_dictionary(root)?;
+
+            let mut tree = WalkedTree::new();
+            for directory in root.find_list_value(b"directories")?.value.iter() {
+                let (path, directory) = decode_directory(as_dictionary(directory)?)?;
+                tree.insert(path, directory);
+            }
+
+            let filter = String::from_utf8_lossy(&root.find_string_value(b"filter")?.value).to_string();
+            let path = to_path(&root.find_string_value(b"path")?.value);
+            scan_index.insert(path, filter, tree);
+        }
+
+        Ok(scan_index)
+    }
+}
+
+fn encode_directory(encoder: &mut Encoder, path: &Path, directory: &WalkedDirectory) {
+    encoder.begin_dictionary()
+        .string(b"directories")
+        .begin_list();
+
+    for subdirectory in directory.directories.iter() {
+        encoder.string(subdirectory.file_name().unwrap_or_default().as_bytes());
+    }
+
+    encoder.end()
+        .string(b"files")
+        .begin_list();
+
+    for file in directory.files.iter() {
+        encoder.begin_dictionary()
+            .string(b"device").integer(file.device_node as i128)
+            .string(b"inode").integer(file.index_node as i128)
+            .string(b"length").integer(file.file_length as i128);
+
+        encode_timestamp(encoder.string(b"modified"), &file.modified);
+
+        encoder.string(b"name").string(file.path.file_name().unwrap_or_default().as_bytes())
+            .end();
+    }
+
+    encoder.end();
+
+    if let Some(modified) = &directory.modified {
+        encode_timestamp(encoder.string(b"modified"), modified);
+    }
+
+    encoder.string(b"path").string(path.as_os_str().as_bytes())
+        .end();
+}
+
+fn encode_timestamp(encoder: &mut Encoder, timestamp: &Timestamp) {
+    encoder.begin_list()
+        .integer(timestamp.seconds as i128)
+        .integer(timestamp.nanoseconds as i128)
+        .end();
+}
+
+fn decode_directory(directory: &BencodeDictionary) -> Result<(PathBuf, WalkedDirectory), BencodeError> {
+    let path = to_path(&directory.find_string_value(b"path")?.value);
+
+    let mut directories = Vec::new();
+    for name in directory.find_list_value(b"directories")?.value.iter() {
+        directories.push(path.join(to_path(as_string(name)?)));
+    }
+
+    let mut files = Vec::new();
+    for file in directory.find_list_value(b"files")?.value.iter() {
+        let file = as_dictionary(file)?;
+
+        files.push(WalkedFile {
+            path: path.join(to_path(&file.find_string_value(b"name")?.value)),
+            file_length: to_integer(file, b"length")?,
+            device_node: to_integer(file, b"device")?,
+            index_node: to_integer(file, b"inode")?,
+            modified: decode_timestamp(file.find_list_value(b"modified")?)?
+        });
+    }
+
+    // A missing modification time means the directory must be read again.
+    let modified = match directory.find_list_value(b"modified") {
+        Ok(modified) => Some(decode_timestamp(modified)?),
+        Err(_) => None
+    };
+
+    Ok((path, WalkedDirectory { modified, files, directories }))
+}
+
+fn decode_timestamp(timestamp: &BencodeList) -> Result<Timestamp, BencodeError> {
+    let values: Vec<i64> = timestamp.value.iter()
+        .map(|value| match value {
+            BencodeToken::Integer(integer) => i64::try_from(integer.value).map_err(|_| malformed("Timestamp is out of range")),
+            _ => Err(malformed("Timestamp is not a list of integers"))
+        })
+        .collect::<Result<_, _>>()?;
+
+    match values.as_slice() {
+        [seconds, nanoseconds] => Ok(Timestamp { seconds: *seconds, nanoseconds: *nanoseconds }),
+        _ => Err(malformed("Timestamp must have two values"))
+    }
+}
+
+fn to_integer(dictionary: &BencodeDictionary, key: &[u8]) -> Result<u64, BencodeError> {
+    let value = dictionary.find_integer_value(key)?;
+
+    u64::try_from(value.value)
+        .map_err(|_| malformed(&format!("Could not convert integer value at position {} to unsigned 64-bit integer value", value.start_position)))
+}
+
+fn to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+fn as_dictionary(token: &BencodeToken) -> Result<&BencodeDictionary, BencodeError> {
+    match token {
+        BencodeToken::Dictionary(dictionary) => Ok(dictionary),
+        _ => Err(malformed("Expected dictionary token"))
+    }
+}
+
+fn as_string(token: &BencodeToken) -> Result<&[u8], BencodeError> {
+    match token {
+        BencodeToken::String(string) => Ok(&string.value),
+        _ => Err(malformed("Expected string token"))
+    }
+}
+
+fn malformed(message: &str) -> BencodeError {
+    BencodeError::new(BencodeErrorKind::MalformedData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_should_round_trip() {
+        let root = PathBuf::from("/scan");
+        let directory = root.join("dir\nwith newline");
+        let timestamp = Timestamp { seconds: 1_700_000_000, nanoseconds: 42 };
+
+        let mut tree = WalkedTree::new();
+        tree.insert(root.clone(), WalkedDirectory {
+            modified: Some(timestamp),
+            files: Vec::new(),
+            directories: vec![directory.clone()]
+        });
+
+        tree.insert(directory.clone(), WalkedDirectory {
+            modified: None,
+            files: vec![WalkedFile {
+                path: directory.join(OsStr::from_bytes(b"\xff.bin")),
+                file_length: u64::MAX,
+                device_node: 2049,
+                index_node: 131,
+                modified: timestamp
+            }],
+            directories: Vec::new()
+        });
+
+        let mut index = ScanIndex::empty();
+        index.insert(root.clone(), "filter".to_string(), tree.clone());
+
+        let decoded = ScanIndex::decode(&index.encode()).unwrap();
+        assert_eq!(Some(&tree), decoded.get(&root, "filter"));
+        assert_eq!(None, decoded.get(&root, "other filter"));
+    }
+}
\ No newline at end of file
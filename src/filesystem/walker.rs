@@ -0,0 +1,457 @@
+use std::{collections::{BTreeMap, HashSet}, fs::{self, Metadata}, os::unix::fs::MetadataExt, path::{Path, PathBuf}, sync::{Condvar, Mutex}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use crate::filesystem::{archive::is_archive, scan_filter::ScanFilter};
+
+// Minimum time between two progress reports of a walk.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Directories modified this recently may still change within the same timestamp, so they are always read again.
+const MODIFIED_GRACE_SECONDS: i64 = 2;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WalkProgress {
+    pub directories: usize,
+    // Directories that were unchanged since a previous walk, and were not read again.
+    pub reused_directories: usize,
+    pub files: usize,
+    pub errors: usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub seconds: i64,
+    pub nanoseconds: i64
+}
+
+impl Timestamp {
+    fn modified(metadata: &Metadata) -> Timestamp {
+        Timestamp {
+            seconds: metadata.mtime(),
+            nanoseconds: metadata.mtime_nsec()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkedFile {
+    pub path: PathBuf,
+    pub file_length: u64,
+    pub device_node: u64,
+    pub index_node: u64,
+    pub modified: Timestamp
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkedDirectory {
+    // None when the directory was modified too recently to be reused by a later walk.
+    pub modified: Option<Timestamp>,
+    // Files and subdirectories that passed the filter, sorted by path.
+    pub files: Vec<WalkedFile>,
+    pub directories: Vec<PathBuf>
+}
+
+// Every directory reached by a walk, keyed by path.
+pub type WalkedTree = BTreeMap<PathBuf, WalkedDirectory>;
+
+struct QueuedDirectory {
+    path: PathBuf,
+    // Depth below the root, where the root has a depth of 0.
+    depth: usize,
+    // The directory or one of its parents was reached through a symlink, only tracked when following symlinks.
+    through_symlink: bool
+}
+
+struct WalkQueue {
+    // Directories waiting to be read.
+    directories: Vec<QueuedDirectory>,
+    // Directories currently being read, which may still add directories to the queue.
+    active: usize,
+    progress: WalkProgress,
+    last_report: Instant
+}
+
+// Walks a directory tree with multiple threads reading directories in parallel. Files are described with stat
+// without opening them, and symlinks to files are indexed with the metadata of their target. Symlinks to directories
+// are only followed when the filter enables it. A directory reached through a symlink is skipped when the same directory
+// was already walked, so symlink loops end the walk of that branch and a farm of symlinks to one directory only walks it
+// once. Directories reached without a symlink are always walked. The result is keyed by path, so it does not depend on
+// the order in which the threads finished, apart from which symlink a directory is walked through. Progress is reported
+// at most once every second while walking.
+//
+// Directories of a previous walk with the same root and filter are reused without being read again when their
+// modification time is unchanged, as adding, removing or renaming an entry updates the modification time of its
+// directory. Files that are modified in place are not detected.
+pub fn walk(
+    root: &Path,
+    filter: &ScanFilter,
+    threads: usize,
+    previous: Option<&WalkedTree>,
+    progress: &(dyn Fn(WalkProgress) + Sync)
+) -> (WalkedTree, WalkProgress) {
+    let root_device = match fs::metadata(root) {
+        Ok(metadata) => metadata.dev(),
+        Err(e) => {
+            eprintln!("Encountered error while searching directory {:#?}: {}", root, e);
+            return (WalkedTree::new(), WalkProgress { errors: 1, ..Default::default() });
+        }
+    };
+
+    let queue = Mutex::new(WalkQueue {
+        directories: vec![QueuedDirectory { path: root.to_path_buf(), depth: 0, through_symlink: false }],
+        active: 0,
+        progress: WalkProgress::default(),
+        last_report: Instant::now()
+    });
+
+    let condvar = Condvar::new();
+    let walker = Walker { root, root_device, filter, previous, visited: Mutex::new(HashSet::new()) };
+
+    let tree: WalkedTree = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..std::cmp::max(threads, 1))
+            .map(|_| scope.spawn(|| walker.work(&queue, &condvar, progress)))
+            .collect();
+
+        handles.into_iter()
+            .flat_map(|handle| handle.join().expect("Encountered panic while joining on walker thread."))
+            .collect()
+    });
+
+    let queue = queue.into_inner()
+        .expect("Walk queue should always lock.");
+
+    (tree, queue.progress)
+}
+
+struct Walker<'a> {
+    root: &'a Path,
+    root_device: u64,
+    filter: &'a ScanFilter,
+    previous: Option<&'a WalkedTree>,
+    // Device and inode of every directory walked so far, only tracked when following symlinks.
+    visited: Mutex<HashSet<(u64, u64)>>
+}
+
+impl Walker<'_> {
+    fn work(&self, queue: &Mutex<WalkQueue>, condvar: &Condvar, progress: &(dyn Fn(WalkProgress) + Sync)) -> Vec<(PathBuf, WalkedDirectory)> {
+        let mut walked = Vec::new();
+
+        loop {
+            let QueuedDirectory { path: directory, depth, mut through_symlink } = {
+                let mut state = queue.lock()
+                    .expect("Walk queue should always lock.");
+
+                loop {
+                    if let Some(next) = state.directories.pop() {
+                        state.active += 1;
+                        break next;
+                    }
+
+                    if state.active == 0 {
+                        condvar.notify_all();
+                        return walked;
+                    }
+
+                    state = condvar.wait(state)
+                        .expect("Walk queue should always lock.");
+                }
+            };
+
+            let mut errors = 0;
+            let mut reused = false;
+
+            if self.filter.follow_symlinks && !through_symlink {
+                through_symlink = fs::symlink_metadata(&directory).is_ok_and(|metadata| metadata.is_symlink());
+            }
+
+            let result = match fs::metadata(&directory) {
+                Ok(metadata) if self.filter.follow_symlinks && !self.visit(&metadata, through_symlink) => {
+                    eprintln!("Skipping symlink at {:#?}, as the directory it points to was already walked.", directory);
+                    None
+                },
+                Ok(metadata) => match self.reuse_directory(&directory, &metadata) {
+                    Some(result) => {
+                        reused = true;
+                        Some(result)
+                    },
+                    None => self.read_directory(&directory, &metadata, depth, &mut errors)
+                },
+                Err(e) => {
+                    eprintln!("Encountered error while searching directory {:#?}: {}", directory, e);
+                    errors += 1;
+                    None
+                }
+            };
+
+            let mut state = queue.lock()
+                .expect("Walk queue should always lock.");
+
+            state.progress.directories += 1;
+            state.progress.reused_directories += reused as usize;
+            state.progress.errors += errors;
+
+            if let Some(result) = result {
+                state.progress.files += result.files.len();
+
+                // Entries of the subdirectories would be deeper than the maximum depth.
+                if self.filter.max_depth.is_none_or(|max_depth| depth + 1 < max_depth) {
+                    state.directories.extend(result.directories.iter().map(|path| QueuedDirectory {
+                        path: path.clone(),
+                        depth: depth + 1,
+                        through_symlink
+                    }));
+                }
+
+                walked.push((directory, result));
+            }
+
+            state.active -= 1;
+            condvar.notify_all();
+
+            if state.last_report.elapsed() >= PROGRESS_INTERVAL {
+                state.last_report = Instant::now();
+                progress(state.progress);
+            }
+        }
+    }
+
+    // Records the directory as walked. Returns false when it was reached through a symlink and was already walked.
+    fn visit(&self, metadata: &Metadata, through_symlink: bool) -> bool {
+        let mut visited = self.visited.lock()
+            .expect("Visited directories should always lock.");
+
+        visited.insert((metadata.dev(), metadata.ino())) || !through_symlink
+    }
+
+    fn reuse_directory(&self, directory: &Path, metadata: &Metadata) -> Option<WalkedDirectory> {
+        let previous = self.previous?.get(directory)?;
+
+        if previous.modified == Some(Timestamp::modified(metadata)) {
+            Some(previous.clone())
+        } else {
+            None
+        }
+    }
+
+    // Reads a single directory, with the files and the subdirectories that should be walked. Errors on entries are
+    // counted, and None is returned when the directory itself cannot be read.
+    fn read_directory(&self, directory: &Path, metadata: &Metadata, depth: usize, errors: &mut usize) -> Option<WalkedDirectory> {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Encountered error while searching directory {:#?}: {}", directory, e);
+                *errors += 1;
+                return None;
+            }
+        };
+
+        let mut walked = WalkedDirectory {
+            modified: Some(Timestamp::modified(metadata)),
+            files: Vec::new(),
+            directories: Vec::new()
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or_default();
+
+        if metadata.mtime() >= now - MODIFIED_GRACE_SECONDS {
+            walked.modified = None;
+        }
+
+        if self.filter.max_depth.is_some_and(|max_depth| depth + 1 > max_depth) {
+            return Some(walked);
+        }
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("Encountered error while searching directory {:#?}: {}", directory, e);
+                    *errors += 1;
+                    continue;
+                }
+            };
+
+            let path = entry.path();
+            if !self.filter.accepts_entry(self.root, &path) {
+                continue;
+            }
+
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(e) => {
+                    eprintln!("Encountered error while reading metadata for path {:#?}: {}", path, e);
+                    *errors += 1;
+                    continue;
+                }
+            };
+
+            if file_type.is_dir() {
+                if self.filter.one_file_system {
+                    match fs::symlink_metadata(&path) {
+                        Ok(metadata) if metadata.dev() != self.root_device => continue,
+                        Ok(_) => {},
+                        Err(e) => {
+                            eprintln!("Encountered error while reading metadata for path {:#?}: {}", path, e);
+                            *errors += 1;
+                            continue;
+                        }
+                    }
+                }
+
+                walked.directories.push(path);
+                continue;
+            }
+
+            // Follows symlinks, so symlinks to files are indexed while symlinks to directories are only descended into
+            // when following symlinks is enabled.
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    eprintln!("Encountered error while reading metadata for path {:#?}: {}", path, e);
+                    *errors += 1;
+                    continue;
+                }
+            };
+
+            if metadata.is_dir() {
+                if self.filter.follow_symlinks && !(self.filter.one_file_system && metadata.dev() != self.root_device) {
+                    walked.directories.push(path);
+                }
+
+                continue;
+            }
+
+            // Archives are kept even when the filter rejects them, as their members are filtered on their own.
+            let is_listed_archive = self.filter.archives && is_archive(&path);
+            if !is_listed_archive && !self.filter.accepts_file(self.root, &path, metadata.len()) {
+                continue;
+            }
+
+            walked.files.push(WalkedFile {
+                path,
+                file_length: metadata.len(),
+                device_node: metadata.dev(),
+                index_node: metadata.ino(),
+                modified: Timestamp::modified(&metadata)
+            });
+        }
+
+        walked.files.sort_by(|a, b| a.path.cmp(&b.path));
+        walked.directories.sort();
+        Some(walked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::TempDir;
+
+    use super::*;
+
+    fn set_modified(path: &Path, seconds: u64) {
+        fs::File::open(path).unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    fn walked_paths(tree: &WalkedTree) -> Vec<PathBuf> {
+        tree.values()
+            .flat_map(|directory| directory.files.iter().map(|file| file.path.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn walk_should_return_sorted_files() {
+        let temp_dir = TempDir::new("walker");
+        let root = temp_dir.path().to_path_buf();
+
+        let mut expected = Vec::new();
+        for directory in 0..8 {
+            let directory = root.join(format!("d{}", directory)).join("nested");
+            fs::create_dir_all(&directory).unwrap();
+
+            for file in 0..4 {
+                let path = directory.join(format!("f{}", file));
+                fs::write(&path, vec![0; file]).unwrap();
+                expected.push(path);
+            }
+        }
+
+        std::os::unix::fs::symlink(root.join("d0"), root.join("link")).unwrap();
+        expected.sort();
+
+        let (tree, progress) = walk(&root, &ScanFilter::default(), 4, None, &|_| {});
+
+        assert_eq!(expected, walked_paths(&tree));
+        assert_eq!(3, tree[&root.join("d0").join("nested")].files[3].file_length);
+        assert_eq!(WalkProgress { directories: 17, reused_directories: 0, files: 32, errors: 0 }, progress);
+
+        let filter = ScanFilter { max_depth: Some(2), ..Default::default() };
+        let (tree, _) = walk(&root, &filter, 2, None, &|_| {});
+        assert!(walked_paths(&tree).is_empty());
+    }
+
+    #[test]
+    fn walk_should_follow_symlinks_and_skip_loops() {
+        let temp_dir = TempDir::new("walker_symlinks");
+        let root = temp_dir.path().to_path_buf();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::write(root.join("b").join("f"), [0; 4]).unwrap();
+
+        std::os::unix::fs::symlink(&root, root.join("a").join("loop")).unwrap();
+
+        // A farm of symlinks to a single directory outside of the root.
+        let target = TempDir::new("walker_symlinks_target");
+        fs::write(target.join("g"), [0; 8]).unwrap();
+        fs::create_dir_all(root.join("farm")).unwrap();
+        for link in 0..4 {
+            std::os::unix::fs::symlink(target.path(), root.join("farm").join(format!("l{}", link))).unwrap();
+        }
+
+        let filter = ScanFilter { follow_symlinks: true, ..Default::default() };
+        let (tree, progress) = walk(&root, &filter, 2, None, &|_| {});
+
+        // The target of the farm is walked through one of the symlinks only.
+        let paths = walked_paths(&tree);
+        assert_eq!(2, paths.len());
+        assert_eq!(root.join("b").join("f"), paths[0]);
+        assert!(paths[1].starts_with(root.join("farm")) && paths[1].ends_with("g"));
+        assert_eq!(1, (0..4).filter(|link| tree.contains_key(&root.join("farm").join(format!("l{}", link)))).count());
+        assert!(!tree.contains_key(&root.join("a").join("loop")));
+        assert_eq!(0, progress.errors);
+
+        let (tree, _) = walk(&root, &ScanFilter::default(), 2, None, &|_| {});
+        assert_eq!(vec![root.join("b").join("f")], walked_paths(&tree));
+    }
+
+    #[test]
+    fn walk_should_reuse_unchanged_directories() {
+        let temp_dir = TempDir::new("walker_reuse");
+        let root = temp_dir.path().to_path_buf();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::write(root.join("a").join("f"), [0; 4]).unwrap();
+
+        for path in [root.join("a"), root.join("b"), root.clone()] {
+            set_modified(&path, 1_000_000);
+        }
+
+        let (first, _) = walk(&root, &ScanFilter::default(), 2, None, &|_| {});
+
+        // A file added to b updates the modification time of b only, so the root and a are reused.
+        fs::write(root.join("b").join("g"), [0; 8]).unwrap();
+        set_modified(&root.join("b"), 2_000_000);
+
+        let (second, progress) = walk(&root, &ScanFilter::default(), 2, Some(&first), &|_| {});
+        assert_eq!(WalkProgress { directories: 3, reused_directories: 2, files: 2, errors: 0 }, progress);
+        assert_eq!(vec![root.join("a").join("f"), root.join("b").join("g")], walked_paths(&second));
+
+        // A file modified in place is not detected in a reused directory.
+        fs::write(root.join("a").join("f"), [0; 16]).unwrap();
+        let (third, _) = walk(&root, &ScanFilter::default(), 2, Some(&second), &|_| {});
+        assert_eq!(4, third[&root.join("a")].files[0].file_length);
+    }
+}
\ No newline at end of file
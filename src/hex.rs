@@ -0,0 +1,21 @@
+use std::fmt::Write as FmtWrite;
+
+// Lowercase hex of arbitrary bytes, such as bitfields or binary bencode strings.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(&mut output, "{:02x}", byte).expect("Unable to write");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_hex_should_pad_and_use_lowercase() {
+        assert_eq!("", to_hex(&[]));
+        assert_eq!("000aff", to_hex(&[0x00, 0x0A, 0xFF]));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    bencode::{BencodeToken, Parser},
+    torrent::{error::{TorrentError, TorrentErrorKind}, pieces::Pieces, Torrent}
+};
+
+#[derive(Debug)]
+pub struct FileInspection {
+    // Path relative to the torrent name, or the torrent name for single file torrents.
+    pub path: PathBuf,
+    pub length: u64,
+    // Offset of the file within the concatenated torrent data.
+    pub offset: u64,
+    pub padding: bool,
+    pub attr: Option<String>,
+    // First and last piece spanning the file, none for empty files.
+    pub pieces: Option<(usize, usize)>
+}
+
+#[derive(Debug)]
+pub struct TorrentInspection {
+    pub info_hash: Vec<u8>,
+    // SHA-256 info hash, only present for v2 and hybrid torrents (BEP 52).
+    pub info_hash_v2: Option<Vec<u8>>,
+    pub name: String,
+    pub piece_length: u64,
+    pub piece_count: usize,
+    pub total_length: u64,
+    pub files: Vec<FileInspection>
+}
+
+// Loads a torrent through Torrent::from_bytes and describes its layout, without touching any file data.
+pub fn inspect(bytes: &[u8]) -> Result<TorrentInspection, TorrentError> {
+    let torrent = Torrent::from_bytes(bytes)?;
+    let info_hash_v2 = calculate_info_hash_v2(bytes)?;
+
+    let mut files: Vec<FileInspection> = match &torrent.info.files {
+        Some(files) => files.iter()
+            .map(|file| FileInspection {
+                path: file.path.iter().collect(),
+                length: file.length,
+                offset: 0,
+                padding: file.padding(),
+                attr: file.attr.as_ref().map(|attr| String::from_utf8_lossy(attr).to_string()),
+                pieces: None
+            })
+            .collect(),
+        None => vec![FileInspection {
+            path: PathBuf::from(&torrent.info.name),
+            length: torrent.info.length.unwrap_or_default(),
+            offset: 0,
+            padding: false,
+            attr: torrent.info.attr.as_ref().map(|attr| String::from_utf8_lossy(attr).to_string()),
+            pieces: None
+        }]
+    };
+
+    let mut offset = 0;
+    for file in files.iter_mut() {
+        file.offset = offset;
+        offset += file.length;
+    }
+
+    for piece in Pieces::from_torrent(&torrent) {
+        // Empty files are included in pieces with a read length of zero, but do not span them.
+        for piece_file in piece.files.iter().filter(|piece_file| piece_file.read_length > 0) {
+            let file = &mut files[piece_file.file_index];
+            file.pieces = match file.pieces {
+                Some((first, _)) => Some((first, piece.position)),
+                None => Some((piece.position, piece.position))
+            };
+        }
+    }
+
+    Ok(TorrentInspection {
+        info_hash: torrent.info_hash,
+        info_hash_v2,
+        name: torrent.info.name,
+        piece_length: torrent.info.piece_length,
+        piece_count: torrent.info.pieces.len(),
+        total_length: offset,
+        files
+    })
+}
+
+// The v2 info hash covers the same info dictionary bytes as the v1 info hash, but is only defined
+// when the info dictionary declares meta version 2.
+fn calculate_info_hash_v2(bytes: &[u8]) -> Result<Option<Vec<u8>>, TorrentError> {
+    let token = Parser::decode(bytes)
+        .map_err(|err| TorrentError::new(TorrentErrorKind::MalformedData, err.message.to_string()))?;
+
+    let BencodeToken::Dictionary(root) = token else {
+        return Ok(None);
+    };
+
+    let Ok(info) = root.find_dictionary_value(b"info") else {
+        return Ok(None);
+    };
+
+    match info.find_integer_value(b"meta version") {
+        Ok(version) if version.value == 2 => {
+            let mut hasher = Sha256::new();
+            hasher.update(&bytes[info.start_position..info.continuation_position]);
+            Ok(Some(hasher.finalize().to_vec()))
+        },
+        _ => Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inspect_should_report_file_piece_ranges() {
+        let mut bytes = b"d4:infod5:filesl".to_vec();
+        bytes.extend_from_slice(b"d6:lengthi3e4:pathl1:aee");
+        bytes.extend_from_slice(b"d4:attr1:p6:lengthi1e4:pathl4:.pad1:1ee");
+        bytes.extend_from_slice(b"d6:lengthi0e4:pathl1:bee");
+        bytes.extend_from_slice(b"d6:lengthi4e4:pathl1:cee");
+        bytes.extend_from_slice(b"e4:name4:test12:piece lengthi4e6:pieces40:");
+        bytes.extend_from_slice(&[0; 40]);
+        bytes.extend_from_slice(b"ee");
+
+        let inspection = inspect(&bytes).unwrap();
+
+        assert_eq!(None, inspection.info_hash_v2);
+        assert_eq!(2, inspection.piece_count);
+        assert_eq!(8, inspection.total_length);
+
+        let pieces: Vec<_> = inspection.files.iter().map(|file| file.pieces).collect();
+        assert_eq!(vec![Some((0, 0)), Some((0, 0)), None, Some((1, 1))], pieces);
+        assert!(inspection.files[1].padding);
+        assert_eq!(Some("p".to_string()), inspection.files[1].attr);
+        assert_eq!(4, inspection.files[3].offset);
+    }
+}
\ No newline at end of file
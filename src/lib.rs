@@ -4,4 +4,16 @@ pub mod bencode;
 pub mod orchestrator;
 pub mod metadata;
 pub mod solver;
-pub mod writer;
\ No newline at end of file
+pub mod writer;
+pub mod checkpoint;
+pub mod plan;
+pub mod explain;
+pub mod bitfield;
+pub mod hex;
+pub mod recheck;
+pub mod verify;
+pub mod inspect;
+pub mod fetch;
+pub mod watch;
+#[cfg(test)]
+mod test_support;
\ No newline at end of file
@@ -1,8 +1,10 @@
 use std::{collections::{BTreeSet, HashMap}, fs::OpenOptions, path::{Path, PathBuf}, sync::Mutex};
 
+use sha1::{Digest, Sha1};
+
 use crate::{filesystem::{ExportPathFormatter, FrozenPathInterner, PathCacheEntry, PathInterner}, torrent::{pieces::Pieces, Torrent}};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TorrentProcessState {
     // Pieces that were discovered successfully
     pub success_pieces: usize,
@@ -13,6 +15,9 @@ pub struct TorrentProcessState {
     // Pieces that encountered a processing exception, like I/O error.
     pub fault_pieces: usize,
 
+    // Pieces that reached the combination or time limit before every combination was tried.
+    pub exhausted_pieces: usize,
+
     // We may detect successful pieces that don't need to be written, as they 
     // may either be padding files or content is already on-disk.
     // For global state, pieces with partial writes do not count as ignored.
@@ -29,11 +34,22 @@ impl TorrentProcessState {
             success_pieces: 0,
             failed_pieces: 0,
             fault_pieces: 0,
+            exhausted_pieces: 0,
             writable_pieces: 0,
             ignored_pieces: 0,
             total_pieces
         }
     }
+
+    // Counts a piece as reported by the solvers.
+    pub fn record_piece(&mut self, found: bool, fault: bool, exhausted: bool, wrote_to_disk: bool) {
+        self.success_pieces += (found && !fault) as usize;
+        self.failed_pieces += (!found && !fault && !exhausted) as usize;
+        self.fault_pieces += (fault) as usize;
+        self.exhausted_pieces += (!found && !fault && exhausted) as usize;
+        self.writable_pieces += (wrote_to_disk) as usize;
+        self.ignored_pieces += (!wrote_to_disk) as usize;
+    }
 }
 
 #[derive(Debug)]
@@ -54,6 +70,18 @@ pub struct TorrentPieceEntry {
     pub total_choices: Vec<usize>
 }
 
+impl TorrentPieceEntry {
+    // The first candidate of the first file in the piece that has candidates, and the position it is read from.
+    // Used to order and schedule pieces by the data they read.
+    pub fn primary_candidate(&self, torrent_file_metadata: &[TorrentFileEntry]) -> Option<(usize, u64)> {
+        self.files.iter().find_map(|piece_file| {
+            let file = &torrent_file_metadata[piece_file.file_id];
+            let searches = file.searches.as_ref()?;
+            searches.first().map(|path_id| (*path_id, piece_file.read_start_position))
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct TorrentFileEntry {
     pub file_id: usize,
@@ -270,6 +298,81 @@ pub fn calculate_total_choices_for_piece(
     }
 }
 
+// Hash of a piece filled with zeros for every distinct piece length. Hashed in chunks, so pieces of any length
+// are hashed without allocating the whole piece.
+pub fn calculate_zero_piece_hashes(torrent_piece_metadata: &[TorrentPieceEntry]) -> HashMap<u64, Vec<u8>> {
+    let zeros = [0u8; 65536];
+    let mut zero_piece_hashes = HashMap::new();
+
+    for piece in torrent_piece_metadata.iter() {
+        zero_piece_hashes.entry(piece.length).or_insert_with(|| {
+            let mut hasher = Sha1::new();
+            let mut remaining = piece.length;
+            while remaining > 0 {
+                let length = std::cmp::min(remaining, zeros.len() as u64);
+                hasher.update(&zeros[..length as usize]);
+                remaining -= length;
+            }
+
+            hasher.finalize().to_vec()
+        });
+    }
+
+    zero_piece_hashes
+}
+
+#[derive(Debug)]
+pub struct SearchSpaceEstimate {
+    // Upper bound on the number of combinations, saturating at u64::MAX.
+    pub total_combinations: u64,
+    pub searchable_pieces: usize,
+    // Pieces where at least one file has no candidates, these can never be solved.
+    pub unsolvable_pieces: usize,
+    // Pieces with more combinations than the configured per-piece limit.
+    pub over_limit_pieces: usize
+}
+
+pub fn calculate_combinations(choices: &[usize]) -> u64 {
+    if choices.is_empty() {
+        return 0;
+    }
+
+    choices.iter().fold(1_u64, |total, choice| total.saturating_mul(*choice as u64))
+}
+
+// The estimate is an upper bound, pieces spanning multiple files discard candidates with identical
+// content before solving.
+pub fn estimate_search_space(
+    torrent_piece_metadata: &[TorrentPieceEntry],
+    piece_ids: &[usize],
+    max_combinations: Option<u64>
+) -> SearchSpaceEstimate {
+    let mut estimate = SearchSpaceEstimate {
+        total_combinations: 0,
+        searchable_pieces: 0,
+        unsolvable_pieces: 0,
+        over_limit_pieces: 0
+    };
+
+    for piece_id in piece_ids {
+        let combinations = calculate_combinations(&torrent_piece_metadata[*piece_id].total_choices);
+
+        if combinations == 0 {
+            estimate.unsolvable_pieces += 1;
+            continue;
+        }
+
+        estimate.searchable_pieces += 1;
+        estimate.total_combinations = estimate.total_combinations.saturating_add(combinations);
+
+        if max_combinations.is_some_and(|max_combinations| combinations > max_combinations) {
+            estimate.over_limit_pieces += 1;
+        }
+    }
+
+    estimate
+}
+
 pub fn discover_and_apply_searches(
     torrent_file_metadata: &mut [TorrentFileEntry], 
     disk_metadata: &HashMap<usize, PathCacheEntry>,
@@ -1,114 +1,286 @@
-use std::{fs::{self}, path::PathBuf, sync::{Arc, Mutex}, time::Instant};
+use std::{collections::{BTreeSet, HashMap, HashSet}, fs::{self}, net::SocketAddr, os::unix::fs::MetadataExt, path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard}, time::{Duration, Instant}};
 
 use crate::{
-    filesystem::{DefaultExportPathFormatter, PathCache, PathInterner},
+    checkpoint::{Checkpoint, CheckpointState, CheckpointWriter},
+    explain::{Explanation, ExplainTarget},
+    fetch::{peer::Peers, web_seed::WebSeeds, PieceFetcher},
+    filesystem::{ClientExportPathFormatter, DefaultExportPathFormatter, ReadOptions, FrozenPathCache, FrozenPathInterner, PathCache, PathInterner, ScanFilter, ScanIndex},
     metadata::{
-        build_raw_torrent_file_metadata, build_raw_torrent_piece_metadata, calculate_total_choices_for_piece, correct_export_file_length, discover_and_apply_searches, validate_export_file_length, TorrentProcessState
+        build_raw_torrent_file_metadata, build_raw_torrent_piece_metadata, calculate_total_choices_for_piece, calculate_zero_piece_hashes, correct_export_file_length, discover_and_apply_searches, estimate_search_space, validate_export_file_length, TorrentFileEntry, TorrentPieceEntry, TorrentProcessState
     },
-    solver::{executor, task::{PieceUpdate, SolverMetadata, Task}},
-    torrent::Torrent, writer::FileWriter,
+    plan::{build_plan, TorrentPlan},
+    recheck::{build_bitfields, recheck},
+    solver::{buffer_pool::BufferPool, cancellation::CancellationToken, devices::{split_device_node, DeviceLimits, DeviceScheduler}, executor, task::{PieceUpdate, SolverLimits, SolverMetadata, Task}},
+    torrent::{info::get_sha1_hexdigest, Torrent},
+    verify::{build_verification, DataLayout, TorrentVerification},
+    writer::FileWriter,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PieceOrder {
+    // Pieces spanning the fewest files are solved first.
+    #[default]
+    FileCount,
+    // Pieces are solved in the order their first candidate is stored on disk, by device, file and offset, so that
+    // every candidate file is read roughly sequentially. Candidate files are opened with sequential access hints.
+    Locality
+}
+
 pub struct OrchestratorOptions {
     pub torrents: Vec<Torrent>,
     pub scan_directories: Vec<PathBuf>,
+    // Limits the files indexed under the scan directories, files under the export directory are always indexed.
+    pub scan_filter: ScanFilter,
+    // Number of threads reading the scan directories in parallel while indexing.
+    pub index_threads: usize,
+    // Scan index that is refreshed and saved after the scan directories are indexed, so later runs only read the
+    // directories that changed.
+    pub index_file: Option<PathBuf>,
+    // Scan index kept in memory between runs, such as by the watch command, and refreshed like the index file. The
+    // index file is only loaded while the index in memory is empty.
+    pub scan_index: Option<Arc<Mutex<ScanIndex>>>,
     pub export_directory: PathBuf,
     pub threads: usize,
-    pub resize_export_files: bool
+    pub resize_export_files: bool,
+    // Pieces recorded in the checkpoint are skipped, and newly finished pieces are appended to it.
+    pub checkpoint: Option<PathBuf>,
+    pub cancellation: CancellationToken,
+    // Pieces that try more combinations, or take longer than this, are reported as exhausted.
+    pub max_combinations_per_piece: Option<u64>,
+    pub max_time_per_piece: Option<Duration>,
+    // Upper bound on candidate files kept open across all solver threads. Set to 0 to open a file for every read.
+    pub max_open_files: usize,
+    // Concurrent candidate reads allowed on each rotational and non-rotational device, None leaves the devices unlimited.
+    // Devices that cannot be detected use the non-rotational limit.
+    pub rotational_concurrency: Option<usize>,
+    pub non_rotational_concurrency: Option<usize>,
+    // Overrides the concurrency of the device holding each path.
+    pub device_concurrency: Vec<(PathBuf, usize)>,
+    pub piece_order: PieceOrder,
+    // Bytes the kernel is asked to read ahead of every candidate read when pieces are ordered by locality.
+    pub read_ahead: u64,
+    // Single-file pieces are hashed directly from a memory mapping of the candidate, falling back to regular reads
+    // for files that cannot be mapped. Mappings are kept with the cached file handles, so this requires max_open_files
+    // to be above 0, and candidates must not be truncated during the run.
+    pub mmap: bool,
+    // Number of solved pieces that can be queued for the writer before the solvers wait.
+    pub pipeline_depth: usize,
+    // Pieces are first rechecked directly against the export files, and only the pieces that fail are searched.
+    pub verify_first: bool,
+    // Pieces that could not be found on disk are fetched from the web seeds listed in their torrent.
+    pub web_seeds: bool,
+    // Timeout for connecting to a web seed and for every read from it.
+    pub web_seed_timeout: Duration,
+    // Peers that the pieces which could not be found on disk, or on a web seed, are requested from.
+    pub peers: Vec<SocketAddr>,
+    // Timeout for connecting to a peer and for every message from it.
+    pub peer_timeout: Duration
+}
+
+impl OrchestratorOptions {
+    pub fn new(torrents: Vec<Torrent>, scan_directories: Vec<PathBuf>, export_directory: PathBuf) -> OrchestratorOptions {
+        OrchestratorOptions {
+            torrents,
+            scan_directories,
+            scan_filter: ScanFilter::default(),
+            index_threads: 4,
+            index_file: None,
+            scan_index: None,
+            export_directory,
+            threads: 1,
+            resize_export_files: false,
+            checkpoint: None,
+            cancellation: CancellationToken::new(),
+            max_combinations_per_piece: None,
+            max_time_per_piece: None,
+            max_open_files: 256,
+            rotational_concurrency: Some(1),
+            non_rotational_concurrency: None,
+            device_concurrency: Vec::new(),
+            piece_order: PieceOrder::FileCount,
+            read_ahead: 8 * 1024 * 1024,
+            mmap: false,
+            pipeline_depth: 4,
+            verify_first: false,
+            web_seeds: false,
+            web_seed_timeout: Duration::from_secs(30),
+            peers: Vec::new(),
+            peer_timeout: Duration::from_secs(30)
+        }
+    }
 }
 
-pub fn start(mut options: OrchestratorOptions) -> Result<(), std::io::Error> {
+fn build_device_limits(options: &OrchestratorOptions) -> Result<DeviceLimits, std::io::Error> {
+    let mut overrides = HashMap::new();
+    for (path, limit) in options.device_concurrency.iter() {
+        if *limit == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Device concurrency for {:#?} cannot be set to 0.", path)));
+        }
+
+        let metadata = fs::metadata(path)?;
+        overrides.insert(metadata.dev(), *limit);
+    }
+
+    if options.rotational_concurrency == Some(0) || options.non_rotational_concurrency == Some(0) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Device concurrency cannot be set to 0."));
+    }
+
+    Ok(DeviceLimits {
+        rotational: options.rotational_concurrency,
+        non_rotational: options.non_rotational_concurrency,
+        overrides
+    })
+}
+
+// The torrent metadata with the on-disk candidates discovered for every torrent file and piece.
+pub struct Discovery {
+    pub torrent_files: Vec<TorrentFileEntry>,
+    pub torrent_pieces: Vec<TorrentPieceEntry>,
+    pub path_interner: FrozenPathInterner,
+    pub path_cache: FrozenPathCache
+}
+
+// Solves every piece of every torrent, and returns the final counters of the run.
+pub fn start(mut options: OrchestratorOptions) -> Result<TorrentProcessState, std::io::Error> {
     let options = &mut options;
 
     if options.torrents.len() == 0 {
-        return Ok(());
+        return Ok(TorrentProcessState::new(0));
     }
 
     if options.threads == 0 {
         return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Thread count cannot be set to 0."));
     }
 
-    options.threads = std::cmp::max(options.threads, 1);
+    if options.pipeline_depth == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Pipeline depth cannot be set to 0."));
+    }
 
-    let now = Instant::now();
-    validate_input_paths(options)?;
+    if options.mmap && options.max_open_files == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Memory mapped reads require at least 1 open file."));
+    }
 
-    // Make sure we don't have duplicate torrents
-    let initial_torrent_count = options.torrents.len();
-    let torrents = &mut options.torrents;
-    
-    torrents.sort_by(|a, b| {
-        a.info_hash.cmp(&b.info_hash)
-    });
-    
-    torrents.dedup_by(|a, b| {
-        a.info_hash.cmp(&b.info_hash).is_eq()
-    });
+    validate_index_threads(options.index_threads)?;
 
-    if torrents.len() != initial_torrent_count {
-        println!("Removed {} duplicated torrents from the input list.", initial_torrent_count - torrents.len());
-    }
+    options.threads = std::cmp::max(options.threads, 1);
+
+    let now = Instant::now();
+    let discovery = discover(options, options.resize_export_files)?;
 
+    let torrents = &options.torrents;
     let torrents_len = torrents.len();
 
-    // Setup required metadata for processing
-    let mut path_interner = PathInterner::new();
+    let Discovery { torrent_files: torrent_file_metadata, torrent_pieces: torrent_piece_metadata, path_interner, path_cache } = discovery;
 
-    let mut torrent_file_metadata 
-        = build_raw_torrent_file_metadata::<DefaultExportPathFormatter>(torrents, &mut path_interner, &options.export_directory);
+    // Skip any pieces that finished processing during a previous run.
+    let checkpoint = match &options.checkpoint {
+        Some(path) => Checkpoint::load(path)?,
+        None => Checkpoint::empty()
+    };
 
-    for metadata_file in torrent_file_metadata.iter() {
-        validate_export_file_length(metadata_file, &path_interner, options.resize_export_files)?
-    }
+    let mut checkpoint_writer = match &options.checkpoint {
+        Some(path) => Some(CheckpointWriter::open(path)?),
+        None => None
+    };
 
-    if options.resize_export_files {
-        for metadata_file in torrent_file_metadata.iter() {
-            correct_export_file_length(metadata_file, &path_interner)?;
+    let mut items: Vec<usize> = Vec::with_capacity(torrent_piece_metadata.len());
+    for piece in torrent_piece_metadata.iter() {
+        let info_hash = &torrents[piece.torrent_id].info_hash;
+        if checkpoint.is_finished(info_hash, piece.position) {
+            continue;
         }
-    }
-
-    // Now that the files have been updated on disk, scan the user-provided scan directories
-    // and get cache the metadata related to the export files that were just updated.
-    let mut path_cache = PathCache::new();
 
-    for scan_directory in options.scan_directories.iter() {
-        path_cache.add_directory(&mut path_interner, &scan_directory);
+        items.push(piece.piece_id);
     }
 
-    for metadata_file in torrent_file_metadata.iter() {
-        path_cache.add_path_by_interner_id(&mut path_interner, metadata_file.export_target);
+    if items.len() != torrent_piece_metadata.len() {
+        println!("Skipping {} pieces that were already found according to the checkpoint.", torrent_piece_metadata.len() - items.len());
     }
 
-    // Freeze the data as we've stopped making modifications to disk-related content.
-    let path_cache = path_cache.freeze();
-    let path_interner = path_interner.freeze();
+    // Phase one, recheck the export files like a client would. Verified pieces are complete on disk already,
+    // so only the pieces that failed are searched.
+    let mut verified_pieces = 0;
+    if options.verify_first {
+        let verified = recheck(&torrent_file_metadata, &torrent_piece_metadata, &path_interner, &items, options.threads, &options.cancellation);
+        let bitfields = build_bitfields(torrents, &torrent_piece_metadata, &verified);
 
-    // Now, setup the search data that will be needed during processing.
-    discover_and_apply_searches(&mut torrent_file_metadata, &path_cache.entries, &path_interner);
+        for (torrent, bitfield) in torrents.iter().zip(bitfields.iter()) {
+            println!(
+                "Recheck of torrent {} verified {} of {} pieces, bitfield: {}",
+                get_sha1_hexdigest(&torrent.info_hash), bitfield.count_ones(), bitfield.len(), bitfield.to_hex()
+            );
+        }
 
-    // Build the piece metadata used for work-scheduling
-    let mut torrent_piece_metadata = build_raw_torrent_piece_metadata(torrents);
-    calculate_total_choices_for_piece(&mut torrent_file_metadata, &mut torrent_piece_metadata);
+        if let Some(checkpoint_writer) = checkpoint_writer.as_mut() {
+            for piece_id in items.iter().filter(|piece_id| verified[**piece_id]) {
+                let piece = &torrent_piece_metadata[*piece_id];
+                if let Err(err) = checkpoint_writer.record(&torrents[piece.torrent_id].info_hash, piece.position, CheckpointState::Success) {
+                    eprintln!("Failed to record piece in checkpoint: {:#?}", err);
+                }
+            }
+        }
 
-    let mut items: Vec<usize> = Vec::with_capacity(torrent_piece_metadata.len());
-    for piece in torrent_piece_metadata.iter() {
-        items.push(piece.piece_id);
-    }
+        let searched_pieces = items.len();
+        items.retain(|piece_id| !verified[*piece_id]);
+        verified_pieces = searched_pieces - items.len();
 
-    items.sort_by(|left, right| {
-        let left_piece = &torrent_piece_metadata[*left];
-        let right_piece = &torrent_piece_metadata[*right];
+        println!("Recheck finished at {} seconds, searching for {} remaining pieces.", now.elapsed().as_secs(), items.len());
+    }
 
-        left_piece.files.len().cmp(&right_piece.files.len())
-    });
+    let estimate = estimate_search_space(&torrent_piece_metadata, &items, options.max_combinations_per_piece);
+    println!(
+        "Estimated search space is at most {} combinations across {} pieces. Pieces without candidates: {}, Pieces over the combination limit: {}", 
+        estimate.total_combinations, estimate.searchable_pieces, estimate.unsolvable_pieces, estimate.over_limit_pieces
+    );
+
+    match options.piece_order {
+        PieceOrder::FileCount => items.sort_by(|left, right| {
+            let left_piece = &torrent_piece_metadata[*left];
+            let right_piece = &torrent_piece_metadata[*right];
+
+            left_piece.files.len().cmp(&right_piece.files.len())
+        }),
+        // Pieces without candidates are placed last.
+        PieceOrder::Locality => items.sort_by_cached_key(|piece_id| {
+            let primary_candidate = torrent_piece_metadata[*piece_id].primary_candidate(&torrent_file_metadata);
+            match primary_candidate {
+                Some((path_id, read_start_position)) => (false, Some(path_cache.get(path_id).identity().clone()), read_start_position),
+                None => (true, None, 0)
+            }
+        })
+    }
 
     items.reverse();
 
+    let devices = DeviceScheduler::new(&path_cache, &build_device_limits(options)?);
+    for device in devices.devices() {
+        let (major, minor) = split_device_node(device.device_node);
+        match device.limit {
+            Some(limit) => println!("Device {}:{} ({}) is limited to {} concurrent reads.", major, minor, device.kind, limit),
+            None => println!("Device {}:{} ({}) is not limited.", major, minor, device.kind)
+        }
+    }
+
+    // Verified pieces count towards the progress as successful pieces that did not have to be written.
+    let mut process_state = TorrentProcessState::new(items.len() + verified_pieces);
+    process_state.success_pieces = verified_pieces;
+    process_state.ignored_pieces = verified_pieces;
+
+    let zero_piece_hashes = calculate_zero_piece_hashes(&torrent_piece_metadata);
     let solver_metadata = SolverMetadata {
         torrent_files: torrent_file_metadata,
         torrent_pieces: torrent_piece_metadata,
         path_interner,
-        counter: Mutex::new(TorrentProcessState::new(items.len()))
+        path_cache,
+        counter: Mutex::new(process_state),
+        limits: SolverLimits {
+            max_combinations: options.max_combinations_per_piece,
+            max_duration: options.max_time_per_piece
+        },
+        devices,
+        // Every thread holds one buffer while solving, and every queued update holds another.
+        buffers: BufferPool::new(options.threads + options.pipeline_depth),
+        zero_piece_hashes
     };
 
     let solver_metadata = Arc::new(solver_metadata);
@@ -119,14 +291,26 @@ pub fn start(mut options: OrchestratorOptions) -> Result<(), std::io::Error> {
 
     // Setup Writer
     let mut writer = FileWriter::new(solver_metadata.clone());
+    let info_hashes: Vec<Vec<u8>> = torrents.iter().map(|torrent| torrent.info_hash.clone()).collect();
+    let shared_metadata = solver_metadata.clone();
 
-    let (sender, receiver) = std::sync::mpsc::sync_channel::<PieceUpdate>(1);
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<PieceUpdate>(options.pipeline_depth);
     let writer_thread = std::thread::spawn(move || {
 
         let solver_metadata = solver_metadata.clone();
-        let global_state = &solver_metadata.counter;
+        let mut unsolved_pieces = Vec::new();
 
         while let Ok(mut result) = receiver.recv() {
+            // Report any candidates that could not be read while solving the piece
+            for fault in result.faults.iter() {
+                let piece = &solver_metadata.torrent_pieces[result.piece_id];
+                eprintln!(
+                    "Unable to read candidate {:#?} for piece {} of torrent {}: {}", 
+                    solver_metadata.path_interner.get(fault.path), piece.position, 
+                    get_sha1_hexdigest(&info_hashes[piece.torrent_id]), fault.message
+                );
+            }
+
             // Write to disk
             let mut wrote_to_disk = false;
 
@@ -148,6 +332,11 @@ pub fn start(mut options: OrchestratorOptions) -> Result<(), std::io::Error> {
                 }
             }
 
+            // Return the buffer so the solvers can reuse it for the next match.
+            if let Some(output_bytes) = result.output_bytes.take() {
+                solver_metadata.buffers.put(output_bytes);
+            }
+
             /*
             // Print a message if all pieces for a file are finished processing
             for file in &result.piece.files {
@@ -170,50 +359,433 @@ pub fn start(mut options: OrchestratorOptions) -> Result<(), std::io::Error> {
             */
 
             // Print out the global processing status
-            let mut global_state = global_state
-                .lock()
-                .expect("Process state should always lock.");
-
-            global_state.success_pieces += (result.found && !result.fault) as usize;
-            global_state.failed_pieces += (!result.found && !result.fault) as usize;
-            global_state.fault_pieces += (result.fault) as usize;
-            global_state.writable_pieces += (wrote_to_disk) as usize;
-            global_state.ignored_pieces += (!wrote_to_disk) as usize;
+            let global_state = record_piece_update(&solver_metadata, &result, wrote_to_disk);
+
+            if !result.found || result.fault {
+                let unsolved = if result.fault {
+                    UnsolvedPiece::Faulted
+                } else if result.exhausted {
+                    UnsolvedPiece::Exhausted
+                } else {
+                    UnsolvedPiece::Failed
+                };
+
+                unsolved_pieces.push((result.piece_id, unsolved));
+            }
 
-            let availability = (global_state.success_pieces as f64 / global_state.total_pieces as f64) * 100_f64;
-            let processed = global_state.success_pieces + global_state.failed_pieces + global_state.fault_pieces;
-            let scanned = (processed as f64 / global_state.total_pieces as f64) * 100_f64;
-        
-            println!(
-                "Availability: {:.03}%, Scanned: {:.03}% - Success: {}, Failed: {}, Faulted: {}, Written: {}, Ignored: {} Total: {} of {}", 
-                availability, scanned, global_state.success_pieces, global_state.failed_pieces, global_state.fault_pieces, 
-                global_state.writable_pieces, global_state.ignored_pieces, processed, global_state.total_pieces
-            );
+            // Only found pieces are recorded. Every other piece is searched again on the next run, as the scan
+            // directories may have changed in the meantime.
+            if let Some(checkpoint_writer) = checkpoint_writer.as_mut() && result.found && !result.fault {
+                let piece = &solver_metadata.torrent_pieces[result.piece_id];
+                if let Err(err) = checkpoint_writer.record(&info_hashes[piece.torrent_id], piece.position, CheckpointState::Success) {
+                    eprintln!("Failed to record piece in checkpoint: {:#?}", err);
+                }
+            }
 
+            print_process_state(&global_state);
         }
+
+        (writer, checkpoint_writer, unsolved_pieces)
     });
 
     // Start processing the work
     println!("Solver threads started at {} seconds.", now.elapsed().as_secs());
 
-    executor::run(tasks, options.threads, sender);
+    let read_options = match options.piece_order {
+        PieceOrder::FileCount => ReadOptions { sequential: false, read_ahead: 0, mmap: options.mmap },
+        PieceOrder::Locality => ReadOptions { sequential: true, read_ahead: options.read_ahead, mmap: options.mmap }
+    };
+
+    executor::run(tasks, options.threads, options.max_open_files, read_options, options.cancellation.clone(), sender);
+
+    let (mut writer, mut checkpoint_writer, mut unsolved_pieces) = writer_thread.join().expect("Writer thread should not crash.");
+
+    // Phase three, fetch the pieces that could not be found on disk from the web seeds of their torrent, and then
+    // the remaining pieces from the peers.
+    if !unsolved_pieces.is_empty() && !options.cancellation.is_cancelled() {
+        let mut fetchers: Vec<Box<dyn PieceFetcher>> = Vec::new();
+        if options.web_seeds {
+            fetchers.push(Box::new(WebSeeds::new(&options.torrents, options.web_seed_timeout)));
+        }
+
+        if !options.peers.is_empty() {
+            fetchers.push(Box::new(Peers::new(&options.peers, &options.torrents, options.peer_timeout)));
+        }
+
+        for fetcher in fetchers.iter_mut() {
+            if unsolved_pieces.is_empty() || options.cancellation.is_cancelled() {
+                break;
+            }
 
-    writer_thread.join().expect("Writer thread should not crash.");
+            fetch_missing_pieces(options, &shared_metadata, fetcher.as_mut(), &mut unsolved_pieces, &mut writer, checkpoint_writer.as_mut());
+        }
+    }
 
     let elapsed = now.elapsed().as_secs();
     println!("Orchestrator took {} seconds for {} torrents.", elapsed, torrents_len);
+
+    if options.cancellation.is_cancelled() {
+        let message = match &options.checkpoint {
+            Some(path) => format!("Processing was cancelled before all pieces finished. Finished pieces were recorded in checkpoint {:#?}.", path),
+            None => "Processing was cancelled before all pieces finished.".to_string()
+        };
+
+        return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, message));
+    }
+
+    let process_state = shared_metadata.counter
+        .lock()
+        .expect("Process state should always lock.");
+
+    Ok(process_state.clone())
+}
+
+fn print_process_state(state: &TorrentProcessState) {
+    let availability = (state.success_pieces as f64 / state.total_pieces as f64) * 100_f64;
+    let processed = state.success_pieces + state.failed_pieces + state.fault_pieces + state.exhausted_pieces;
+    let scanned = (processed as f64 / state.total_pieces as f64) * 100_f64;
+
+    println!(
+        "Availability: {:.03}%, Scanned: {:.03}% - Success: {}, Failed: {}, Faulted: {}, Exhausted: {}, Written: {}, Ignored: {} Total: {} of {}", 
+        availability, scanned, state.success_pieces, state.failed_pieces, state.fault_pieces, 
+        state.exhausted_pieces, state.writable_pieces, state.ignored_pieces, processed, state.total_pieces
+    );
+}
+
+// How the solvers reported a piece that was not found, so the counters can be corrected when it is fetched later.
+#[derive(Debug, Clone, Copy)]
+enum UnsolvedPiece {
+    Failed,
+    Faulted,
+    Exhausted
+}
+
+// Counts a piece reported by the solvers in the global state, and in the state of every file the piece reads from.
+// Returns the global state, so it can be printed.
+fn record_piece_update<'a>(solver_metadata: &'a SolverMetadata, result: &PieceUpdate, wrote_to_disk: bool) -> MutexGuard<'a, TorrentProcessState> {
+    let mut global_state = solver_metadata.counter
+        .lock()
+        .expect("Process state should always lock.");
+
+    global_state.record_piece(result.found, result.fault, result.exhausted, wrote_to_disk);
+    for file_id in piece_file_ids(&solver_metadata.torrent_pieces[result.piece_id]) {
+        solver_metadata.torrent_files[file_id].processing_state
+            .lock()
+            .expect("Process state should always lock.")
+            .record_piece(result.found, result.fault, result.exhausted, wrote_to_disk);
+    }
+
+    global_state
+}
+
+// The files a piece reads from, each listed once.
+fn piece_file_ids(piece: &TorrentPieceEntry) -> BTreeSet<usize> {
+    piece.files.iter().map(|file| file.file_id).collect()
+}
+
+// Moves a piece that was reported as unsolved to the successful pieces, once it has been fetched.
+fn record_fetched_piece(state: &mut TorrentProcessState, unsolved: UnsolvedPiece, wrote_to_disk: bool) {
+    match unsolved {
+        UnsolvedPiece::Failed => state.failed_pieces -= 1,
+        UnsolvedPiece::Faulted => state.fault_pieces -= 1,
+        UnsolvedPiece::Exhausted => state.exhausted_pieces -= 1
+    }
+
+    state.success_pieces += 1;
+    state.writable_pieces += wrote_to_disk as usize;
+    state.ignored_pieces -= wrote_to_disk as usize;
+}
+
+// Requests every unsolved piece the fetcher can fetch. Fetched pieces match the piece hash, and are written and
+// recorded in the checkpoint like solved pieces. Fetched pieces are removed from the unsolved pieces.
+fn fetch_missing_pieces(
+    options: &OrchestratorOptions,
+    solver_metadata: &SolverMetadata,
+    fetcher: &mut dyn PieceFetcher,
+    unsolved_pieces: &mut Vec<(usize, UnsolvedPiece)>,
+    writer: &mut FileWriter,
+    mut checkpoint_writer: Option<&mut CheckpointWriter>
+) {
+    let pieces: Vec<(usize, UnsolvedPiece)> = unsolved_pieces.iter()
+        .filter(|(piece_id, _)| fetcher.can_fetch(solver_metadata.torrent_pieces[*piece_id].torrent_id))
+        .copied()
+        .collect();
+
+    if pieces.is_empty() {
+        println!("None of the {} missing pieces can be fetched from {}.", unsolved_pieces.len(), fetcher.description());
+        return;
+    }
+
+    println!("Fetching {} missing pieces from {}.", pieces.len(), fetcher.description());
+
+    let mut fetched_pieces = HashSet::new();
+    for (piece_id, unsolved) in pieces.iter() {
+        if options.cancellation.is_cancelled() {
+            break;
+        }
+
+        let piece = &solver_metadata.torrent_pieces[*piece_id];
+        let Some(bytes) = fetcher.fetch_piece(piece, &solver_metadata.torrent_files) else {
+            continue;
+        };
+
+        // Fetched bytes do not come from any path on disk, so every file of the piece is written.
+        let wrote_to_disk = match writer.write(*piece_id, &vec![None; piece.files.len()], &bytes) {
+            Ok(wrote_to_disk) => wrote_to_disk,
+            Err(err) => {
+                eprintln!("Failed to write piece to disk: {:#?}", err);
+                continue;
+            }
+        };
+
+        fetched_pieces.insert(*piece_id);
+
+        let mut global_state = solver_metadata.counter
+            .lock()
+            .expect("Process state should always lock.");
+
+        record_fetched_piece(&mut global_state, *unsolved, wrote_to_disk);
+        for file_id in piece_file_ids(piece) {
+            let mut file_state = solver_metadata.torrent_files[file_id].processing_state
+                .lock()
+                .expect("Process state should always lock.");
+
+            record_fetched_piece(&mut file_state, *unsolved, wrote_to_disk);
+        }
+
+        if let Some(checkpoint_writer) = checkpoint_writer.as_mut() {
+            let info_hash = &options.torrents[piece.torrent_id].info_hash;
+            if let Err(err) = checkpoint_writer.record(info_hash, piece.position, CheckpointState::Success) {
+                eprintln!("Failed to record piece in checkpoint: {:#?}", err);
+            }
+        }
+
+        print_process_state(&global_state);
+    }
+
+    println!("Fetched {} of {} missing pieces from {}.", fetched_pieces.len(), pieces.len(), fetcher.description());
+    unsolved_pieces.retain(|(piece_id, _)| !fetched_pieces.contains(piece_id));
+}
+
+// Runs discovery without solving, and reports the search space for every torrent and piece.
+pub fn plan(mut options: OrchestratorOptions, explosive_threshold: u64) -> Result<Vec<TorrentPlan>, std::io::Error> {
+    if options.torrents.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    validate_index_threads(options.index_threads)?;
+
+    // Planning must never modify files on disk, so export files are not resized.
+    let discovery = discover(&mut options, false)?;
+    Ok(build_plan(&options.torrents, &discovery, explosive_threshold))
+}
+
+// Runs discovery for a single torrent and explains how the solver handles one of its pieces or files.
+pub fn explain(mut options: OrchestratorOptions, target: ExplainTarget, max_combinations: u64) -> Result<Explanation, std::io::Error> {
+    if options.torrents.len() != 1 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Explain requires exactly one torrent."));
+    }
+
+    validate_index_threads(options.index_threads)?;
+
+    let discovery = discover(&mut options, false)?;
+    crate::explain::explain(&options.torrents[0], &discovery, &target, max_combinations)
+}
+
+// Rechecks every piece of every torrent against the data in the export directory, without searching for candidates
+// or writing anything. The scan directories are not used.
+pub fn verify(mut options: OrchestratorOptions, layout: DataLayout) -> Result<Vec<TorrentVerification>, std::io::Error> {
+    if options.threads == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Thread count cannot be set to 0."));
+    }
+
+    options.export_directory = validate_path(&options.export_directory)?;
+    remove_duplicate_torrents(&mut options.torrents);
+
+    let torrents = &options.torrents;
+    let mut path_interner = PathInterner::new();
+    let torrent_files = match layout {
+        DataLayout::Client => build_raw_torrent_file_metadata::<ClientExportPathFormatter>(torrents, &mut path_interner, &options.export_directory),
+        DataLayout::InfoHash => build_raw_torrent_file_metadata::<DefaultExportPathFormatter>(torrents, &mut path_interner, &options.export_directory)
+    };
+
+    let path_interner = path_interner.freeze();
+    let torrent_pieces = build_raw_torrent_piece_metadata(torrents);
+
+    let piece_ids: Vec<usize> = torrent_pieces.iter().map(|piece| piece.piece_id).collect();
+    let verified = recheck(&torrent_files, &torrent_pieces, &path_interner, &piece_ids, options.threads, &options.cancellation);
+
+    if options.cancellation.is_cancelled() {
+        return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Verification was cancelled before all pieces were checked."));
+    }
+
+    Ok(build_verification(torrents, &torrent_files, &torrent_pieces, &path_interner, &verified))
+}
+
+fn remove_duplicate_torrents(torrents: &mut Vec<Torrent>) {
+    let initial_torrent_count = torrents.len();
+
+    torrents.sort_by(|a, b| {
+        a.info_hash.cmp(&b.info_hash)
+    });
+    
+    torrents.dedup_by(|a, b| {
+        a.info_hash.cmp(&b.info_hash).is_eq()
+    });
+
+    if torrents.len() != initial_torrent_count {
+        println!("Removed {} duplicated torrents from the input list.", initial_torrent_count - torrents.len());
+    }
+}
+
+// Removes duplicated torrents, validates the input paths and discovers the candidates for every torrent file
+// and piece. Export files are only resized when resize_export_files is set.
+fn discover(options: &mut OrchestratorOptions, resize_export_files: bool) -> Result<Discovery, std::io::Error> {
+    validate_input_paths(options)?;
+
+    // Make sure we don't have duplicate torrents
+    remove_duplicate_torrents(&mut options.torrents);
+    let torrents = &options.torrents;
+
+    // Setup required metadata for processing
+    let mut path_interner = PathInterner::new();
+
+    let mut torrent_file_metadata 
+        = build_raw_torrent_file_metadata::<DefaultExportPathFormatter>(torrents, &mut path_interner, &options.export_directory);
+
+    for metadata_file in torrent_file_metadata.iter() {
+        validate_export_file_length(metadata_file, &path_interner, resize_export_files)?
+    }
+
+    if resize_export_files {
+        for metadata_file in torrent_file_metadata.iter() {
+            correct_export_file_length(metadata_file, &path_interner)?;
+        }
+    }
+
+    // Now that the files have been updated on disk, scan the user-provided scan directories
+    // and get cache the metadata related to the export files that were just updated.
+    let mut path_cache = PathCache::with_filter(options.scan_filter.clone(), options.index_threads);
+    index_scan_directories(
+        &mut path_cache, &mut path_interner, &options.scan_directories, options.index_file.as_deref(), options.scan_index.as_deref()
+    )?;
+
+    for metadata_file in torrent_file_metadata.iter() {
+        path_cache.add_path_by_interner_id(&mut path_interner, metadata_file.export_target);
+    }
+
+    // Freeze the data as we've stopped making modifications to disk-related content.
+    let path_cache = path_cache.freeze();
+    let path_interner = path_interner.freeze();
+
+    // Now, setup the search data that will be needed during processing.
+    discover_and_apply_searches(&mut torrent_file_metadata, &path_cache.entries, &path_interner);
+
+    // Build the piece metadata used for work-scheduling
+    let mut torrent_piece_metadata = build_raw_torrent_piece_metadata(torrents);
+    calculate_total_choices_for_piece(&mut torrent_file_metadata, &mut torrent_piece_metadata);
+
+    Ok(Discovery {
+        torrent_files: torrent_file_metadata,
+        torrent_pieces: torrent_piece_metadata,
+        path_interner,
+        path_cache
+    })
+}
+
+// Indexes the scan directories without any torrents, only refreshing and saving the scan index.
+pub fn index(scan_directories: &[PathBuf], scan_filter: ScanFilter, index_threads: usize, index_file: &Path) -> Result<(), std::io::Error> {
+    validate_index_threads(index_threads)?;
+
+    let mut scan_directories = scan_directories.to_vec();
+    for scan_directory in scan_directories.iter_mut() {
+        *scan_directory = validate_path(scan_directory)?;
+    }
+
+    let mut path_cache = PathCache::with_filter(scan_filter, index_threads);
+    let mut path_interner = PathInterner::new();
+    index_scan_directories(&mut path_cache, &mut path_interner, &scan_directories, Some(index_file), None)
+}
+
+fn index_scan_directories(
+    path_cache: &mut PathCache,
+    path_interner: &mut PathInterner,
+    scan_directories: &[PathBuf],
+    index_file: Option<&Path>,
+    scan_index: Option<&Mutex<ScanIndex>>
+) -> Result<(), std::io::Error> {
+    let mut index = scan_index.map(|scan_index| {
+        let mut scan_index = scan_index.lock().expect("Scan index should always lock.");
+        std::mem::replace(&mut *scan_index, ScanIndex::empty())
+    });
+
+    // A scan index that cannot be read is only a cache, so the scan directories are read in full instead.
+    if let Some(index_file) = index_file && index.as_ref().is_none_or(|index| index.is_empty()) {
+        match ScanIndex::load(index_file) {
+            Ok(loaded) => index = Some(loaded),
+            Err(err) => {
+                eprintln!("Ignoring scan index that could not be loaded: {}", err);
+                index = Some(ScanIndex::empty());
+            }
+        }
+    }
+
+    if let Some(index) = index {
+        path_cache.set_index(index);
+    }
+
+    for scan_directory in scan_directories.iter() {
+        let now = Instant::now();
+        let progress = path_cache.add_directory_with_progress(path_interner, scan_directory, &|progress| {
+            println!("Indexing {:#?}: {} files in {} directories so far.", scan_directory, progress.files, progress.directories);
+        });
+
+        // The shared index was taken out of its mutex, and is put back so the next run can still reuse it.
+        let progress = match progress {
+            Ok(progress) => progress,
+            Err(err) => {
+                if let Some(scan_index) = scan_index && let Some(index) = path_cache.take_index() {
+                    *scan_index.lock().expect("Scan index should always lock.") = index;
+                }
+
+                return Err(err);
+            }
+        };
+
+        match progress {
+            Some(progress) => println!(
+                "Indexed {} files in {} directories under {:#?} in {} seconds, {} directories were unchanged, with {} errors.",
+                progress.files, progress.directories, scan_directory, now.elapsed().as_secs(), progress.reused_directories, progress.errors
+            ),
+            None => println!("Skipped indexing {:#?} as it is within another scan path.", scan_directory)
+        }
+    }
+
+    if let Some(index) = path_cache.take_index() {
+        if let Some(index_file) = index_file {
+            index.save(index_file)?;
+            println!("Saved scan index with {} scan paths to {:#?}.", index.len(), index_file);
+        }
+
+        if let Some(scan_index) = scan_index {
+            *scan_index.lock().expect("Scan index should always lock.") = index;
+        }
+    }
+
     Ok(())
 }
 
-fn validate_path(path: &PathBuf) -> Result<(), std::io::Error> {
-    if !path.is_absolute() {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            format!("{:#?} path must be absolute.", &path),
-        ))?
+// Returns the canonical form of a directory path, relative paths are resolved against the working directory
+// and symlinks are resolved, so the same directory is always interned under the same path.
+fn validate_path(path: &Path) -> Result<PathBuf, std::io::Error> {
+    let canonical_path = fs::canonicalize(path);
+
+    if let Err(error) = &canonical_path {
+        Err(std::io::Error::new(error.kind(), format!("Encountered error while resolving path {:#?}: {}", path, error)))?;
     }
 
-    let metadata = fs::metadata(path);
+    let canonical_path = canonical_path.unwrap();
+    let metadata = fs::metadata(&canonical_path);
 
     if let Err(error) = &metadata {
         Err(std::io::Error::new(error.kind(), format!("Encountered error while reading metadata for path {:#?}: {}", path, error)))?;
@@ -225,15 +797,161 @@ fn validate_path(path: &PathBuf) -> Result<(), std::io::Error> {
         Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{:#?} is not a directory.", path)))?
     }
 
+    Ok(canonical_path)
+}
+
+fn validate_index_threads(index_threads: usize) -> Result<(), std::io::Error> {
+    if index_threads == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Index thread count cannot be set to 0."));
+    }
+
     Ok(())
 }
 
-fn validate_input_paths(options: &OrchestratorOptions) -> Result<(), std::io::Error> {
-    // Make sure all input scan paths are absolute and are proper directories
-    for scan_directory in options.scan_directories.iter() {
-        validate_path(scan_directory)?;
+fn validate_input_paths(options: &mut OrchestratorOptions) -> Result<(), std::io::Error> {
+    // Make sure all input scan paths are proper directories, and replace them with their canonical paths.
+    for scan_directory in options.scan_directories.iter_mut() {
+        *scan_directory = validate_path(scan_directory)?;
     }
 
     // Same thing as above, but for the export path.
-    validate_path(&options.export_directory)
+    options.export_directory = validate_path(&options.export_directory)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{filesystem::{DataSource, MemorySource}, test_support::{build_solver_metadata, build_torrent, serve_directory, solve, TempDir}};
+
+    use super::*;
+
+    #[test]
+    fn record_piece_update_should_count_exhausted_pieces_per_file() {
+        let torrent = build_torrent(&[("first.bin", b"abcd"), ("second.bin", b"efgh")], 8);
+        let sources: Vec<(PathBuf, Box<dyn DataSource>)> = vec![
+            (PathBuf::from("/candidates/first"), Box::new(MemorySource::new("first", b"abcd".to_vec()))),
+            (PathBuf::from("/candidates/second"), Box::new(MemorySource::new("second", b"wxyz".to_vec()))),
+        ];
+
+        let limits = SolverLimits { max_combinations: Some(1), max_duration: None };
+        let solver_metadata = build_solver_metadata(&[torrent], Path::new("/export"), sources, limits);
+
+        let updates = solve(&solver_metadata);
+        assert_eq!(1, updates.len());
+        assert!(updates[0].exhausted);
+
+        let global_state = record_piece_update(&solver_metadata, &updates[0], false);
+        assert_eq!(1, global_state.exhausted_pieces);
+        assert_eq!(1, global_state.ignored_pieces);
+        drop(global_state);
+
+        for file in solver_metadata.torrent_files.iter() {
+            let processing_state = file.processing_state.lock().unwrap();
+            assert_eq!(1, processing_state.exhausted_pieces);
+            assert_eq!(0, processing_state.failed_pieces);
+        }
+    }
+
+    #[test]
+    fn fetch_missing_pieces_should_write_count_and_checkpoint_fetched_pieces() {
+        let directory = TempDir::new("orchestrator_fetch");
+        let files: [(&str, &[u8]); 3] = [("first.bin", b"abcdef"), ("second.bin", b"ghijkl"), ("third.bin", b"mnop")];
+        let mut torrent = build_torrent(&files, 4);
+
+        // The seed is missing the third file, so the last piece cannot be fetched.
+        let seed_directory = directory.join("seed").join(&torrent.info.name);
+        std::fs::create_dir_all(&seed_directory).unwrap();
+        for (name, bytes) in &files[..2] {
+            std::fs::write(seed_directory.join(name), bytes).unwrap();
+        }
+
+        torrent.url_list = vec![serve_directory(&directory.join("seed"))];
+
+        let export_directory = directory.join("export");
+        let solver_metadata = build_solver_metadata(std::slice::from_ref(&torrent), &export_directory, Vec::new(), SolverLimits::default());
+
+        let updates = solve(&solver_metadata);
+        let mut unsolved_pieces: Vec<(usize, UnsolvedPiece)> = updates.iter()
+            .map(|update| {
+                drop(record_piece_update(&solver_metadata, update, false));
+                (update.piece_id, UnsolvedPiece::Failed)
+            })
+            .collect();
+
+        assert_eq!(4, unsolved_pieces.len());
+
+        let mut options = OrchestratorOptions::new(vec![torrent], Vec::new(), export_directory);
+        options.web_seeds = true;
+
+        let checkpoint_path = directory.join("checkpoint");
+        let mut checkpoint_writer = CheckpointWriter::open(&checkpoint_path).unwrap();
+        let mut writer = FileWriter::new(solver_metadata.clone());
+        let mut fetcher = WebSeeds::new(&options.torrents, Duration::from_secs(5));
+
+        fetch_missing_pieces(&options, &solver_metadata, &mut fetcher, &mut unsolved_pieces, &mut writer, Some(&mut checkpoint_writer));
+        drop(checkpoint_writer);
+
+        assert_eq!(vec![3], unsolved_pieces.iter().map(|(piece_id, _)| *piece_id).collect::<Vec<usize>>());
+
+        let global_state = solver_metadata.counter.lock().unwrap();
+        assert_eq!(3, global_state.success_pieces);
+        assert_eq!(1, global_state.failed_pieces);
+        assert_eq!(3, global_state.writable_pieces);
+        assert_eq!(1, global_state.ignored_pieces);
+        drop(global_state);
+
+        // The second piece spans the first and second file.
+        let file_states: Vec<(usize, usize)> = solver_metadata.torrent_files.iter()
+            .map(|file| {
+                let processing_state = file.processing_state.lock().unwrap();
+                (processing_state.success_pieces, processing_state.failed_pieces)
+            })
+            .collect();
+
+        assert_eq!(vec![(2, 0), (2, 0), (0, 1)], file_states);
+
+        for (file, (_, bytes)) in solver_metadata.torrent_files[..2].iter().zip(files.iter()) {
+            let export_path = solver_metadata.path_interner.get(file.export_target);
+            assert_eq!(bytes.to_vec(), std::fs::read(export_path).unwrap());
+        }
+
+        let checkpoint = Checkpoint::load(&checkpoint_path).unwrap();
+        let info_hash = &options.torrents[0].info_hash;
+        assert_eq!(3, checkpoint.len());
+        assert!((0..3).all(|position| checkpoint.is_finished(info_hash, position)));
+        assert!(!checkpoint.is_finished(info_hash, 3));
+    }
+
+    #[test]
+    fn index_scan_directories_should_restore_the_shared_index_on_error() {
+        let directory = TempDir::new("orchestrator_index");
+        let scan_directory = directory.join("scan");
+        std::fs::create_dir_all(&scan_directory).unwrap();
+        std::fs::write(scan_directory.join("data.bin"), b"abcd").unwrap();
+
+        let scan_index = Mutex::new(ScanIndex::empty());
+        let mut path_cache = PathCache::new();
+        let mut path_interner = PathInterner::new();
+        index_scan_directories(&mut path_cache, &mut path_interner, &[scan_directory], None, Some(&scan_index)).unwrap();
+        assert_eq!(1, scan_index.lock().unwrap().len());
+
+        let mut path_cache = PathCache::new();
+        let mut path_interner = PathInterner::new();
+        let result = index_scan_directories(&mut path_cache, &mut path_interner, &[directory.join("missing")], None, Some(&scan_index));
+
+        assert!(result.is_err());
+        assert_eq!(1, scan_index.lock().unwrap().len());
+    }
+
+    #[test]
+    fn start_should_reject_mmap_without_open_files() {
+        let torrent = build_torrent(&[("data.bin", b"abcd")], 4);
+        let mut options = OrchestratorOptions::new(vec![torrent], Vec::new(), PathBuf::from("/export"));
+        options.mmap = true;
+        options.max_open_files = 0;
+
+        assert_eq!(std::io::ErrorKind::InvalidInput, start(options).unwrap_err().kind());
+    }
 }
\ No newline at end of file
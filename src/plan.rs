@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use crate::{metadata::calculate_combinations, orchestrator::Discovery, torrent::Torrent};
+
+#[derive(Debug)]
+pub struct PiecePlan {
+    pub position: usize,
+    // Number of candidates for each file the piece spans. Padding files always have a single candidate.
+    pub candidates: Vec<usize>,
+    pub combinations: u64,
+    // Every candidate is read once per piece, so this is the sum of candidates times the bytes read from each file.
+    pub bytes_read: u64,
+    pub explosive: bool
+}
+
+impl PiecePlan {
+    pub fn unsolvable(&self) -> bool {
+        self.combinations == 0
+    }
+}
+
+#[derive(Debug)]
+pub struct TorrentPlan {
+    pub info_hash: Vec<u8>,
+    pub name: String,
+    pub pieces: Vec<PiecePlan>,
+    // Non-padding files without a single candidate on disk. No piece spanning these files can be solved.
+    pub files_without_candidates: Vec<PathBuf>,
+    pub total_combinations: u64,
+    pub total_bytes_read: u64
+}
+
+impl TorrentPlan {
+    pub fn explosive_pieces(&self) -> usize {
+        self.pieces.iter().filter(|piece| piece.explosive).count()
+    }
+
+    pub fn unsolvable_pieces(&self) -> usize {
+        self.pieces.iter().filter(|piece| piece.unsolvable()).count()
+    }
+}
+
+// Pieces with more combinations than the explosive threshold are flagged, so they can be excluded or limited before solving.
+pub fn build_plan(torrents: &[Torrent], discovery: &Discovery, explosive_threshold: u64) -> Vec<TorrentPlan> {
+    let mut plans: Vec<TorrentPlan> = torrents.iter()
+        .map(|torrent| TorrentPlan {
+            info_hash: torrent.info_hash.clone(),
+            name: torrent.info.name.clone(),
+            pieces: Vec::with_capacity(torrent.info.pieces.len()),
+            files_without_candidates: Vec::new(),
+            total_combinations: 0,
+            total_bytes_read: 0
+        })
+        .collect();
+
+    for file in discovery.torrent_files.iter() {
+        if !file.padding && file.searches.is_none() {
+            let relative_target = discovery.path_interner.get(file.relative_target);
+            plans[file.torrent_id].files_without_candidates.push(relative_target.to_path_buf());
+        }
+    }
+
+    for piece in discovery.torrent_pieces.iter() {
+        let mut candidates = Vec::with_capacity(piece.files.len());
+        let mut bytes_read: u64 = 0;
+
+        for piece_file in piece.files.iter() {
+            let file = &discovery.torrent_files[piece_file.file_id];
+
+            if file.padding {
+                candidates.push(1);
+                continue;
+            }
+
+            let count = file.searches.as_ref().map_or(0, |searches| searches.len());
+            bytes_read = bytes_read.saturating_add((count as u64).saturating_mul(piece_file.read_length));
+            candidates.push(count);
+        }
+
+        let combinations = calculate_combinations(&candidates);
+
+        // The solver never reads a piece that has a file without candidates.
+        if combinations == 0 {
+            bytes_read = 0;
+        }
+
+        let plan = &mut plans[piece.torrent_id];
+        plan.total_combinations = plan.total_combinations.saturating_add(combinations);
+        plan.total_bytes_read = plan.total_bytes_read.saturating_add(bytes_read);
+        plan.pieces.push(PiecePlan {
+            position: piece.position,
+            candidates,
+            combinations,
+            bytes_read,
+            explosive: combinations > explosive_threshold
+        });
+    }
+
+    plans
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{filesystem::{DataSource, MemorySource}, test_support::{build_discovery, build_torrent}};
+
+    use super::*;
+
+    fn source(name: &str, length: usize) -> (PathBuf, Box<dyn DataSource>) {
+        (PathBuf::from(name), Box::new(MemorySource::new(name, vec![0xAA; length])))
+    }
+
+    #[test]
+    fn build_plan_should_count_candidates_combinations_and_bytes_read() {
+        // Pieces of 4 bytes: a[0..4], a[4..6] b[0..2], b[2..6], b[6..7] missing[0..3], missing[3..5]
+        let torrent = build_torrent(&[("a.bin", &[1; 6]), ("b.bin", &[2; 7]), ("missing.bin", &[3; 5])], 4);
+        let sources = vec![source("/candidates/a1", 6), source("/candidates/a2", 6), source("/candidates/b1", 7)];
+        let discovery = build_discovery(std::slice::from_ref(&torrent), Path::new("/export"), sources);
+
+        let plans = build_plan(&[torrent], &discovery, 1);
+        assert_eq!(1, plans.len());
+
+        let plan = &plans[0];
+        let candidates: Vec<Vec<usize>> = plan.pieces.iter().map(|piece| piece.candidates.clone()).collect();
+        let combinations: Vec<u64> = plan.pieces.iter().map(|piece| piece.combinations).collect();
+        let bytes_read: Vec<u64> = plan.pieces.iter().map(|piece| piece.bytes_read).collect();
+        let explosive: Vec<bool> = plan.pieces.iter().map(|piece| piece.explosive).collect();
+
+        assert_eq!(vec![vec![2], vec![2, 1], vec![1], vec![1, 0], vec![0]], candidates);
+        assert_eq!(vec![2, 2, 1, 0, 0], combinations);
+        // Pieces with a file without candidates are never read.
+        assert_eq!(vec![8, 6, 4, 0, 0], bytes_read);
+        assert_eq!(vec![true, true, false, false, false], explosive);
+
+        assert_eq!(5, plan.total_combinations);
+        assert_eq!(18, plan.total_bytes_read);
+        assert_eq!(2, plan.explosive_pieces());
+        assert_eq!(2, plan.unsolvable_pieces());
+
+        assert_eq!(1, plan.files_without_candidates.len());
+        assert!(plan.files_without_candidates[0].ends_with("missing.bin"));
+    }
+
+    #[test]
+    fn build_plan_should_not_flag_pieces_at_explosive_threshold() {
+        let torrent = build_torrent(&[("a.bin", &[1; 4])], 4);
+        let sources = vec![source("/candidates/a1", 4), source("/candidates/a2", 4)];
+        let discovery = build_discovery(std::slice::from_ref(&torrent), Path::new("/export"), sources);
+
+        let plans = build_plan(&[torrent], &discovery, 2);
+
+        assert_eq!(2, plans[0].pieces[0].combinations);
+        assert!(!plans[0].pieces[0].explosive);
+        assert!(plans[0].files_without_candidates.is_empty());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,114 @@
+use sha1::{Digest, Sha1};
+
+use crate::{
+    bitfield::Bitfield,
+    filesystem::{FileHandleCache, FrozenPathInterner, ReadOptions},
+    metadata::{TorrentFileEntry, TorrentPieceEntry},
+    solver::cancellation::CancellationToken,
+    torrent::Torrent
+};
+
+// Number of export files each recheck thread keeps open.
+const RECHECK_OPEN_FILES: usize = 16;
+
+// Hashes every piece directly against the export target of its files, like the recheck of a client. The pieces are
+// split into contiguous ranges, one for each thread, so every thread reads its part of the export files sequentially.
+// Returns whether each piece matched, indexed by piece id. Pieces that were not checked are reported as not matching.
+pub fn recheck(
+    torrent_files: &[TorrentFileEntry],
+    torrent_pieces: &[TorrentPieceEntry],
+    path_interner: &FrozenPathInterner,
+    piece_ids: &[usize],
+    threads: usize,
+    cancellation: &CancellationToken
+) -> Vec<bool> {
+    let mut piece_ids = piece_ids.to_vec();
+    piece_ids.sort();
+
+    let mut verified = vec![false; torrent_pieces.len()];
+    if piece_ids.is_empty() {
+        return verified;
+    }
+
+    let chunk_size = piece_ids.len().div_ceil(std::cmp::max(threads, 1));
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = piece_ids.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || recheck_range(torrent_files, torrent_pieces, path_interner, chunk, cancellation)))
+            .collect();
+
+        for handle in handles {
+            let matched = handle.join()
+                .expect("Encountered panic while joining on recheck thread.");
+
+            for piece_id in matched {
+                verified[piece_id] = true;
+            }
+        }
+    });
+
+    verified
+}
+
+fn recheck_range(
+    torrent_files: &[TorrentFileEntry],
+    torrent_pieces: &[TorrentPieceEntry],
+    path_interner: &FrozenPathInterner,
+    piece_ids: &[usize],
+    cancellation: &CancellationToken
+) -> Vec<usize> {
+    let mut handles = FileHandleCache::new(RECHECK_OPEN_FILES, ReadOptions { sequential: true, read_ahead: 0, mmap: false });
+    let mut hasher = Sha1::new();
+    let mut buffer = Vec::new();
+    let mut matched = Vec::new();
+
+    'pieces: for piece_id in piece_ids {
+        if cancellation.is_cancelled() {
+            break;
+        }
+
+        let piece = &torrent_pieces[*piece_id];
+        buffer.clear();
+
+        for piece_file in piece.files.iter() {
+            let file = &torrent_files[piece_file.file_id];
+
+            if file.padding {
+                buffer.resize(buffer.len() + piece_file.read_length as usize, 0);
+                continue;
+            }
+
+            // Missing or short export files simply fail the piece.
+            let path = path_interner.get(file.export_target);
+            let result = handles.read_into(
+                file.export_target, path, piece_file.read_length, piece_file.read_start_position, &mut buffer
+            );
+
+            if result.is_err() {
+                continue 'pieces;
+            }
+        }
+
+        hasher.update(&buffer);
+        if piece.hash.as_slice() == hasher.finalize_reset().as_slice() {
+            matched.push(*piece_id);
+        }
+    }
+
+    matched
+}
+
+// Builds the bitfield of every torrent from the recheck result.
+pub fn build_bitfields(torrents: &[Torrent], torrent_pieces: &[TorrentPieceEntry], verified: &[bool]) -> Vec<Bitfield> {
+    let mut bitfields: Vec<Bitfield> = torrents.iter()
+        .map(|torrent| Bitfield::new(torrent.info.pieces.len()))
+        .collect();
+
+    for piece in torrent_pieces.iter() {
+        if verified[piece.piece_id] {
+            bitfields[piece.torrent_id].set(piece.position);
+        }
+    }
+
+    bitfields
+}
\ No newline at end of file
@@ -0,0 +1,81 @@
+use std::sync::Mutex;
+
+// Recycled piece buffers shared by the solvers and the writer. A solver hands its output buffer to the writer
+// with a matching piece and takes a replacement from the pool, and the writer returns the buffer once the piece
+// has been written, so large pieces are not allocated and copied for every match.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    capacity: usize
+}
+
+impl BufferPool {
+    // Keeps at most capacity idle buffers, any buffers returned beyond that are freed.
+    pub fn new(capacity: usize) -> BufferPool {
+        BufferPool {
+            buffers: Mutex::new(Vec::with_capacity(capacity)),
+            capacity
+        }
+    }
+
+    // Returns an empty buffer, reusing the allocation of a returned buffer when one is available.
+    pub fn take(&self) -> Vec<u8> {
+        let mut buffers = self.buffers
+            .lock()
+            .expect("Buffer pool should always lock.");
+
+        buffers.pop().unwrap_or_default()
+    }
+
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+
+        let mut buffers = self.buffers
+            .lock()
+            .expect("Buffer pool should always lock.");
+
+        if buffers.len() < self.capacity {
+            buffers.push(buffer);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers
+            .lock()
+            .expect("Buffer pool should always lock.")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_should_reuse_returned_buffer() {
+        let pool = BufferPool::new(1);
+
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(&[1; 1024]);
+        let allocation = buffer.as_ptr();
+        pool.put(buffer);
+
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 1024);
+        assert_eq!(allocation, buffer.as_ptr());
+    }
+
+    #[test]
+    fn put_should_respect_capacity() {
+        let pool = BufferPool::new(1);
+
+        pool.put(vec![1; 16]);
+        pool.put(vec![2; 16]);
+
+        assert_eq!(1, pool.len());
+    }
+}
\ No newline at end of file
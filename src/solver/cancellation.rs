@@ -0,0 +1,24 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+// Shared flag used to ask a running solve to stop. Cloning the token shares the underlying
+// flag, so cancelling any clone is observed by the executor, the solvers and the orchestrator.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken {
+            cancelled: Arc::new(AtomicBool::new(false))
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,250 @@
+use std::{collections::HashMap, fmt::Display, sync::{Condvar, Mutex}};
+
+use crate::filesystem::FrozenPathCache;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Rotational,
+    NonRotational,
+    // Network filesystems, virtual filesystems and platforms without sysfs.
+    Unknown
+}
+
+impl Display for DeviceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceKind::Rotational => write!(f, "rotational"),
+            DeviceKind::NonRotational => write!(f, "non-rotational"),
+            DeviceKind::Unknown => write!(f, "unknown")
+        }
+    }
+}
+
+// Number of concurrent candidate reads allowed on each device. Devices of an unknown kind use the non-rotational limit,
+// and None leaves a device unlimited. Overrides are keyed by device node and take precedence over the detected kind.
+#[derive(Clone)]
+pub struct DeviceLimits {
+    pub rotational: Option<usize>,
+    pub non_rotational: Option<usize>,
+    pub overrides: HashMap<u64, usize>
+}
+
+impl Default for DeviceLimits {
+    fn default() -> Self {
+        DeviceLimits {
+            rotational: Some(1),
+            non_rotational: None,
+            overrides: HashMap::new()
+        }
+    }
+}
+
+pub struct DeviceInfo {
+    pub device_node: u64,
+    pub kind: DeviceKind,
+    pub limit: Option<usize>
+}
+
+struct DeviceSlots {
+    limit: usize,
+    in_use: Mutex<usize>,
+    available: Condvar
+}
+
+// Holds one of the read slots of a device, the slot is released when the permit is dropped.
+pub struct DevicePermit<'a> {
+    slots: &'a DeviceSlots
+}
+
+impl Drop for DevicePermit<'_> {
+    fn drop(&mut self) {
+        let mut in_use = self.slots.in_use
+            .lock()
+            .expect("Device slots should always lock.");
+
+        *in_use -= 1;
+        self.slots.available.notify_one();
+    }
+}
+
+// Limits the number of concurrent reads on every device holding candidates, so that spinning disks are read by a
+// single worker at a time instead of seeking between the reads of every worker. Only the read itself holds a slot,
+// hashing happens after the slot has been released.
+pub struct DeviceScheduler {
+    path_devices: HashMap<usize, u64>,
+    slots: HashMap<u64, DeviceSlots>,
+    devices: Vec<DeviceInfo>
+}
+
+impl DeviceScheduler {
+    // A scheduler that never limits reads.
+    pub fn unlimited() -> DeviceScheduler {
+        DeviceScheduler {
+            path_devices: HashMap::new(),
+            slots: HashMap::new(),
+            devices: Vec::new()
+        }
+    }
+
+    pub fn new(path_cache: &FrozenPathCache, limits: &DeviceLimits) -> DeviceScheduler {
+        let mut scheduler = Self::unlimited();
+
+        // Sources that are not read from a local device are never limited.
+        for (path_id, entry) in path_cache.entries.iter() {
+            if let Some(device_node) = entry.identity().device_node() {
+                scheduler.path_devices.insert(*path_id, device_node);
+            }
+        }
+
+        let mut device_nodes: Vec<u64> = scheduler.path_devices.values().copied().collect();
+        device_nodes.sort();
+        device_nodes.dedup();
+
+        for device_node in device_nodes {
+            let kind = detect_device_kind(device_node);
+            let limit = match limits.overrides.get(&device_node) {
+                Some(limit) => Some(*limit),
+                None if kind == DeviceKind::Rotational => limits.rotational,
+                None => limits.non_rotational
+            };
+
+            if let Some(limit) = limit {
+                scheduler.slots.insert(device_node, DeviceSlots {
+                    limit: std::cmp::max(limit, 1),
+                    in_use: Mutex::new(0),
+                    available: Condvar::new()
+                });
+            }
+
+            scheduler.devices.push(DeviceInfo { device_node, kind, limit });
+        }
+
+        scheduler
+    }
+
+    pub fn devices(&self) -> &[DeviceInfo] {
+        &self.devices
+    }
+
+    // Blocks until the device holding the path has a free read slot. Paths on unlimited devices do not need a permit.
+    pub fn acquire(&self, path_id: usize) -> Option<DevicePermit<'_>> {
+        let slots = self.slots_for(path_id)?;
+
+        let mut in_use = slots.in_use
+            .lock()
+            .expect("Device slots should always lock.");
+
+        while *in_use >= slots.limit {
+            in_use = slots.available
+                .wait(in_use)
+                .expect("Device slots should always lock.");
+        }
+
+        *in_use += 1;
+        Some(DevicePermit { slots })
+    }
+
+    // Returns true if a read of the path would not have to wait for a slot.
+    pub fn is_available(&self, path_id: usize) -> bool {
+        match self.slots_for(path_id) {
+            Some(slots) => {
+                let in_use = slots.in_use
+                    .lock()
+                    .expect("Device slots should always lock.");
+
+                *in_use < slots.limit
+            },
+            None => true
+        }
+    }
+
+    fn slots_for(&self, path_id: usize) -> Option<&DeviceSlots> {
+        let device_node = self.path_devices.get(&path_id)?;
+        self.slots.get(device_node)
+    }
+}
+
+// Splits a Linux device number into its major and minor numbers, matching the encoding used by glibc.
+pub fn split_device_node(device_node: u64) -> (u64, u64) {
+    let major = ((device_node >> 8) & 0xfff) | ((device_node >> 32) & !0xfff);
+    let minor = (device_node & 0xff) | ((device_node >> 12) & !0xff);
+    (major, minor)
+}
+
+// Reads the rotational flag of the block device from sysfs. Partitions do not have a queue of their own,
+// so the flag of the parent device is used instead.
+#[cfg(target_os = "linux")]
+pub fn detect_device_kind(device_node: u64) -> DeviceKind {
+    let (major, minor) = split_device_node(device_node);
+    let device_path = std::path::Path::new("/sys/dev/block").join(format!("{}:{}", major, minor));
+
+    let device_path = match device_path.canonicalize() {
+        Ok(device_path) => device_path,
+        Err(_) => return DeviceKind::Unknown
+    };
+
+    let mut queue_paths = vec![device_path.join("queue/rotational")];
+    if let Some(parent) = device_path.parent() {
+        queue_paths.push(parent.join("queue/rotational"));
+    }
+
+    for queue_path in queue_paths {
+        if let Ok(value) = std::fs::read_to_string(&queue_path) {
+            return match value.trim() {
+                "1" => DeviceKind::Rotational,
+                "0" => DeviceKind::NonRotational,
+                _ => DeviceKind::Unknown
+            };
+        }
+    }
+
+    DeviceKind::Unknown
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn detect_device_kind(_device_node: u64) -> DeviceKind {
+    DeviceKind::Unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(limit: usize) -> DeviceScheduler {
+        let mut scheduler = DeviceScheduler::unlimited();
+        scheduler.path_devices.insert(0, 10);
+        scheduler.path_devices.insert(1, 10);
+        scheduler.path_devices.insert(2, 20);
+        scheduler.slots.insert(10, DeviceSlots { limit, in_use: Mutex::new(0), available: Condvar::new() });
+        scheduler
+    }
+
+    #[test]
+    fn split_device_node_should_succeed() {
+        assert_eq!((254, 0), split_device_node(0xfe00));
+        assert_eq!((8, 17), split_device_node(0x811));
+        assert_eq!((259, 1048576), split_device_node(0x1_0001_0300));
+    }
+
+    #[test]
+    fn acquire_should_limit_device() {
+        let scheduler = scheduler(1);
+
+        let permit = scheduler.acquire(0);
+        assert!(permit.is_some());
+        assert!(!scheduler.is_available(0));
+        assert!(!scheduler.is_available(1));
+
+        drop(permit);
+        assert!(scheduler.is_available(1));
+    }
+
+    #[test]
+    fn acquire_on_unlimited_device_should_not_need_permit() {
+        let scheduler = scheduler(1);
+
+        assert!(scheduler.acquire(2).is_none());
+        assert!(scheduler.acquire(3).is_none());
+        assert!(scheduler.is_available(2));
+    }
+}
\ No newline at end of file
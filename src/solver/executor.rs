@@ -1,13 +1,17 @@
 use std::{sync::{mpsc::SyncSender, Arc, Mutex, MutexGuard}, thread::{self, JoinHandle}};
 
-use crate::solver::{choices::ChoiceConsumer, task::{PieceUpdate, Solver, Task}};
+use crate::{filesystem::{FileHandleCache, ReadOptions}, solver::{cancellation::CancellationToken, choices::ChoiceConsumer, task::{PieceUpdate, Solver, Task}}};
+
+// Number of pending tasks inspected for one on an idle device before falling back to the next task in order.
+const DEVICE_LOOKAHEAD: usize = 64;
 
 struct ExecutionState {
     pending: Mutex<Vec<Task>>,
     active: Vec<Mutex<Option<Task>>>
 }
 
-pub fn run(mut items: Vec<Task>, thread_count: usize, writer: SyncSender<PieceUpdate>) {
+// The open files limit is split evenly between the workers, as every worker keeps its own handles.
+pub fn run(mut items: Vec<Task>, thread_count: usize, max_open_files: usize, read_options: ReadOptions, cancellation: CancellationToken, writer: SyncSender<PieceUpdate>) {
     if items.is_empty() {
         return;
     }
@@ -32,13 +36,16 @@ pub fn run(mut items: Vec<Task>, thread_count: usize, writer: SyncSender<PieceUp
     });
 
     // Start up the workers
+    // Every worker keeps at least one handle, even when there are more workers than open files.
+    let open_files_per_thread = std::cmp::max(max_open_files / thread_count, 1);
     let mut handles: Vec<JoinHandle<()>> = Vec::with_capacity(thread_count);
     for thread_id in 0..thread_count {
         let writer = writer.clone();
         let execution_state = execution_state.clone();
+        let cancellation = cancellation.clone();
 
         let handle = thread::spawn(move || {
-            run_internal(thread_id, execution_state, writer);
+            run_internal(thread_id, execution_state, open_files_per_thread, read_options, cancellation, writer);
         });
 
         handles.push(handle);
@@ -50,12 +57,18 @@ pub fn run(mut items: Vec<Task>, thread_count: usize, writer: SyncSender<PieceUp
     }
 }
 
-fn run_internal(thread_id: usize, execution_state: Arc<ExecutionState>, mut writer: SyncSender<PieceUpdate>) {
+fn run_internal(thread_id: usize, execution_state: Arc<ExecutionState>, open_files: usize, read_options: ReadOptions, cancellation: CancellationToken, mut writer: SyncSender<PieceUpdate>) {
     let mut current_thread_id = thread_id;
     let mut choice_consumer = ChoiceConsumer::empty();
     let mut solver = Solver::new();
+    let mut file_handles = FileHandleCache::new(open_files, read_options);
 
     'outer: loop {
+        // Stop picking up work once cancelled, any remaining tasks are left unprocessed.
+        if cancellation.is_cancelled() {
+            break 'outer;
+        }
+
         let found = {
             let mut guard = execution_state
                 .active[current_thread_id]
@@ -64,7 +77,7 @@ fn run_internal(thread_id: usize, execution_state: Arc<ExecutionState>, mut writ
 
             let mut item = None;
             if let Some(generator) = guard.as_mut() {
-                item = generator.take(&mut choice_consumer);
+                item = generator.take(&mut choice_consumer, &mut file_handles, &cancellation);
                 if let None = item {
                     guard.take();
                 }
@@ -75,7 +88,7 @@ fn run_internal(thread_id: usize, execution_state: Arc<ExecutionState>, mut writ
 
         match found {
             Some(task_state) => {
-                solver.solve(&mut choice_consumer, task_state.as_ref(), &mut writer);
+                solver.solve(&mut choice_consumer, task_state.as_ref(), &mut file_handles, &cancellation, &mut writer);
             },
             None => {
                 let mut pending = execution_state.pending
@@ -93,8 +106,8 @@ fn run_internal(thread_id: usize, execution_state: Arc<ExecutionState>, mut writ
 
                 // If multiple threads were waiting for work, we need to abort the thread from
                 // performing a work re-balance, as it was just done.
-                if !pending.is_empty() {
-                    let _ = local.insert(pending.pop().unwrap());
+                if let Some(task) = take_pending(&mut pending) {
+                    let _ = local.insert(task);
                     continue;
                 }
 
@@ -143,3 +156,20 @@ fn run_internal(thread_id: usize, execution_state: Arc<ExecutionState>, mut writ
         }
     }
 }
+
+// Prefers the next pending task whose device has a free read slot, so that workers spread out over the devices
+// instead of queueing on the read slots of a single spinning disk.
+fn take_pending(pending: &mut Vec<Task>) -> Option<Task> {
+    let len = pending.len();
+    if len == 0 {
+        return None;
+    }
+
+    let index = pending.iter()
+        .rev()
+        .take(DEVICE_LOOKAHEAD)
+        .position(|task| task.is_device_available())
+        .map_or(len - 1, |offset| len - 1 - offset);
+
+    Some(pending.remove(index))
+}
\ No newline at end of file
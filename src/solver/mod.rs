@@ -1,3 +1,6 @@
 pub mod executor;
 pub mod task;
-pub mod choices;
\ No newline at end of file
+pub mod choices;
+pub mod cancellation;
+pub mod devices;
+pub mod buffer_pool;
\ No newline at end of file
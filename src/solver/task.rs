@@ -1,30 +1,139 @@
-use std::{collections::HashMap, fs::File, io::{Read, Seek, SeekFrom}, path::Path, sync::{atomic::{AtomicBool, Ordering}, mpsc::SyncSender, Arc, Mutex}};
+use std::{collections::HashMap, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, mpsc::SyncSender, Arc, Mutex}, time::{Duration, Instant}};
 use sha1::{digest::core_api::CoreWrapper, Digest, Sha1, Sha1Core};
 
-use crate::{filesystem::FrozenPathInterner, metadata::{TorrentFileEntry, TorrentPieceEntry, TorrentProcessState}, solver::choices::{ChoiceConsumer, ChoiceGenerator}};
+use crate::{filesystem::{FileHandleCache, FrozenPathCache, FrozenPathInterner}, metadata::{TorrentFileEntry, TorrentPieceEntry, TorrentProcessState}, solver::{cancellation::CancellationToken, choices::{ChoiceConsumer, ChoiceGenerator}, buffer_pool::BufferPool, devices::DeviceScheduler}};
 
 pub type PreloadCache = Vec<Vec<(Option<usize>, Vec<u8>)>>;
 
+// An I/O error encountered while reading a candidate. The candidate is skipped and the remaining
+// candidates are still tried.
+#[derive(Debug, Clone)]
+pub struct ReadFault {
+    pub path: usize,
+    pub kind: std::io::ErrorKind,
+    pub os_error: Option<i32>,
+    pub message: String
+}
+
+impl ReadFault {
+    pub fn new(path: usize, error: &std::io::Error) -> ReadFault {
+        ReadFault {
+            path,
+            kind: error.kind(),
+            os_error: error.raw_os_error(),
+            message: error.to_string()
+        }
+    }
+}
+
 pub struct PieceUpdate {
     pub piece_id: usize,
     pub found: bool,
     pub fault: bool,
+    pub exhausted: bool,
     pub output_bytes: Option<Vec<u8>>,
-    pub output_paths: Option<Vec<Option<usize>>>
+    pub output_paths: Option<Vec<Option<usize>>>,
+    // Candidates that could not be read while solving the piece.
+    pub faults: Vec<ReadFault>
+}
+
+// Upper bounds on the work spent on a single piece. A piece that reaches either limit
+// before a match is found is reported as exhausted.
+#[derive(Clone, Copy, Default)]
+pub struct SolverLimits {
+    pub max_combinations: Option<u64>,
+    pub max_duration: Option<Duration>
 }
 
 pub struct SolverMetadata {
     pub torrent_files: Vec<TorrentFileEntry>,
     pub torrent_pieces: Vec<TorrentPieceEntry>,
     pub path_interner: FrozenPathInterner,
-    pub counter: Mutex<TorrentProcessState>
+    // Source of every candidate, keyed by the interned path handle.
+    pub path_cache: FrozenPathCache,
+    pub counter: Mutex<TorrentProcessState>,
+    pub limits: SolverLimits,
+    pub devices: DeviceScheduler,
+    pub buffers: BufferPool,
+    // Hash of a piece filled with zeros, for every piece length.
+    pub zero_piece_hashes: HashMap<u64, Vec<u8>>
+}
+
+// Tracks the choice chunks of a piece that have been handed out to solvers, so that the
+// solver finishing the last chunk knows it is responsible for reporting an unsolved piece.
+struct ChunkTracker {
+    outstanding: usize,
+    generated: bool
 }
 
 pub struct TaskState {
     piece_id: usize,
     solver_metadata: Arc<SolverMetadata>,
     preloaded: Option<Arc<PreloadCache>>,
-    completed: AtomicBool
+    completed: AtomicBool,
+    exhausted: AtomicBool,
+    attempts: AtomicU64,
+    started: Instant,
+    chunks: Mutex<ChunkTracker>,
+    faults: Mutex<Vec<ReadFault>>,
+    // The piece hash is the hash of zeros, so holes and all-zero ranges are valid matches.
+    zero_piece: bool
+}
+
+impl TaskState {
+    // Keeps the first fault for every candidate path.
+    fn record_fault(&self, fault: ReadFault) {
+        let mut faults = self.faults
+            .lock()
+            .expect("Faults should always lock.");
+
+        if !faults.iter().any(|existing| existing.path == fault.path) {
+            faults.push(fault);
+        }
+    }
+
+    fn take_faults(&self) -> Vec<ReadFault> {
+        let mut faults = self.faults
+            .lock()
+            .expect("Faults should always lock.");
+
+        std::mem::take(&mut *faults)
+    }
+
+    // Counts an attempted combination and returns true if the piece is now over one of its limits.
+    fn attempt(&self, limits: &SolverLimits) -> bool {
+        let attempts = self.attempts.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let over_combinations = limits.max_combinations
+            .is_some_and(|max_combinations| attempts > max_combinations);
+
+        let over_duration = limits.max_duration
+            .is_some_and(|max_duration| self.started.elapsed() > max_duration);
+
+        over_combinations || over_duration
+    }
+
+    // Stops any further chunks from being handed out, so the solvers that are still working on the
+    // piece wind down and the last one reports it as exhausted.
+    fn exhaust(&self) {
+        let mut chunks = self.chunks
+            .lock()
+            .expect("Chunk tracker should always lock.");
+
+        self.exhausted.store(true, Ordering::Release);
+        chunks.generated = true;
+    }
+
+    // Returns true when the caller released the last outstanding chunk after every chunk
+    // for the piece has been generated.
+    fn release_chunk(&self) -> bool {
+        let mut chunks = self.chunks
+            .lock()
+            .expect("Chunk tracker should always lock.");
+
+        chunks.outstanding -= 1;
+        chunks.outstanding == 0 && chunks.generated
+    }
 }
 
 pub struct Task {
@@ -46,7 +155,11 @@ impl Task {
         }
     }
 
-    pub fn take(&mut self, consumer: &mut ChoiceConsumer) -> Option<Arc<TaskState>> {
+    pub fn take(&mut self, consumer: &mut ChoiceConsumer, handles: &mut FileHandleCache, cancellation: &CancellationToken) -> Option<Arc<TaskState>> {
+        if cancellation.is_cancelled() {
+            return None;
+        }
+
         if let None = self.initialized {
             let mut choice_generator = ChoiceGenerator::empty();
             let mut preloaded: Option<Arc<PreloadCache>> = None;
@@ -55,9 +168,10 @@ impl Task {
                 .torrent_pieces[self.piece_id];
 
             let mut choices = piece.total_choices.clone();
+            let mut faults = Vec::new();
 
             if piece.files.len() > 1 {
-                let loaded = self.preload().unwrap();
+                let loaded = self.preload(handles, &mut faults);
                 for index in 0..loaded.len() {
                     choices[index] = loaded[index].len();
                 }
@@ -66,11 +180,21 @@ impl Task {
 
             choice_generator.reset_from(&choices, self.target_split);
 
+            let zero_piece = self.solver_metadata.zero_piece_hashes
+                .get(&piece.length)
+                .is_some_and(|zero_hash| zero_hash == &piece.hash);
+
             self.task_state = Some(Arc::new(TaskState {
                 solver_metadata: self.solver_metadata.clone(),
                 piece_id: self.piece_id,
                 preloaded,
-                completed: AtomicBool::new(false)
+                completed: AtomicBool::new(false),
+                exhausted: AtomicBool::new(false),
+                attempts: AtomicU64::new(0),
+                started: Instant::now(),
+                chunks: Mutex::new(ChunkTracker { outstanding: 0, generated: false }),
+                faults: Mutex::new(faults),
+                zero_piece
             }));
             self.initialized = Some(choice_generator);
         }
@@ -78,77 +202,108 @@ impl Task {
         let generator = self.initialized.as_mut().unwrap();
         let task_state = self.task_state.as_ref().unwrap();
 
-        if generator.ended() {
+        if task_state.completed.load(Ordering::Relaxed) {
             return None;
         }
 
-        if task_state.completed.load(Ordering::Relaxed) {
+        let mut chunks = task_state.chunks
+            .lock()
+            .expect("Chunk tracker should always lock.");
+
+        // A piece without any choices is still handed out once with an empty consumer,
+        // so that a solver reports it as failed.
+        if chunks.generated {
             return None;
         }
 
         generator.get(consumer);
         generator.next();
 
+        chunks.outstanding += 1;
+        chunks.generated = generator.ended();
+        drop(chunks);
+
         Some(task_state.clone())
     }
 
-    fn preload(&self) -> std::io::Result<PreloadCache> {
-        let piece = &self
-            .solver_metadata
+    // Returns true if the device holding the first candidate of the piece has a free read slot. Used by the
+    // executor to prefer pieces on idle devices.
+    pub fn is_device_available(&self) -> bool {
+        let piece = &self.solver_metadata
             .torrent_pieces[self.piece_id];
 
-        let mut loaded = Vec::with_capacity(piece.files.len());
-
-        for piece_file in piece.files.iter() {
-            let torrent_file = &self
-                .solver_metadata
-                .torrent_files[piece_file.file_id];
-
-            let mut results: HashMap<Vec<u8>, Option<usize>> = HashMap::new();
-
-            if torrent_file.padding { 
-                results.insert(vec![0; piece_file.read_length as usize], None);
-            } else if let Some(searches) = torrent_file.searches.as_ref() {
-                // De-duplicate identical files if the file has already been seen.
-                'inner: for search_path_handle in searches {
-                    let search_path = self.solver_metadata.path_interner.get(*search_path_handle);
-                    let value = Self::read_bytes(search_path, piece_file.read_length, piece_file.read_start_position)?;
-        
-                    if results.contains_key(&value) {
-                        continue;
-                    }
-        
-                    results.insert(value, Some(*search_path_handle));
-                }
-            } else {
-                return Ok(Vec::new());
-            }
+        match piece.primary_candidate(&self.solver_metadata.torrent_files) {
+            Some((path_id, _)) => self.solver_metadata.devices.is_available(path_id),
+            None => true
+        }
+    }
 
-            let mut real: Vec<(Option<usize>, Vec<u8>)> = Vec::new();
-            for (key, value) in results.into_iter() {
-                real.push((value, key));
+    fn preload(&self, handles: &mut FileHandleCache, faults: &mut Vec<ReadFault>) -> PreloadCache {
+        let solver_metadata = self.solver_metadata.as_ref();
+        let piece = &solver_metadata.torrent_pieces[self.piece_id];
+
+        preload_piece(piece, &solver_metadata.torrent_files, &solver_metadata.path_cache, &solver_metadata.devices, handles, faults)
+    }
+}
+
+// Reads every candidate of every file in the piece, keeping a single candidate for identical bytes. Candidates
+// that cannot be read are recorded as faults and left out of the cache.
+pub(crate) fn preload_piece(
+    piece: &TorrentPieceEntry,
+    torrent_files: &[TorrentFileEntry],
+    path_cache: &FrozenPathCache,
+    devices: &DeviceScheduler,
+    handles: &mut FileHandleCache,
+    faults: &mut Vec<ReadFault>
+) -> PreloadCache {
+    let mut loaded = Vec::with_capacity(piece.files.len());
+
+    for piece_file in piece.files.iter() {
+        let torrent_file = &torrent_files[piece_file.file_id];
+
+        let mut results: HashMap<Vec<u8>, Option<usize>> = HashMap::new();
+
+        if torrent_file.padding { 
+            results.insert(vec![0; piece_file.read_length as usize], None);
+        } else if let Some(searches) = torrent_file.searches.as_ref() {
+            // De-duplicate identical files if the file has already been seen.
+            for search_path_handle in searches {
+                let source = path_cache.get(*search_path_handle).source();
+                let mut value = Vec::with_capacity(piece_file.read_length as usize);
+                let permit = devices.acquire(*search_path_handle);
+                let result = handles.read_source(source, piece_file.read_length, piece_file.read_start_position, &mut value);
+
+                drop(permit);
+
+                if let Err(err) = result {
+                    faults.push(ReadFault::new(*search_path_handle, &err));
+                    continue;
+                }
+    
+                if results.contains_key(&value) {
+                    continue;
+                }
+    
+                results.insert(value, Some(*search_path_handle));
             }
+        } else {
+            return Vec::new();
+        }
 
-            loaded.push(real);
+        let mut real: Vec<(Option<usize>, Vec<u8>)> = Vec::new();
+        for (key, value) in results.into_iter() {
+            real.push((value, key));
         }
 
-        Ok(loaded)
+        loaded.push(real);
     }
 
-    fn read_bytes(
-        path: &Path,
-        read_length: u64,
-        read_start_position: u64
-    ) -> Result<Vec<u8>, std::io::Error> {
-        let mut handle = File::open(path)?;
-        let mut read_bytes = Vec::with_capacity(read_length as usize);
-
-        handle.seek(SeekFrom::Start(read_start_position))?;
-        handle.take(read_length)
-            .read_to_end(&mut read_bytes)?;
+    loaded
+}
 
-        Ok(read_bytes)
-    }
+// Compares in fixed-size chunks, which is considerably faster than comparing byte by byte.
+fn is_zeros(bytes: &[u8]) -> bool {
+    bytes.chunks(64).all(|chunk| chunk.iter().fold(0, |acc, byte| acc | byte) == 0)
 }
 
 pub struct Solver {
@@ -166,25 +321,93 @@ impl Solver {
         }
     }
 
-    pub fn solve(&mut self, choices: &mut ChoiceConsumer, task_state: &TaskState, writer: &mut SyncSender<PieceUpdate>) {
+    pub fn solve(&mut self, choices: &mut ChoiceConsumer, task_state: &TaskState, handles: &mut FileHandleCache, cancellation: &CancellationToken, writer: &mut SyncSender<PieceUpdate>) {
         let solver_metadata = task_state.solver_metadata.as_ref();
         let torrent_files = &solver_metadata.torrent_files;
-        let path_interner = &solver_metadata.path_interner;
+        let path_cache = &solver_metadata.path_cache;
         let piece = &solver_metadata.torrent_pieces[task_state.piece_id];
 
         let piece_hash = piece.hash.as_slice();
         let completed = &task_state.completed;
+        let exhausted = &task_state.exhausted;
+
+        // Unless the piece is all zeros, a hole or an all-zero range can never match, so those choices are skipped without hashing.
+        let skip_zeros = !task_state.zero_piece;
+
+        // Single-file pieces can be hashed directly from a mapping of the candidate, without copying the bytes.
+        let single_file = task_state.preloaded.is_none()
+            && piece.files.len() == 1
+            && !torrent_files[piece.files[0].file_id].padding;
+        let map_single_file = handles.mapping_enabled() && single_file;
 
         'choices: while !choices.ended() {
             self.output_bytes.clear();
             self.output_paths.clear();
             self.hasher.reset();
             
-            if completed.load(Ordering::Relaxed) {
+            if completed.load(Ordering::Relaxed) || exhausted.load(Ordering::Relaxed) || cancellation.is_cancelled() {
                 break 'choices;
             }
 
+            if task_state.attempt(&solver_metadata.limits) {
+                task_state.exhaust();
+                break 'choices;
+            }
+
+            // The range of the candidate is a hole, so it is skipped without being read. Holes are only checked for
+            // single-file pieces, as a hole in a single file of a multi-file piece may still be valid data.
+            if skip_zeros && single_file {
+                let piece_file_entry = &piece.files[0];
+                let path_id = torrent_files[piece_file_entry.file_id].searches.as_ref().unwrap()[choices.get(0).get()];
+                let source = path_cache.get(path_id).source();
+
+                if handles.source_is_hole(source, piece_file_entry.read_length, piece_file_entry.read_start_position) {
+                    choices.next();
+                    continue 'choices;
+                }
+            }
+
+            let mut read_fault = None;
+            let mut mapped_hash = None;
+            let mut zeros = false;
+
+            if map_single_file {
+                let piece_file_entry = &piece.files[0];
+                let file_entry = &torrent_files[piece_file_entry.file_id];
+                let path_id = file_entry.searches.as_ref().unwrap()[choices.get(0).get()];
+                let source = path_cache.get(path_id).source();
+
+                // The mapping is read from the device while hashing, so the read slot is held until the hash is done.
+                let permit = solver_metadata.devices.acquire(path_id);
+                let result = handles.map_source(source, piece_file_entry.read_length, piece_file_entry.read_start_position);
+
+                match result {
+                    Ok(Some(bytes)) if skip_zeros && is_zeros(bytes) => zeros = true,
+                    Ok(Some(bytes)) => {
+                        self.hasher.update(bytes);
+                        let hash = self.hasher.finalize_reset();
+
+                        // The bytes are only copied for a match that has to be written to the export target.
+                        if piece_hash == hash.as_slice() && path_id != file_entry.export_target {
+                            self.output_bytes.extend_from_slice(bytes);
+                        }
+
+                        self.output_paths.push(Some(path_id));
+                        mapped_hash = Some(hash);
+                    },
+                    Ok(None) => {},
+                    Err(err) => read_fault = Some(ReadFault::new(path_id, &err))
+                }
+
+                drop(permit);
+            }
+
+            let mapped = mapped_hash.is_some() || read_fault.is_some() || zeros;
             for file_index in 0..choices.len() {
+                if mapped {
+                    break;
+                }
+
                 let choice = choices.get(file_index).get();
 
                 let piece_file_entry = &piece.files[file_index];
@@ -198,19 +421,44 @@ impl Solver {
                     self.output_paths.push(None);
                 } else {
                     let path_id = file_entry.searches.as_ref().unwrap()[choice];
-                    let path = path_interner.get(path_id);
+                    let source = path_cache.get(path_id).source();
+
+                    // The read slot of the device is released before hashing.
+                    let permit = solver_metadata.devices.acquire(path_id);
+                    let result = handles.read_source(
+                        source, piece_file_entry.read_length, piece_file_entry.read_start_position, &mut self.output_bytes
+                    );
 
-                    let mut file_handle = File::open(path).unwrap(); // TODO: FIX ME
-                    file_handle.seek(SeekFrom::Start(piece_file_entry.read_start_position)).unwrap();
-                    file_handle.take(piece_file_entry.read_length)
-                        .read_to_end(&mut self.output_bytes).unwrap();
+                    drop(permit);
+
+                    if let Err(err) = result {
+                        read_fault = Some(ReadFault::new(path_id, &err));
+                        break;
+                    }
 
                     self.output_paths.push(Some(path_id));
                 }
             }
 
-            self.hasher.update(&self.output_bytes);
-            let hash = self.hasher.finalize_reset();
+            // Skip this choice, the remaining choices may still match.
+            if let Some(read_fault) = read_fault {
+                task_state.record_fault(read_fault);
+                choices.next();
+                continue 'choices;
+            }
+
+            if zeros || (mapped_hash.is_none() && skip_zeros && is_zeros(&self.output_bytes)) {
+                choices.next();
+                continue 'choices;
+            }
+
+            let hash = match mapped_hash {
+                Some(hash) => hash,
+                None => {
+                    self.hasher.update(&self.output_bytes);
+                    self.hasher.finalize_reset()
+                }
+            };
 
             if piece_hash.cmp(&hash).is_eq() {
                 let swapped = completed.compare_exchange(
@@ -223,8 +471,11 @@ impl Solver {
                         piece_id: piece.piece_id,
                         found: true,
                         fault: false,
-                        output_bytes: Some(self.output_bytes.clone()),
-                        output_paths: Some(self.output_paths.clone())
+                        exhausted: false,
+                        // Hand the buffer to the writer and continue with a recycled one.
+                        output_bytes: Some(std::mem::replace(&mut self.output_bytes, solver_metadata.buffers.take())),
+                        output_paths: Some(self.output_paths.clone()),
+                        faults: task_state.take_faults()
                     };
 
                     writer
@@ -237,5 +488,144 @@ impl Solver {
 
             choices.next();
         }
+
+        // Every combination for the piece has been tried, or a limit was reached, without a match. Cancelled pieces
+        // are left unreported, as they have not finished processing and should be picked up again on resume.
+        // Unsolved pieces with unreadable candidates are reported as faulted, as the missing data may have matched.
+        if task_state.release_chunk() && !completed.load(Ordering::Acquire) && !cancellation.is_cancelled() {
+            let faults = task_state.take_faults();
+            let piece_update = PieceUpdate {
+                piece_id: piece.piece_id,
+                found: false,
+                fault: !faults.is_empty(),
+                exhausted: exhausted.load(Ordering::Acquire),
+                output_bytes: None,
+                output_paths: None,
+                faults
+            };
+
+            writer
+                .send(piece_update)
+                .expect("Should never fail to write.");
+        }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::MetadataExt, path::{Path, PathBuf}, time::Duration};
+
+    use crate::{filesystem::{DataSource, LocalFile, MemorySource}, test_support::{build_solver_metadata, build_torrent, solve, TempDir}};
+
+    use super::*;
+
+    // Candidates of the same length as the file, none of which match.
+    fn mismatched_sources(count: usize) -> Vec<(PathBuf, Box<dyn DataSource>)> {
+        (0..count)
+            .map(|index| {
+                let name = format!("/candidates/{}", index);
+                let source: Box<dyn DataSource> = Box::new(MemorySource::new(&name, vec![index as u8 + 1; 8]));
+                (PathBuf::from(name), source)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn solve_should_exhaust_piece_at_combination_limit() {
+        let torrent = build_torrent(&[("data.bin", b"abcdefgh")], 8);
+        let limits = SolverLimits { max_combinations: Some(1), max_duration: None };
+        let solver_metadata = build_solver_metadata(&[torrent], Path::new("/export"), mismatched_sources(3), limits);
+
+        let updates = solve(&solver_metadata);
+
+        assert_eq!(1, updates.len());
+        assert!(!updates[0].found);
+        assert!(updates[0].exhausted);
+        assert_eq!(vec![3], solver_metadata.torrent_pieces[0].total_choices);
+    }
+
+    #[test]
+    fn solve_should_exhaust_piece_at_time_limit() {
+        let torrent = build_torrent(&[("data.bin", b"abcdefgh")], 8);
+        let limits = SolverLimits { max_combinations: None, max_duration: Some(Duration::ZERO) };
+        let solver_metadata = build_solver_metadata(&[torrent], Path::new("/export"), mismatched_sources(3), limits);
+
+        let updates = solve(&solver_metadata);
+
+        assert_eq!(1, updates.len());
+        assert!(!updates[0].found);
+        assert!(updates[0].exhausted);
+    }
+
+    #[test]
+    fn solve_should_fail_piece_within_limits() {
+        let torrent = build_torrent(&[("data.bin", b"abcdefgh")], 8);
+        let limits = SolverLimits { max_combinations: Some(3), max_duration: None };
+        let solver_metadata = build_solver_metadata(&[torrent], Path::new("/export"), mismatched_sources(3), limits);
+
+        let updates = solve(&solver_metadata);
+
+        assert_eq!(1, updates.len());
+        assert!(!updates[0].found);
+        assert!(!updates[0].exhausted);
+    }
+
+    #[test]
+    fn solve_should_record_fault_and_solve_from_another_candidate() {
+        let directory = TempDir::new("task_fault");
+
+        let sources: Vec<(PathBuf, Box<dyn DataSource>)> = ["first.bin", "second.bin"].iter()
+            .enumerate()
+            .map(|(handle_id, name)| {
+                let path = directory.join(name);
+                std::fs::write(&path, b"abcdefgh").unwrap();
+
+                let metadata = std::fs::metadata(&path).unwrap();
+                let source: Box<dyn DataSource> = Box::new(LocalFile::new(handle_id, path.clone(), metadata.len(), metadata.dev(), metadata.ino()));
+                (path, source)
+            })
+            .collect();
+
+        let torrent = build_torrent(&[("data.bin", b"abcdefgh")], 8);
+        let solver_metadata = build_solver_metadata(&[torrent], Path::new("/export"), sources, SolverLimits::default());
+
+        // Remove the candidate that is searched first, after discovery has already found it.
+        let searches = solver_metadata.torrent_files[0].searches.clone().unwrap();
+        assert_eq!(2, searches.len());
+        std::fs::remove_file(solver_metadata.path_interner.get(searches[0])).unwrap();
+
+        let updates = solve(&solver_metadata);
+
+        assert_eq!(1, updates.len());
+        assert!(updates[0].found);
+        assert!(!updates[0].fault);
+        assert_eq!(Some(vec![Some(searches[1])]), updates[0].output_paths);
+
+        assert_eq!(1, updates[0].faults.len());
+        assert_eq!(searches[0], updates[0].faults[0].path);
+        assert_eq!(std::io::ErrorKind::NotFound, updates[0].faults[0].kind);
+    }
+
+    #[test]
+    fn solve_should_solve_pieces_spanning_files_from_memory_sources() {
+        let sources: Vec<(PathBuf, Box<dyn DataSource>)> = [("/memory/wrong", b"abcxyz"), ("/memory/first", b"abcdef"), ("/memory/second", b"ghijkl")].iter()
+            .map(|(name, bytes)| {
+                let source: Box<dyn DataSource> = Box::new(MemorySource::new(name, bytes.to_vec()));
+                (PathBuf::from(name), source)
+            })
+            .collect();
+
+        let torrent = build_torrent(&[("first.bin", b"abcdef"), ("second.bin", b"ghijkl")], 8);
+        let solver_metadata = build_solver_metadata(&[torrent], Path::new("/export"), sources, SolverLimits::default());
+
+        let updates = solve(&solver_metadata);
+
+        assert_eq!(2, updates.len());
+        assert!(updates.iter().all(|update| update.found && !update.fault && update.faults.is_empty()));
+
+        let output_paths: Vec<_> = updates[0].output_paths.clone().unwrap().into_iter()
+            .map(|path_id| solver_metadata.path_interner.get(path_id.unwrap()).to_path_buf())
+            .collect();
+        assert_eq!(vec![PathBuf::from("/memory/first"), PathBuf::from("/memory/second")], output_paths);
+    }
+}
\ No newline at end of file
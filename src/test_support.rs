@@ -0,0 +1,180 @@
+use std::{fs, io::{BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, path::{Path, PathBuf}, sync::{mpsc::sync_channel, Arc, Mutex}, thread};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    filesystem::{DataSource, DefaultExportPathFormatter, PathCache, PathInterner, ReadOptions},
+    metadata::{build_raw_torrent_file_metadata, build_raw_torrent_piece_metadata, calculate_total_choices_for_piece, calculate_zero_piece_hashes, discover_and_apply_searches, TorrentProcessState},
+    orchestrator::Discovery,
+    solver::{buffer_pool::BufferPool, cancellation::CancellationToken, devices::DeviceScheduler, executor, task::{PieceUpdate, SolverLimits, SolverMetadata, Task}},
+    torrent::{Torrent, TorrentFile, TorrentInfo}
+};
+
+// Directory under the system temp directory, named after the test and the process so parallel tests never share one.
+// The directory is removed when dropped, also when the test fails.
+pub struct TempDir {
+    path: PathBuf
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("torrent_bootstrap_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir { path: fs::canonicalize(path).unwrap() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+// Builds a multi-file torrent named after its first file, with the piece hashes of the given file contents.
+pub fn build_torrent(files: &[(&str, &[u8])], piece_length: u64) -> Torrent {
+    let content: Vec<u8> = files.iter().flat_map(|(_, bytes)| bytes.iter().copied()).collect();
+    let pieces = content.chunks(piece_length as usize)
+        .map(|piece| Sha1::digest(piece).to_vec())
+        .collect();
+
+    let name = files[0].0.to_string();
+    Torrent {
+        info_hash: Sha1::digest(name.as_bytes()).to_vec(),
+        info: TorrentInfo {
+            name,
+            length: None,
+            attr: None,
+            files: Some(files.iter()
+                .map(|(path, bytes)| TorrentFile { length: bytes.len() as u64, path: vec![path.to_string()], attr: None })
+                .collect()),
+            piece_length,
+            pieces
+        },
+        url_list: Vec::new()
+    }
+}
+
+// Discovers the torrents like the orchestrator does, with the given sources as the only candidates. The export
+// directory is never added as a candidate.
+pub fn build_discovery(torrents: &[Torrent], export_directory: &Path, sources: Vec<(PathBuf, Box<dyn DataSource>)>) -> Discovery {
+    let mut path_interner = PathInterner::new();
+    let mut torrent_files = build_raw_torrent_file_metadata::<DefaultExportPathFormatter>(torrents, &mut path_interner, export_directory);
+
+    let mut path_cache = PathCache::new();
+    for (path, source) in sources {
+        path_cache.add_source(&mut path_interner, path, source);
+    }
+
+    let path_cache = path_cache.freeze();
+    let path_interner = path_interner.freeze();
+    discover_and_apply_searches(&mut torrent_files, &path_cache.entries, &path_interner);
+
+    let mut torrent_pieces = build_raw_torrent_piece_metadata(torrents);
+    calculate_total_choices_for_piece(&mut torrent_files, &mut torrent_pieces);
+
+    Discovery { torrent_files, torrent_pieces, path_interner, path_cache }
+}
+
+pub fn build_solver_metadata(
+    torrents: &[Torrent],
+    export_directory: &Path,
+    sources: Vec<(PathBuf, Box<dyn DataSource>)>,
+    limits: SolverLimits
+) -> Arc<SolverMetadata> {
+    let Discovery { torrent_files, torrent_pieces, path_interner, path_cache } = build_discovery(torrents, export_directory, sources);
+
+    Arc::new(SolverMetadata {
+        counter: Mutex::new(TorrentProcessState::new(torrent_pieces.len())),
+        zero_piece_hashes: calculate_zero_piece_hashes(&torrent_pieces),
+        devices: DeviceScheduler::unlimited(),
+        buffers: BufferPool::new(2),
+        torrent_files,
+        torrent_pieces,
+        path_interner,
+        path_cache,
+        limits
+    })
+}
+
+// Solves every piece on a single thread, and returns the reported pieces in order.
+pub fn solve(solver_metadata: &Arc<SolverMetadata>) -> Vec<PieceUpdate> {
+    let piece_count = solver_metadata.torrent_pieces.len();
+    let tasks = (0..piece_count)
+        .rev()
+        .map(|piece_id| Task::new(piece_id, solver_metadata.clone(), 1))
+        .collect();
+
+    let (sender, receiver) = sync_channel(piece_count);
+    let read_options = ReadOptions { sequential: false, read_ahead: 0, mmap: false };
+    executor::run(tasks, 1, 16, read_options, CancellationToken::new(), sender);
+
+    let mut updates: Vec<PieceUpdate> = receiver.into_iter().collect();
+    updates.sort_by_key(|update| update.piece_id);
+    updates
+}
+
+// Serves the files under the directory over HTTP for the rest of the test run, and returns the URL of the directory.
+// Range requests are answered with the requested bytes, and any request for a missing file with 404.
+pub fn serve_directory(directory: &Path) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let directory = directory.to_path_buf();
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = serve_file(&directory, stream);
+        }
+    });
+
+    url
+}
+
+fn serve_file(directory: &Path, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim_end().is_empty() {
+            break;
+        }
+
+        if let Some((key, value)) = line.split_once(':') && key.eq_ignore_ascii_case("range") {
+            range = value.trim()
+                .strip_prefix("bytes=")
+                .and_then(|value| value.split_once('-'))
+                .and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?)));
+        }
+    }
+
+    let path = request_line.split(' ').nth(1).unwrap_or_default().trim_start_matches('/');
+    match (fs::read(directory.join(path)), range) {
+        (Ok(bytes), Some((start, end))) if start < bytes.len() => {
+            let end = std::cmp::min(end + 1, bytes.len());
+            write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                start, end - 1, bytes.len(), end - start
+            )?;
+            stream.write_all(&bytes[start..end])
+        },
+        (Ok(bytes), None) => {
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", bytes.len())?;
+            stream.write_all(&bytes)
+        },
+        _ => stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+    }
+}
\ No newline at end of file
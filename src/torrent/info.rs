@@ -1,7 +1,6 @@
-use std::fmt::Write as FmtWrite;
 use sha1::{Digest, Sha1};
 
-use crate::bencode::BencodeDictionary;
+use crate::{bencode::BencodeDictionary, hex::to_hex};
 
 pub fn calculate_info_hash(info: &BencodeDictionary, bytes: &[u8]) -> Vec<u8> {
     let mut hasher = Sha1::new();
@@ -10,11 +9,7 @@ pub fn calculate_info_hash(info: &BencodeDictionary, bytes: &[u8]) -> Vec<u8> {
 }
 
 pub fn get_sha1_hexdigest(bytes: &[u8]) -> String {
-    let mut output = String::new();
-    for byte in bytes {
-        write!(&mut output, "{:02x?}", byte).expect("Unable to write");
-    }
-    output
+    to_hex(bytes)
 }
 
 #[cfg(test)]
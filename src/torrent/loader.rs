@@ -0,0 +1,190 @@
+use std::{collections::HashSet, fs, io::{Error, ErrorKind, Result}, path::{Path, PathBuf}};
+
+use walkdir::WalkDir;
+
+use super::Torrent;
+
+#[derive(Debug)]
+pub struct SkippedTorrent {
+    pub path: PathBuf,
+    pub message: String
+}
+
+#[derive(Debug, Default)]
+pub struct LoadedTorrents {
+    pub torrents: Vec<Torrent>,
+    // Path each torrent was loaded from, in the same order as the torrents.
+    pub paths: Vec<PathBuf>,
+    pub skipped: Vec<SkippedTorrent>
+}
+
+// Loads torrents from a list of inputs, where every input is one of:
+// - a torrent file,
+// - a directory, which is scanned recursively for files with a .torrent extension,
+// - a glob pattern such as "downloads/**/*.torrent", when no file exists at that literal path,
+// - @<list file>, a text file with one input per line. Empty lines and lines starting with # are ignored, and
+//   relative paths are resolved against the directory of the list file. List files cannot be nested.
+// Inputs that cannot be read or parsed are skipped and reported with their error, or fail the load in strict mode.
+pub fn load_torrents(inputs: &[PathBuf], strict: bool) -> Result<LoadedTorrents> {
+    let mut loaded = LoadedTorrents::default();
+    let mut paths = Vec::new();
+
+    for input in inputs {
+        expand_input(input, true, &mut paths, &mut loaded.skipped)?;
+    }
+
+    let mut visited = HashSet::new();
+    for path in paths {
+        if !visited.insert(path.clone()) {
+            continue;
+        }
+
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                loaded.skipped.push(SkippedTorrent { path, message: err.to_string() });
+                continue;
+            }
+        };
+
+        match Torrent::from_bytes(&bytes) {
+            Ok(torrent) => {
+                loaded.torrents.push(torrent);
+                loaded.paths.push(path);
+            },
+            Err(err) => loaded.skipped.push(SkippedTorrent { path, message: format!("{:?}: {}", err.kind, err.message) })
+        }
+    }
+
+    if strict && let Some(skipped) = loaded.skipped.first() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unable to load {} torrents, including {:#?}: {}", loaded.skipped.len(), skipped.path, skipped.message)
+        ));
+    }
+
+    Ok(loaded)
+}
+
+fn expand_input(input: &Path, allow_list_file: bool, paths: &mut Vec<PathBuf>, skipped: &mut Vec<SkippedTorrent>) -> Result<()> {
+    let value = input.to_string_lossy();
+
+    if let Some(list_file) = value.strip_prefix('@') {
+        if !allow_list_file {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("List file {:#?} cannot be referenced from another list file.", list_file)));
+        }
+
+        let list_file = Path::new(list_file);
+        let contents = fs::read_to_string(list_file)
+            .map_err(|err| Error::new(err.kind(), format!("Unable to read torrent list file {:#?}: {}", list_file, err)))?;
+
+        let parent = list_file.parent().unwrap_or(Path::new(""));
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            expand_input(&parent.join(line), false, paths, skipped)?;
+        }
+
+        return Ok(());
+    }
+
+    if input.is_dir() {
+        expand_directory(input, paths, skipped);
+        return Ok(());
+    }
+
+    if !input.exists() && value.contains(['*', '?', '[']) {
+        let matches = glob::glob(&value)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("Invalid torrent pattern {:?}: {}", value, err)))?;
+
+        let mut matched = false;
+        for entry in matches {
+            matched = true;
+
+            match entry {
+                Ok(path) if path.is_dir() => expand_directory(&path, paths, skipped),
+                Ok(path) => paths.push(path),
+                Err(err) => skipped.push(SkippedTorrent { path: err.path().to_path_buf(), message: err.error().to_string() })
+            }
+        }
+
+        if !matched {
+            skipped.push(SkippedTorrent { path: input.to_path_buf(), message: "No paths matched the pattern.".to_string() });
+        }
+
+        return Ok(());
+    }
+
+    paths.push(input.to_path_buf());
+    Ok(())
+}
+
+fn expand_directory(root: &Path, paths: &mut Vec<PathBuf>, skipped: &mut Vec<SkippedTorrent>) {
+    for result in WalkDir::new(root).sort_by_file_name() {
+        let entry = match result {
+            Ok(entry) => entry,
+            Err(err) => {
+                let path = err.path().unwrap_or(root).to_path_buf();
+                skipped.push(SkippedTorrent { path, message: err.to_string() });
+                continue;
+            }
+        };
+
+        let is_torrent = entry.path()
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("torrent"));
+
+        if entry.file_type().is_file() && is_torrent {
+            paths.push(entry.into_path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::TempDir;
+
+    use super::*;
+
+    const TORRENT: &[u8] = b"d4:infod6:lengthi1e4:name1:a12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+
+    fn create_directory(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("loader_{}", name));
+        fs::create_dir_all(root.join("nested")).unwrap();
+
+        fs::write(root.join("a.torrent"), TORRENT).unwrap();
+        fs::write(root.join("nested").join("b.TORRENT"), TORRENT).unwrap();
+        fs::write(root.join("nested").join("invalid.torrent"), b"invalid").unwrap();
+        fs::write(root.join("notes.txt"), TORRENT).unwrap();
+        root
+    }
+
+    #[test]
+    fn load_torrents_should_scan_directories_and_report_skipped() {
+        let root = create_directory("directory");
+
+        let loaded = load_torrents(&[root.path().to_path_buf()], false).unwrap();
+        assert_eq!(vec![root.join("a.torrent"), root.join("nested").join("b.TORRENT")], loaded.paths);
+        assert_eq!(2, loaded.torrents.len());
+        assert_eq!(1, loaded.skipped.len());
+        assert_eq!(root.join("nested").join("invalid.torrent"), loaded.skipped[0].path);
+
+        assert!(load_torrents(&[root.path().to_path_buf()], true).is_err());
+    }
+
+    #[test]
+    fn load_torrents_should_expand_globs_and_list_files() {
+        let root = create_directory("list");
+        fs::write(root.join("list.txt"), "# comment\n\na.torrent\nmissing.torrent\n").unwrap();
+
+        let pattern = root.join("*.torrent");
+        let list = PathBuf::from(format!("@{}", root.join("list.txt").display()));
+
+        let loaded = load_torrents(&[pattern, list], false).unwrap();
+        assert_eq!(vec![root.join("a.torrent")], loaded.paths);
+        assert_eq!(1, loaded.skipped.len());
+        assert_eq!(root.join("missing.torrent"), loaded.skipped[0].path);
+    }
+}
\ No newline at end of file
@@ -2,5 +2,6 @@ mod torrent;
 pub mod pieces;
 pub mod error;
 pub mod info;
+pub mod loader;
 
 pub use torrent::*;
\ No newline at end of file
@@ -158,6 +158,7 @@ mod tests {
                     vec![222, 220, 208, 9, 117, 139, 87, 43, 47, 57, 191, 94, 78, 142, 68, 176, 66, 206, 40, 67],
                 ],
             },
+            url_list: Vec::new(),
             info_hash: vec![158, 107, 242, 157, 198, 208, 115, 71, 243, 8, 84, 55, 8, 17, 60, 86, 152, 141, 19, 186],
         };
 
@@ -220,6 +221,7 @@ mod tests {
                     ],
                 ],
             },
+            url_list: Vec::new(),
             info_hash: vec![
                 222, 16, 92, 167, 219, 78, 170, 190, 18, 50, 30, 43, 240, 88, 62, 206, 226, 0, 163, 166,
             ],
@@ -4,8 +4,10 @@ use super::error::{TorrentError, TorrentErrorKind};
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Torrent {
-    // We do not record outer properties as they are not relevant to this program.
+    // Outer properties are only recorded when this program uses them.
     pub info: TorrentInfo,
+    // Web seed URLs from the url-list property (BEP 19).
+    pub url_list: Vec<String>,
     // Not a field in a torrent, but tracked here as it is needed for identification.
     pub info_hash: Vec<u8>
 }
@@ -62,13 +64,35 @@ impl Torrent {
 
         // Evaluate Info
         let info = Torrent::evaluate_info(info)?;
+        let url_list = Torrent::evaluate_url_list(root);
 
         Ok(Torrent {
             info,
+            url_list,
             info_hash
         })        
     }
 
+    // The url-list is either a single URL or a list of URLs. Web seeds are optional, so invalid entries are ignored.
+    fn evaluate_url_list(root: &BencodeDictionary) -> Vec<String> {
+        let mut tokens = Vec::new();
+
+        if let Ok(url) = root.find_string_value(b"url-list") {
+            tokens.push(url);
+        } else if let Ok(urls) = root.find_list_value(b"url-list") {
+            tokens.extend(urls.value.iter().filter_map(|token| match token {
+                BencodeToken::String(url) => Some(url),
+                _ => None
+            }));
+        }
+
+        tokens.into_iter()
+            .filter_map(|url| std::str::from_utf8(&url.value).ok())
+            .filter(|url| !url.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
     fn evaluate_info(info: &BencodeDictionary) -> Result<TorrentInfo, TorrentError> {
         // https://github.com/BiglySoftware/BiglyBT/issues/1274
         let name_utf8 = info.find_string_value(b"name.utf-8")
@@ -321,6 +345,7 @@ mod tests {
                     vec![61, 3, 229, 89, 49, 68, 20, 82, 246, 47, 157, 161, 155, 97, 235, 212, 64, 88, 227, 255]
                 ],
             },
+            url_list: Vec::new(),
             info_hash: vec![79, 24, 196, 139, 13, 130, 147, 71, 144, 199, 252, 22, 35, 74, 190, 56, 163, 8, 18, 123],
         };
 
@@ -375,6 +400,7 @@ mod tests {
                     vec![222, 220, 208, 9, 117, 139, 87, 43, 47, 57, 191, 94, 78, 142, 68, 176, 66, 206, 40, 67],
                 ],
             },
+            url_list: Vec::new(),
             info_hash: vec![158, 107, 242, 157, 198, 208, 115, 71, 243, 8, 84, 55, 8, 17, 60, 86, 152, 141, 19, 186],
         };
 
@@ -932,4 +958,16 @@ mod tests {
         assert_eq!("1.png", actual.info.files.as_ref().unwrap()[0].path[0]);
         assert_eq!("2.jpeg", actual.info.files.as_ref().unwrap()[1].path[0]);
     }
+
+    #[test]
+    fn from_bytes_url_list_should_succeed() {
+        let info = b"4:infod6:lengthi1e4:name1:a12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae".as_slice();
+
+        let single = [b"d", info, b"8:url-list18:http://example.come"].concat();
+        let list = [b"d", info, b"8:url-listl18:http://example.comi1e1:\xff0:19:http://example.org/ee"].concat();
+
+        assert_eq!(vec!["http://example.com"], Torrent::from_bytes(&single).unwrap().url_list);
+        assert_eq!(vec!["http://example.com", "http://example.org/"], Torrent::from_bytes(&list).unwrap().url_list);
+        assert!(Torrent::from_bytes(&[b"d", info, b"e"].concat()).unwrap().url_list.is_empty());
+    }
 }
\ No newline at end of file
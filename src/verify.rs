@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+
+use crate::{bitfield::Bitfield, filesystem::FrozenPathInterner, metadata::{TorrentFileEntry, TorrentPieceEntry}, recheck::build_bitfields, torrent::Torrent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataLayout {
+    // <data path>/<torrent name>, as saved by a torrent client.
+    Client,
+    // <data path>/<info hash>/Data/<torrent name>, as exported by a bootstrap run.
+    InfoHash
+}
+
+#[derive(Debug)]
+pub struct FileVerification {
+    pub path: PathBuf,
+    pub file_length: u64,
+    pub exists: bool,
+    // Pieces spanning the file, a piece spanning multiple files counts towards each of them.
+    pub verified_pieces: usize,
+    pub total_pieces: usize
+}
+
+impl FileVerification {
+    pub fn complete(&self) -> bool {
+        self.verified_pieces == self.total_pieces
+    }
+}
+
+#[derive(Debug)]
+pub struct TorrentVerification {
+    pub info_hash: Vec<u8>,
+    pub name: String,
+    pub bitfield: Bitfield,
+    // Padding files are left out, as they are never stored on disk.
+    pub files: Vec<FileVerification>
+}
+
+impl TorrentVerification {
+    pub fn complete(&self) -> bool {
+        self.bitfield.is_complete()
+    }
+}
+
+pub fn build_verification(
+    torrents: &[Torrent],
+    torrent_files: &[TorrentFileEntry],
+    torrent_pieces: &[TorrentPieceEntry],
+    path_interner: &FrozenPathInterner,
+    verified: &[bool]
+) -> Vec<TorrentVerification> {
+    let bitfields = build_bitfields(torrents, torrent_pieces, verified);
+
+    let mut files: Vec<FileVerification> = torrent_files.iter()
+        .map(|file| FileVerification {
+            path: path_interner.get(file.export_target).to_path_buf(),
+            file_length: file.file_length,
+            exists: !file.padding && path_interner.get(file.export_target).is_file(),
+            verified_pieces: 0,
+            total_pieces: 0
+        })
+        .collect();
+
+    for piece in torrent_pieces.iter() {
+        for piece_file in piece.files.iter() {
+            let file = &mut files[piece_file.file_id];
+            file.total_pieces += 1;
+            file.verified_pieces += verified[piece.piece_id] as usize;
+        }
+    }
+
+    let mut verifications: Vec<TorrentVerification> = torrents.iter()
+        .zip(bitfields)
+        .map(|(torrent, bitfield)| TorrentVerification {
+            info_hash: torrent.info_hash.clone(),
+            name: torrent.info.name.clone(),
+            bitfield,
+            files: Vec::new()
+        })
+        .collect();
+
+    for (file, verification) in torrent_files.iter().zip(files) {
+        if !file.padding {
+            verifications[file.torrent_id].files.push(verification);
+        }
+    }
+
+    verifications
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{orchestrator::{verify, OrchestratorOptions}, test_support::{build_torrent, TempDir}};
+
+    use super::*;
+
+    // Pieces of 4 bytes: abcd, ef gh, ij
+    const FILES: [(&str, &[u8]); 2] = [("a.bin", b"abcdef"), ("b.bin", b"ghij")];
+
+    fn verify_client_layout(name: &str, files: &[(&str, &[u8])]) -> TorrentVerification {
+        let directory = TempDir::new(&format!("verify_{}", name));
+        let torrent = build_torrent(&FILES, 4);
+
+        let torrent_directory = directory.join(&torrent.info.name);
+        std::fs::create_dir_all(&torrent_directory).unwrap();
+        for (path, bytes) in files {
+            std::fs::write(torrent_directory.join(path), bytes).unwrap();
+        }
+
+        let options = OrchestratorOptions::new(vec![torrent], Vec::new(), directory.path().to_path_buf());
+        let mut verifications = verify(options, DataLayout::Client).unwrap();
+        assert_eq!(1, verifications.len());
+        verifications.remove(0)
+    }
+
+    fn file_states(verification: &TorrentVerification) -> Vec<(bool, usize, usize)> {
+        verification.files.iter()
+            .map(|file| (file.exists, file.verified_pieces, file.total_pieces))
+            .collect()
+    }
+
+    #[test]
+    fn verify_should_accept_complete_client_layout() {
+        let verification = verify_client_layout("complete", &FILES);
+
+        assert!(verification.complete());
+        assert_eq!("e0", verification.bitfield.to_hex());
+        assert_eq!(vec![(true, 2, 2), (true, 2, 2)], file_states(&verification));
+        assert!(verification.files.iter().all(|file| file.complete()));
+        assert!(verification.files[0].path.ends_with(Path::new("a.bin").join("a.bin")));
+    }
+
+    #[test]
+    fn verify_should_report_incomplete_client_layout() {
+        let verification = verify_client_layout("incomplete", &[FILES[0], ("b.bin", b"ghXX")]);
+
+        assert!(!verification.complete());
+        assert_eq!("c0", verification.bitfield.to_hex());
+        assert_eq!(vec![(true, 2, 2), (true, 1, 2)], file_states(&verification));
+        assert!(!verification.files[1].complete());
+    }
+
+    #[test]
+    fn verify_should_report_missing_file() {
+        let verification = verify_client_layout("missing", &FILES[..1]);
+
+        assert!(!verification.complete());
+        assert_eq!("80", verification.bitfield.to_hex());
+        assert_eq!(vec![(true, 1, 2), (false, 0, 2)], file_states(&verification));
+    }
+}
\ No newline at end of file
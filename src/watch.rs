@@ -0,0 +1,430 @@
+use std::{collections::VecDeque, ffi::{CString, OsStr, OsString}, fs, io::{Error, ErrorKind, Result}, os::unix::ffi::OsStrExt, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use crate::{filesystem::ScanIndex, metadata::TorrentProcessState, orchestrator::{self, OrchestratorOptions}, solver::cancellation::CancellationToken, torrent::{info::get_sha1_hexdigest, Torrent}};
+
+// How long the inbox is waited on before cancellation is checked again.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Size of the fixed part of an inotify event, which is followed by the padded name of the file.
+const EVENT_HEADER_LENGTH: usize = 16;
+
+// Directory watched with inotify for torrent files. Files are only reported once they are closed after writing, or
+// renamed into the directory, so a torrent that is still being written is never read.
+pub struct Inbox {
+    directory: PathBuf,
+    fd: libc::c_int
+}
+
+impl Inbox {
+    pub fn open(directory: &Path) -> Result<Inbox> {
+        let path = CString::new(directory.as_os_str().as_bytes())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("Inbox path {:#?} contains a nul byte.", directory)))?;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        // The inbox closes the descriptor when the watch cannot be added.
+        let inbox = Inbox { directory: directory.to_path_buf(), fd };
+        let watch = unsafe { libc::inotify_add_watch(fd, path.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) };
+        if watch < 0 {
+            let err = Error::last_os_error();
+            return Err(Error::new(err.kind(), format!("Unable to watch inbox {:#?}: {}", directory, err)));
+        }
+
+        Ok(inbox)
+    }
+
+    // Torrent files that are already in the inbox, sorted by name.
+    pub fn pending(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() && is_torrent_file(&entry.path()) {
+                paths.push(entry.path());
+            }
+        }
+
+        paths.sort();
+        Ok(paths)
+    }
+
+    // Waits until torrent files arrive, or the timeout passes, and returns the files that arrived in the order of their
+    // events. When the kernel dropped events, every torrent file in the inbox is returned instead.
+    pub fn wait(&self, timeout: Duration) -> Result<Vec<PathBuf>> {
+        let mut poll_fd = libc::pollfd { fd: self.fd, events: libc::POLLIN, revents: 0 };
+        let timeout = std::cmp::min(timeout.as_millis(), libc::c_int::MAX as u128) as libc::c_int;
+
+        if unsafe { libc::poll(&mut poll_fd, 1, timeout) } < 0 {
+            let err = Error::last_os_error();
+            return if err.kind() == ErrorKind::Interrupted { Ok(Vec::new()) } else { Err(err) };
+        }
+
+        let mut paths = Vec::new();
+        let mut overflow = false;
+        let mut buffer = vec![0_u8; 64 * 1024];
+
+        loop {
+            let read = unsafe { libc::read(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            if read < 0 {
+                let err = Error::last_os_error();
+                match err.kind() {
+                    ErrorKind::WouldBlock => break,
+                    ErrorKind::Interrupted => continue,
+                    _ => return Err(err)
+                }
+            }
+
+            if read == 0 {
+                break;
+            }
+
+            // Events are parsed from the bytes, as the buffer is not aligned for the event struct.
+            let mut offset = 0;
+            while offset + EVENT_HEADER_LENGTH <= read as usize {
+                let mask = u32::from_ne_bytes(buffer[offset + 4..offset + 8].try_into().unwrap());
+                let name_length = u32::from_ne_bytes(buffer[offset + 12..offset + 16].try_into().unwrap()) as usize;
+                let name = &buffer[offset + EVENT_HEADER_LENGTH..offset + EVENT_HEADER_LENGTH + name_length];
+                offset += EVENT_HEADER_LENGTH + name_length;
+
+                if mask & libc::IN_Q_OVERFLOW != 0 {
+                    overflow = true;
+                }
+
+                let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+                if name.is_empty() {
+                    continue;
+                }
+
+                let path = self.directory.join(OsStr::from_bytes(name));
+                if is_torrent_file(&path) && !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+
+        if overflow {
+            return self.pending();
+        }
+
+        Ok(paths)
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn is_torrent_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("torrent"))
+}
+
+pub struct WatchOptions {
+    pub inbox: PathBuf,
+    // Torrents that were bootstrapped completely are moved here, every other torrent is moved to the failed directory.
+    pub processed: PathBuf,
+    pub failed: PathBuf,
+    pub cancellation: CancellationToken
+}
+
+// Outcome of bootstrapping a single torrent from the inbox.
+#[derive(Debug)]
+pub struct WatchReport {
+    // Path the torrent was moved to.
+    pub torrent: PathBuf,
+    // Missing when the torrent could not be loaded.
+    pub info_hash: Option<Vec<u8>>,
+    pub outcome: std::result::Result<TorrentProcessState, String>,
+    pub seconds: u64
+}
+
+impl WatchReport {
+    pub fn is_complete(&self) -> bool {
+        matches!(&self.outcome, Ok(state) if state.success_pieces == state.total_pieces)
+    }
+
+    pub fn summary(&self) -> String {
+        let info_hash = self.info_hash.as_ref().map_or_else(|| "unknown".to_string(), |info_hash| get_sha1_hexdigest(info_hash));
+
+        match &self.outcome {
+            Ok(state) => format!(
+                "Torrent {} at {:#?} is {} after {} seconds - Success: {}, Failed: {}, Faulted: {}, Exhausted: {}, Written: {}, Total: {}",
+                info_hash, self.torrent, if self.is_complete() { "complete" } else { "incomplete" }, self.seconds,
+                state.success_pieces, state.failed_pieces, state.fault_pieces, state.exhausted_pieces, state.writable_pieces, state.total_pieces
+            ),
+            Err(message) => format!("Torrent {} at {:#?} failed after {} seconds: {}", info_hash, self.torrent, self.seconds, message)
+        }
+    }
+}
+
+// Bootstraps every torrent file that arrives in the inbox, one at a time, until cancelled. Torrents already in the
+// inbox are bootstrapped first. The options of every run are built from its torrent, and share a scan index kept in
+// memory, so every run after the first only reads the scan directories that changed since the previous run. Options
+// that cannot be built fail the torrent.
+pub fn watch(options: &WatchOptions, build_options: impl Fn(Vec<Torrent>) -> Result<OrchestratorOptions>) -> Result<()> {
+    fs::create_dir_all(&options.processed)?;
+    fs::create_dir_all(&options.failed)?;
+
+    // The inbox is watched before it is listed, so no torrent arriving in between is missed.
+    let inbox = Inbox::open(&options.inbox)?;
+    let mut queue: VecDeque<PathBuf> = inbox.pending()?.into();
+    let scan_index = Arc::new(Mutex::new(ScanIndex::empty()));
+
+    println!("Watching {:#?} for torrent files, {} are already waiting.", options.inbox, queue.len());
+
+    while !options.cancellation.is_cancelled() {
+        let Some(path) = queue.pop_front() else {
+            queue.extend(inbox.wait(POLL_INTERVAL)?);
+            continue;
+        };
+
+        // A torrent can be both listed and reported by an event, and is gone once it was bootstrapped.
+        if !path.is_file() {
+            continue;
+        }
+
+        if bootstrap_torrent(&path, options, &build_options, &scan_index).is_none() {
+            println!("Stopped bootstrapping {:#?}, it is left in the inbox for the next run.", path);
+        }
+    }
+
+    Ok(())
+}
+
+// Bootstraps a single torrent, moves it to the processed or failed directory and writes its report next to it.
+// Returns None when the run was cancelled.
+fn bootstrap_torrent(
+    path: &Path,
+    options: &WatchOptions,
+    build_options: &impl Fn(Vec<Torrent>) -> Result<OrchestratorOptions>,
+    scan_index: &Arc<Mutex<ScanIndex>>
+) -> Option<WatchReport> {
+    let now = Instant::now();
+    println!("Bootstrapping {:#?}.", path);
+
+    let torrent = fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| Torrent::from_bytes(&bytes).map_err(|err| format!("{:?}: {}", err.kind, err.message)));
+
+    let (info_hash, outcome) = match torrent {
+        Ok(torrent) => {
+            let info_hash = torrent.info_hash.clone();
+
+            let result = build_options(vec![torrent]).and_then(|mut run_options| {
+                run_options.scan_index = Some(scan_index.clone());
+                run_options.cancellation = options.cancellation.clone();
+                orchestrator::start(run_options)
+            });
+
+            if options.cancellation.is_cancelled() {
+                return None;
+            }
+
+            (Some(info_hash), result.map_err(|err| err.to_string()))
+        },
+        Err(message) => (None, Err(message))
+    };
+
+    let mut report = WatchReport { torrent: path.to_path_buf(), info_hash, outcome, seconds: now.elapsed().as_secs() };
+    let destination = if report.is_complete() { &options.processed } else { &options.failed };
+
+    // A torrent that cannot be moved is left in the inbox, and is bootstrapped again on the next run.
+    let moved = match move_into(path, destination) {
+        Ok(torrent) => {
+            report.torrent = torrent;
+            true
+        },
+        Err(err) => {
+            eprintln!("Unable to move {:#?} to {:#?}: {}", path, destination, err);
+            false
+        }
+    };
+
+    let summary = report.summary();
+    println!("{}", summary);
+
+    if moved {
+        let mut report_path = report.torrent.clone().into_os_string();
+        report_path.push(".report");
+        if let Err(err) = fs::write(&report_path, summary + "\n") {
+            eprintln!("Unable to write report {:#?}: {}", report_path, err);
+        }
+    }
+
+    Some(report)
+}
+
+// Moves the file into the directory, numbering its name when a file with the same name is already there.
+fn move_into(path: &Path, directory: &Path) -> Result<PathBuf> {
+    let file_name = path.file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Path {:#?} has no file name.", path)))?;
+
+    let mut target = directory.join(file_name);
+    let mut copy = 1;
+    while fs::symlink_metadata(&target).is_ok() {
+        let mut name = OsString::from(path.file_stem().unwrap_or(file_name));
+        name.push(format!(" ({})", copy));
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+
+        target = directory.join(name);
+        copy += 1;
+    }
+
+    match fs::rename(path, &target) {
+        Ok(()) => Ok(target),
+        // The inbox can be on another filesystem than the destination.
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+            fs::copy(path, &target)?;
+            fs::remove_file(path)?;
+            Ok(target)
+        },
+        Err(err) => Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+
+    use crate::{bencode::Encoder, test_support::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn inbox_should_report_written_and_moved_torrents() {
+        let directory = TempDir::new("watch_inbox");
+        fs::write(directory.join("existing.torrent"), b"d").unwrap();
+        fs::write(directory.join("b.tmp"), b"d").unwrap();
+
+        let inbox = Inbox::open(directory.path()).unwrap();
+        assert_eq!(vec![directory.join("existing.torrent")], inbox.pending().unwrap());
+
+        fs::write(directory.join("a.torrent"), b"d").unwrap();
+        fs::write(directory.join("notes.txt"), b"d").unwrap();
+        fs::rename(directory.join("b.tmp"), directory.join("b.TORRENT")).unwrap();
+
+        let arrived = inbox.wait(Duration::from_secs(5)).unwrap();
+        assert_eq!(Vec::<PathBuf>::new(), inbox.wait(Duration::ZERO).unwrap());
+
+        assert_eq!(vec![directory.join("a.torrent"), directory.join("b.TORRENT")], arrived);
+    }
+
+    #[test]
+    fn move_into_should_number_duplicate_names() {
+        let directory = TempDir::new("watch_move");
+        let destination = directory.join("processed");
+        fs::create_dir_all(&destination).unwrap();
+
+        fs::write(directory.join("album.torrent"), b"first").unwrap();
+        let first = move_into(&directory.join("album.torrent"), &destination).unwrap();
+
+        fs::write(directory.join("album.torrent"), b"second").unwrap();
+        let second = move_into(&directory.join("album.torrent"), &destination).unwrap();
+
+        let contents = fs::read(&second).unwrap();
+
+        assert_eq!(destination.join("album.torrent"), first);
+        assert_eq!(destination.join("album (1).torrent"), second);
+        assert_eq!(b"second".as_slice(), contents.as_slice());
+    }
+
+    // Writes a single-file torrent of the data, with pieces of 4 bytes.
+    fn write_torrent(path: &Path, name: &str, data: &[u8]) {
+        let pieces: Vec<u8> = data.chunks(4).flat_map(|piece| Sha1::digest(piece).to_vec()).collect();
+
+        let mut encoder = Encoder::new();
+        encoder.begin_dictionary()
+            .string(b"info").begin_dictionary()
+                .string(b"length").integer(data.len() as i128)
+                .string(b"name").string(name.as_bytes())
+                .string(b"piece length").integer(4)
+                .string(b"pieces").string(&pieces)
+            .end()
+            .end();
+
+        fs::write(path, encoder.into_bytes()).unwrap();
+    }
+
+    fn watch_options(directory: &TempDir) -> WatchOptions {
+        let options = WatchOptions {
+            inbox: directory.join("inbox"),
+            processed: directory.join("processed"),
+            failed: directory.join("failed"),
+            cancellation: CancellationToken::new()
+        };
+
+        for path in [&options.inbox, &options.processed, &options.failed, &directory.join("scan"), &directory.join("export")] {
+            fs::create_dir_all(path).unwrap();
+        }
+
+        options
+    }
+
+    fn read_report(torrent: &Path) -> String {
+        let mut report_path = torrent.to_path_buf().into_os_string();
+        report_path.push(".report");
+        fs::read_to_string(report_path).unwrap()
+    }
+
+    #[test]
+    fn bootstrap_torrent_should_move_complete_torrents_to_processed() {
+        let directory = TempDir::new("watch_complete");
+        let options = watch_options(&directory);
+        fs::write(directory.join("scan").join("data.bin"), b"abcdefgh").unwrap();
+
+        let path = options.inbox.join("album.torrent");
+        write_torrent(&path, "data.bin", b"abcdefgh");
+
+        let build_options = |torrents| Ok(OrchestratorOptions::new(torrents, vec![directory.join("scan")], directory.join("export")));
+        let scan_index = Arc::new(Mutex::new(ScanIndex::empty()));
+        let report = bootstrap_torrent(&path, &options, &build_options, &scan_index).unwrap();
+
+        assert!(report.is_complete());
+        assert_eq!(options.processed.join("album.torrent"), report.torrent);
+        assert!(report.torrent.is_file());
+        assert!(!path.exists());
+        assert!(read_report(&report.torrent).contains("is complete"));
+        assert_eq!(1, scan_index.lock().unwrap().len());
+    }
+
+    #[test]
+    fn bootstrap_torrent_should_move_incomplete_and_unloadable_torrents_to_failed() {
+        let directory = TempDir::new("watch_failed");
+        let options = watch_options(&directory);
+
+        let incomplete = options.inbox.join("incomplete.torrent");
+        write_torrent(&incomplete, "missing.bin", b"abcdefgh");
+        let unloadable = options.inbox.join("unloadable.torrent");
+        fs::write(&unloadable, b"not a torrent").unwrap();
+        let unbuildable = options.inbox.join("unbuildable.torrent");
+        write_torrent(&unbuildable, "missing.bin", b"ijklmnop");
+
+        let build_options = |torrents| Ok(OrchestratorOptions::new(torrents, vec![directory.join("scan")], directory.join("export")));
+        let failing_build_options = |_| Err(Error::new(ErrorKind::InvalidInput, "Options cannot be built."));
+        let scan_index = Arc::new(Mutex::new(ScanIndex::empty()));
+
+        let incomplete_report = bootstrap_torrent(&incomplete, &options, &build_options, &scan_index).unwrap();
+        let unloadable_report = bootstrap_torrent(&unloadable, &options, &build_options, &scan_index).unwrap();
+        let unbuildable_report = bootstrap_torrent(&unbuildable, &options, &failing_build_options, &scan_index).unwrap();
+
+        assert!(matches!(&incomplete_report.outcome, Ok(state) if state.success_pieces == 0 && state.total_pieces == 2));
+        assert!(unloadable_report.info_hash.is_none());
+        assert_eq!(Err("Options cannot be built.".to_string()), unbuildable_report.outcome.map(|_| ()));
+
+        for (report, name) in [(&incomplete_report, "incomplete.torrent"), (&unloadable_report, "unloadable.torrent")] {
+            assert!(!report.is_complete());
+            assert_eq!(options.failed.join(name), report.torrent);
+            assert!(report.torrent.is_file());
+        }
+
+        assert!(read_report(&incomplete_report.torrent).contains("is incomplete"));
+        assert!(read_report(&unloadable_report.torrent).contains("Torrent unknown"));
+        assert!(read_report(&unbuildable_report.torrent).contains("Options cannot be built."));
+        assert_eq!(Vec::<PathBuf>::new(), Inbox::open(&options.inbox).unwrap().pending().unwrap());
+    }
+}
\ No newline at end of file